@@ -0,0 +1,25 @@
+CREATE TABLE note_revisions (
+    -- The ID of the revision
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the note that the revision belongs to
+    note_id INTEGER NOT NULL,
+
+    -- The sequence number of the revision, starting at 1 for each note
+    revision INTEGER NOT NULL,
+
+    -- The ID of the user that authored the revision
+    user_id INTEGER NOT NULL,
+
+    -- The title of the note at this revision
+    title TEXT NOT NULL,
+
+    -- The text contained in the note at this revision
+    body TEXT NOT NULL,
+
+    UNIQUE (note_id, revision)
+);
+
+-- Every existing note starts out with its current contents as its first revision
+INSERT INTO note_revisions (note_id, revision, user_id, title, body)
+    SELECT id, 1, user_id, title, body FROM notes;
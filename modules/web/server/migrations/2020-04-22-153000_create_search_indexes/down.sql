@@ -0,0 +1,3 @@
+DROP INDEX notes_search_idx;
+
+DROP INDEX boards_search_idx;
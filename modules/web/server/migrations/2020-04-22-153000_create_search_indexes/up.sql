@@ -0,0 +1,5 @@
+-- Full-text search indexes. Queries must use exactly the same expressions for postgres to pick
+-- these up (see api/search.rs).
+CREATE INDEX notes_search_idx ON notes USING GIN (to_tsvector('english', title || ' ' || body));
+
+CREATE INDEX boards_search_idx ON boards USING GIN (to_tsvector('english', title));
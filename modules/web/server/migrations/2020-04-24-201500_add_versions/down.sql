@@ -0,0 +1,3 @@
+ALTER TABLE boards DROP COLUMN version;
+
+ALTER TABLE notes DROP COLUMN version;
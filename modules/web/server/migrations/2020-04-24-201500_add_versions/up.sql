@@ -0,0 +1,5 @@
+-- The number of times each board has been written to, used for optimistic concurrency control
+ALTER TABLE boards ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+-- The number of times each note has been written to, used for optimistic concurrency control
+ALTER TABLE notes ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
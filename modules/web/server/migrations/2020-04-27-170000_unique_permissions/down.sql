@@ -0,0 +1 @@
+ALTER TABLE permissions DROP CONSTRAINT permissions_user_id_board_id_key;
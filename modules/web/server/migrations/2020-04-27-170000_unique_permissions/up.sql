@@ -0,0 +1,18 @@
+-- Fold any duplicate grants into the oldest grant for the same user and board, keeping the most
+-- permissive combination of flags
+UPDATE permissions
+    SET read = dupes.read, write = dupes.write
+    FROM (
+        SELECT MIN(id) AS id, bool_or(read) AS read, bool_or(write) AS write
+        FROM permissions
+        GROUP BY user_id, board_id
+        HAVING COUNT(*) > 1
+    ) dupes
+    WHERE permissions.id = dupes.id;
+
+DELETE FROM permissions a
+    USING permissions b
+    WHERE a.user_id = b.user_id AND a.board_id = b.board_id AND a.id > b.id;
+
+-- A user can only be granted access to a board once
+ALTER TABLE permissions ADD CONSTRAINT permissions_user_id_board_id_key UNIQUE (user_id, board_id);
@@ -0,0 +1,32 @@
+CREATE TABLE board_invitations (
+    -- The ID of the invitation
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the board that the invitee is invited to
+    board_id INTEGER NOT NULL,
+
+    -- The ID of the user that sent the invitation
+    inviter_id INTEGER NOT NULL,
+
+    -- The email address that the invitation was sent to
+    email TEXT NOT NULL,
+
+    -- Will the invitee be able to read the posts on the board?
+    read BOOLEAN NOT NULL,
+
+    -- Will the invitee be able to write new posts to the board?
+    write BOOLEAN NOT NULL,
+
+    -- A hash of the invitation's signed acceptance token
+    token_hash TEXT NOT NULL UNIQUE,
+
+    -- When the invitation can no longer be accepted
+    expires_at TIMESTAMP NOT NULL,
+
+    -- The state of the invitation (0 => pending, 1 => accepted, 2 => declined, 3 => revoked)
+    status SMALLINT NOT NULL DEFAULT 0
+);
+
+-- An email address can only have one pending invitation to each board
+CREATE UNIQUE INDEX board_invitations_pending_idx ON board_invitations (board_id, lower(email))
+    WHERE status = 0;
@@ -0,0 +1,5 @@
+-- A hard-to-guess name for each board, which can be handed out to share unlisted boards
+ALTER TABLE boards ADD COLUMN share_slug TEXT;
+UPDATE boards SET share_slug = md5(random()::text || clock_timestamp()::text || id::text);
+ALTER TABLE boards ALTER COLUMN share_slug SET NOT NULL;
+ALTER TABLE boards ADD CONSTRAINT boards_share_slug_key UNIQUE (share_slug);
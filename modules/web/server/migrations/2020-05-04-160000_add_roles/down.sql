@@ -0,0 +1,13 @@
+ALTER TABLE board_invitations ADD COLUMN read BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE board_invitations ADD COLUMN write BOOLEAN NOT NULL DEFAULT false;
+UPDATE board_invitations SET write = role >= 2;
+ALTER TABLE board_invitations ALTER COLUMN read DROP DEFAULT;
+ALTER TABLE board_invitations ALTER COLUMN write DROP DEFAULT;
+ALTER TABLE board_invitations DROP COLUMN role;
+
+ALTER TABLE permissions ADD COLUMN read BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE permissions ADD COLUMN write BOOLEAN NOT NULL DEFAULT false;
+UPDATE permissions SET write = role >= 2;
+ALTER TABLE permissions ALTER COLUMN read DROP DEFAULT;
+ALTER TABLE permissions ALTER COLUMN write DROP DEFAULT;
+ALTER TABLE permissions DROP COLUMN role;
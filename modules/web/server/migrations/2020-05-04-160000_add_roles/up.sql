@@ -0,0 +1,27 @@
+-- The role that each member has on a board (0 => viewer, 1 => commenter, 2 => editor, 3 => admin,
+-- 4 => owner)
+ALTER TABLE permissions ADD COLUMN role SMALLINT NOT NULL DEFAULT 0;
+
+-- A grant that didn't allow reading never gave access to anything
+DELETE FROM permissions WHERE NOT read;
+
+-- Members that could write become editors, and everyone else stays a viewer
+UPDATE permissions SET role = 2 WHERE write;
+
+-- Every board's owner is a member of it with the owner role
+INSERT INTO permissions (user_id, board_id, read, write, role)
+    SELECT user_id, id, true, true, 4 FROM boards
+    ON CONFLICT (user_id, board_id) DO UPDATE SET role = 4;
+
+ALTER TABLE permissions ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE permissions DROP COLUMN read;
+ALTER TABLE permissions DROP COLUMN write;
+
+-- Invitations carry the role that the invitee will be given
+ALTER TABLE board_invitations ADD COLUMN role SMALLINT NOT NULL DEFAULT 0;
+DELETE FROM board_invitations WHERE NOT read;
+UPDATE board_invitations SET role = 2 WHERE write;
+
+ALTER TABLE board_invitations ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE board_invitations DROP COLUMN read;
+ALTER TABLE board_invitations DROP COLUMN write;
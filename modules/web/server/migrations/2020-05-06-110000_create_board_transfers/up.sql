@@ -0,0 +1,19 @@
+CREATE TABLE board_transfers (
+    -- The ID of the transfer
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the board being transferred
+    board_id INTEGER NOT NULL,
+
+    -- The ID of the owner that offered the board
+    from_user_id INTEGER NOT NULL,
+
+    -- The ID of the user that the board was offered to
+    to_user_id INTEGER NOT NULL,
+
+    -- The state of the transfer (0 => pending, 1 => accepted, 2 => declined, 3 => cancelled)
+    status SMALLINT NOT NULL DEFAULT 0
+);
+
+-- A board can only have one pending transfer at a time
+CREATE UNIQUE INDEX board_transfers_pending_idx ON board_transfers (board_id) WHERE status = 0;
@@ -0,0 +1,7 @@
+-- Only GitHub users can be represented without a provider
+DELETE FROM users WHERE oauth_provider != 'github';
+
+ALTER TABLE users DROP CONSTRAINT users_oauth_provider_oauth_id_key;
+ALTER TABLE users DROP COLUMN oauth_provider;
+ALTER TABLE users ALTER COLUMN oauth_id TYPE INTEGER USING oauth_id::INTEGER;
+ALTER TABLE users ADD CONSTRAINT users_oauth_id_key UNIQUE (oauth_id);
@@ -0,0 +1,10 @@
+-- Google identifies users by a string subject that doesn't fit in an integer
+ALTER TABLE users ALTER COLUMN oauth_id TYPE TEXT USING oauth_id::TEXT;
+
+-- The provider that issued the user's oauth ID. Every existing user signed in with GitHub.
+ALTER TABLE users ADD COLUMN oauth_provider TEXT NOT NULL DEFAULT 'github';
+ALTER TABLE users ALTER COLUMN oauth_provider DROP DEFAULT;
+
+-- Different providers may issue the same ID to different users
+ALTER TABLE users DROP CONSTRAINT users_oauth_id_key;
+ALTER TABLE users ADD CONSTRAINT users_oauth_provider_oauth_id_key UNIQUE (oauth_provider, oauth_id);
@@ -0,0 +1,16 @@
+ALTER TABLE users ADD COLUMN oauth_id TEXT;
+ALTER TABLE users ADD COLUMN oauth_provider TEXT;
+
+-- Users can only keep the first identity that they linked
+UPDATE users SET oauth_id = i.subject, oauth_provider = i.provider
+FROM (SELECT DISTINCT ON (user_id) user_id, provider, subject FROM user_identities ORDER BY user_id, id) i
+WHERE users.id = i.user_id;
+
+-- Users without an identity can't log in anyway
+DELETE FROM users WHERE oauth_id IS NULL;
+
+ALTER TABLE users ALTER COLUMN oauth_id SET NOT NULL;
+ALTER TABLE users ALTER COLUMN oauth_provider SET NOT NULL;
+ALTER TABLE users ADD CONSTRAINT users_oauth_provider_oauth_id_key UNIQUE (oauth_provider, oauth_id);
+
+DROP TABLE user_identities;
@@ -0,0 +1,27 @@
+CREATE TABLE user_identities (
+    -- The ID of the identity
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the user that the identity belongs to
+    user_id INTEGER NOT NULL,
+
+    -- The name of the provider that issued the identity
+    provider TEXT NOT NULL,
+
+    -- The provider's unique identifier for the user
+    subject TEXT NOT NULL,
+
+    -- Each identity can only belong to one user
+    UNIQUE (provider, subject),
+
+    -- A user can only link one account per provider
+    UNIQUE (user_id, provider)
+);
+
+-- Every existing user logged in with exactly one provider
+INSERT INTO user_identities (user_id, provider, subject)
+SELECT id, oauth_provider, oauth_id FROM users;
+
+ALTER TABLE users DROP CONSTRAINT users_oauth_provider_oauth_id_key;
+ALTER TABLE users DROP COLUMN oauth_id;
+ALTER TABLE users DROP COLUMN oauth_provider;
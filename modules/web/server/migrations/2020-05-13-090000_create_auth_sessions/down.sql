@@ -0,0 +1,10 @@
+ALTER TABLE users ADD COLUMN oauth_token TEXT NOT NULL DEFAULT '';
+
+-- Restore the token of each user's most recent session
+UPDATE users SET oauth_token = s.token_hash
+FROM (SELECT DISTINCT ON (user_id) user_id, token_hash FROM auth_sessions ORDER BY user_id, last_used_at DESC) s
+WHERE users.id = s.user_id;
+
+ALTER TABLE users ALTER COLUMN oauth_token DROP DEFAULT;
+
+DROP TABLE auth_sessions;
@@ -0,0 +1,30 @@
+CREATE TABLE auth_sessions (
+    -- The ID of the session
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the user that the session belongs to
+    user_id INTEGER NOT NULL,
+
+    -- A hash of the session's bearer token
+    token_hash TEXT NOT NULL UNIQUE,
+
+    -- The user agent of the device that the session was started on
+    user_agent TEXT NOT NULL DEFAULT '',
+
+    -- When the session was started
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    -- When the session was last used to make a request
+    last_used_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    -- When the session stops being accepted
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX auth_sessions_user_id_idx ON auth_sessions (user_id);
+
+-- Keep existing clients logged in: their bearer token is the one that the user last logged in with
+INSERT INTO auth_sessions (user_id, token_hash, expires_at)
+SELECT id, oauth_token, now() + interval '30 days' FROM users;
+
+ALTER TABLE users DROP COLUMN oauth_token;
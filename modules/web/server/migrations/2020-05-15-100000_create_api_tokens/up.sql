@@ -0,0 +1,30 @@
+CREATE TABLE api_tokens (
+    -- The ID of the token
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the user that minted the token
+    user_id INTEGER NOT NULL,
+
+    -- The name that the user gave the token (e.g. "CI bot")
+    name TEXT NOT NULL,
+
+    -- A hash of the token's secret
+    token_hash TEXT NOT NULL UNIQUE,
+
+    -- What the token can be used for (e.g. "notes:read")
+    scopes TEXT[] NOT NULL,
+
+    -- The boards that the token is restricted to, if any
+    board_ids INTEGER[],
+
+    -- When the token was minted
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    -- When the token was last used to make a request
+    last_used_at TIMESTAMP,
+
+    -- When the token stops being accepted, if ever
+    expires_at TIMESTAMP
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
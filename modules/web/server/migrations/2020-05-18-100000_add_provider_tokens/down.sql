@@ -0,0 +1,7 @@
+DROP INDEX auth_sessions_identity_id_idx;
+ALTER TABLE auth_sessions DROP COLUMN identity_id;
+
+ALTER TABLE user_identities DROP COLUMN checked_at;
+ALTER TABLE user_identities DROP COLUMN token_expires_at;
+ALTER TABLE user_identities DROP COLUMN refresh_token;
+ALTER TABLE user_identities DROP COLUMN access_token;
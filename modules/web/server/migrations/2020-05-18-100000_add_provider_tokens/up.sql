@@ -0,0 +1,16 @@
+-- The provider tokens of each identity, encrypted with the server's secret key, so that the
+-- server can check that the user hasn't revoked its access at the provider
+ALTER TABLE user_identities ADD COLUMN access_token TEXT;
+ALTER TABLE user_identities ADD COLUMN refresh_token TEXT;
+
+-- When the identity's access token expires, if the provider said
+ALTER TABLE user_identities ADD COLUMN token_expires_at TIMESTAMP;
+
+-- When the identity's grant was last checked with the provider
+ALTER TABLE user_identities ADD COLUMN checked_at TIMESTAMP NOT NULL DEFAULT now();
+
+-- The identity that each session was started with, so that the session can be revoked along
+-- with the identity's grant
+ALTER TABLE auth_sessions ADD COLUMN identity_id INTEGER;
+
+CREATE INDEX auth_sessions_identity_id_idx ON auth_sessions (identity_id);
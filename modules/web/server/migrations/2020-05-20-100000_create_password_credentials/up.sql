@@ -0,0 +1,22 @@
+CREATE TABLE password_credentials (
+    -- The ID of the user that can log in with the password
+    user_id INTEGER PRIMARY KEY,
+
+    -- The email address that the user logs in with
+    email TEXT NOT NULL,
+
+    -- The argon2id hash of the password
+    password_hash TEXT NOT NULL,
+
+    -- Whether or not the user has proven that they own the email address
+    verified BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- When the credential was created
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    -- When the password was last changed
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Email addresses are case-insensitive, so nobody can sign up twice by changing the case
+CREATE UNIQUE INDEX password_credentials_email_idx ON password_credentials (lower(email));
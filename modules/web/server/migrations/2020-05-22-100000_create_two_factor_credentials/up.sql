@@ -0,0 +1,34 @@
+CREATE TABLE two_factor_credentials (
+    -- The ID of the user that the credential protects
+    user_id INTEGER PRIMARY KEY,
+
+    -- The user's TOTP secret, encrypted with the server's secret key
+    secret TEXT NOT NULL,
+
+    -- Whether or not the user has confirmed their authenticator app, and is challenged on login
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+
+    -- The time step of the last code that was accepted, so that codes can't be replayed
+    last_used_step BIGINT NOT NULL DEFAULT 0,
+
+    -- The number of incorrect codes entered since the last correct one
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+
+    -- When an incorrect code was last entered
+    last_failed_at TIMESTAMP,
+
+    -- When the user started enrolling
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE TABLE recovery_codes (
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the user that the code can stand in for a TOTP code for
+    user_id INTEGER NOT NULL,
+
+    -- The hash of the code, which is deleted once it has been used
+    code_hash TEXT NOT NULL
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
@@ -0,0 +1,27 @@
+DROP INDEX board_transfers_board_id_idx;
+DROP INDEX board_invitations_board_id_idx;
+DROP INDEX permissions_board_id_idx;
+DROP INDEX note_revisions_user_id_idx;
+DROP INDEX notes_user_id_idx;
+DROP INDEX notes_board_id_idx;
+DROP INDEX boards_user_id_idx;
+
+ALTER TABLE auth_sessions DROP CONSTRAINT auth_sessions_identity_id_fkey;
+ALTER TABLE recovery_codes DROP CONSTRAINT recovery_codes_user_id_fkey;
+ALTER TABLE two_factor_credentials DROP CONSTRAINT two_factor_credentials_user_id_fkey;
+ALTER TABLE password_credentials DROP CONSTRAINT password_credentials_user_id_fkey;
+ALTER TABLE api_tokens DROP CONSTRAINT api_tokens_user_id_fkey;
+ALTER TABLE auth_sessions DROP CONSTRAINT auth_sessions_user_id_fkey;
+ALTER TABLE user_identities DROP CONSTRAINT user_identities_user_id_fkey;
+ALTER TABLE board_transfers DROP CONSTRAINT board_transfers_to_user_id_fkey;
+ALTER TABLE board_transfers DROP CONSTRAINT board_transfers_from_user_id_fkey;
+ALTER TABLE board_invitations DROP CONSTRAINT board_invitations_inviter_id_fkey;
+ALTER TABLE permissions DROP CONSTRAINT permissions_user_id_fkey;
+ALTER TABLE note_revisions DROP CONSTRAINT note_revisions_user_id_fkey;
+ALTER TABLE notes DROP CONSTRAINT notes_user_id_fkey;
+ALTER TABLE board_transfers DROP CONSTRAINT board_transfers_board_id_fkey;
+ALTER TABLE board_invitations DROP CONSTRAINT board_invitations_board_id_fkey;
+ALTER TABLE permissions DROP CONSTRAINT permissions_board_id_fkey;
+ALTER TABLE note_revisions DROP CONSTRAINT note_revisions_note_id_fkey;
+ALTER TABLE notes DROP CONSTRAINT notes_board_id_fkey;
+ALTER TABLE boards DROP CONSTRAINT boards_user_id_fkey;
@@ -0,0 +1,90 @@
+-- Rows left behind by interrupted writes would violate the new constraints. Rather than deleting
+-- them here, stop until the operator has looked at them (`notedlyd db check` lists them, and
+-- `notedlyd db check --repair` removes them)
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM boards WHERE user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM notes
+            WHERE board_id NOT IN (SELECT id FROM boards) OR user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM note_revisions
+            WHERE note_id NOT IN (SELECT id FROM notes) OR user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM permissions
+            WHERE board_id NOT IN (SELECT id FROM boards) OR user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM board_invitations
+            WHERE board_id NOT IN (SELECT id FROM boards)
+                OR inviter_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM board_transfers
+            WHERE board_id NOT IN (SELECT id FROM boards)
+                OR from_user_id NOT IN (SELECT id FROM users)
+                OR to_user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM user_identities WHERE user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM auth_sessions
+            WHERE user_id NOT IN (SELECT id FROM users)
+                OR identity_id NOT IN (SELECT id FROM user_identities))
+        OR EXISTS (SELECT 1 FROM api_tokens WHERE user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM password_credentials WHERE user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM two_factor_credentials
+            WHERE user_id NOT IN (SELECT id FROM users))
+        OR EXISTS (SELECT 1 FROM recovery_codes WHERE user_id NOT IN (SELECT id FROM users))
+    THEN
+        RAISE EXCEPTION 'some rows refer to rows that don''t exist. Run `notedlyd db check` to '
+            'list them, and `notedlyd db check --repair` to remove them, then migrate again.';
+    END IF;
+END
+$$;
+
+-- Everything that belongs to a board goes with it
+ALTER TABLE boards ADD CONSTRAINT boards_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE notes ADD CONSTRAINT notes_board_id_fkey
+    FOREIGN KEY (board_id) REFERENCES boards (id) ON DELETE CASCADE;
+ALTER TABLE note_revisions ADD CONSTRAINT note_revisions_note_id_fkey
+    FOREIGN KEY (note_id) REFERENCES notes (id) ON DELETE CASCADE;
+ALTER TABLE permissions ADD CONSTRAINT permissions_board_id_fkey
+    FOREIGN KEY (board_id) REFERENCES boards (id) ON DELETE CASCADE;
+ALTER TABLE board_invitations ADD CONSTRAINT board_invitations_board_id_fkey
+    FOREIGN KEY (board_id) REFERENCES boards (id) ON DELETE CASCADE;
+ALTER TABLE board_transfers ADD CONSTRAINT board_transfers_board_id_fkey
+    FOREIGN KEY (board_id) REFERENCES boards (id) ON DELETE CASCADE;
+
+-- Notes and revisions written on other users' boards aren't removed along with their author, so
+-- they have to be dealt with before the author can be deleted
+ALTER TABLE notes ADD CONSTRAINT notes_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id);
+ALTER TABLE note_revisions ADD CONSTRAINT note_revisions_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id);
+
+-- Everything else that belongs to a user goes with them
+ALTER TABLE permissions ADD CONSTRAINT permissions_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE board_invitations ADD CONSTRAINT board_invitations_inviter_id_fkey
+    FOREIGN KEY (inviter_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE board_transfers ADD CONSTRAINT board_transfers_from_user_id_fkey
+    FOREIGN KEY (from_user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE board_transfers ADD CONSTRAINT board_transfers_to_user_id_fkey
+    FOREIGN KEY (to_user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE user_identities ADD CONSTRAINT user_identities_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE auth_sessions ADD CONSTRAINT auth_sessions_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE api_tokens ADD CONSTRAINT api_tokens_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE password_credentials ADD CONSTRAINT password_credentials_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE two_factor_credentials ADD CONSTRAINT two_factor_credentials_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE recovery_codes ADD CONSTRAINT recovery_codes_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+-- Sessions outlive the identity that they were started with, as when it is unlinked
+ALTER TABLE auth_sessions ADD CONSTRAINT auth_sessions_identity_id_fkey
+    FOREIGN KEY (identity_id) REFERENCES user_identities (id) ON DELETE SET NULL;
+
+-- Cascading deletes look rows up by these columns
+CREATE INDEX boards_user_id_idx ON boards (user_id);
+CREATE INDEX notes_board_id_idx ON notes (board_id);
+CREATE INDEX notes_user_id_idx ON notes (user_id);
+CREATE INDEX note_revisions_user_id_idx ON note_revisions (user_id);
+CREATE INDEX permissions_board_id_idx ON permissions (board_id);
+CREATE INDEX board_invitations_board_id_idx ON board_invitations (board_id);
+CREATE INDEX board_transfers_board_id_idx ON board_transfers (board_id);
@@ -0,0 +1,23 @@
+DROP INDEX notes_updated_at_idx;
+DROP INDEX boards_updated_at_idx;
+
+DROP TRIGGER set_updated_at ON permissions;
+DROP TRIGGER set_updated_at ON notes;
+DROP TRIGGER set_updated_at ON boards;
+DROP TRIGGER set_updated_at ON users;
+
+ALTER TABLE permissions DROP COLUMN updated_by;
+ALTER TABLE notes DROP COLUMN updated_by;
+ALTER TABLE boards DROP COLUMN updated_by;
+
+ALTER TABLE permissions DROP COLUMN updated_at;
+ALTER TABLE permissions DROP COLUMN created_at;
+
+ALTER TABLE notes DROP COLUMN updated_at;
+ALTER TABLE notes DROP COLUMN created_at;
+
+ALTER TABLE boards DROP COLUMN updated_at;
+ALTER TABLE boards DROP COLUMN created_at;
+
+ALTER TABLE users DROP COLUMN updated_at;
+ALTER TABLE users DROP COLUMN created_at;
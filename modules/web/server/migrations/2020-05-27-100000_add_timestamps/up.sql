@@ -0,0 +1,38 @@
+-- When each row was created and last changed. updated_at is kept up to date by the trigger that
+-- diesel_manage_updated_at sets up, so that no write can forget it.
+ALTER TABLE users ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT now();
+
+ALTER TABLE boards ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE boards ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT now();
+
+ALTER TABLE notes ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE notes ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT now();
+
+ALTER TABLE permissions ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE permissions ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT now();
+
+-- The user that last changed each board, note and permission (users can only be changed by
+-- themselves). Set by the server, since the database can't tell who is making a change.
+ALTER TABLE boards ADD COLUMN updated_by INTEGER REFERENCES users (id) ON DELETE SET NULL;
+ALTER TABLE notes ADD COLUMN updated_by INTEGER REFERENCES users (id) ON DELETE SET NULL;
+ALTER TABLE permissions ADD COLUMN updated_by INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+-- Boards were only ever changed by their owners, and notes by the author of their latest revision
+UPDATE boards SET updated_by = user_id;
+UPDATE notes SET updated_by = COALESCE(
+    (SELECT user_id FROM note_revisions
+        WHERE note_revisions.note_id = notes.id
+        ORDER BY revision DESC
+        LIMIT 1),
+    user_id
+);
+
+SELECT diesel_manage_updated_at('users');
+SELECT diesel_manage_updated_at('boards');
+SELECT diesel_manage_updated_at('notes');
+SELECT diesel_manage_updated_at('permissions');
+
+-- Lists are usually sorted by when their items last changed
+CREATE INDEX boards_updated_at_idx ON boards (updated_at);
+CREATE INDEX notes_updated_at_idx ON notes (updated_at);
@@ -0,0 +1,20 @@
+CREATE TABLE note_comments (
+    -- The ID of the comment
+    id SERIAL PRIMARY KEY,
+
+    -- The ID of the note that the comment was left on
+    note_id INTEGER NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+
+    -- The ID of the user that wrote the comment
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+
+    -- The text of the comment
+    body TEXT NOT NULL,
+
+    -- When the comment was written
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Comments are listed by note, and cascading deletes look them up by author
+CREATE INDEX note_comments_note_id_idx ON note_comments (note_id);
+CREATE INDEX note_comments_user_id_idx ON note_comments (user_id);
@@ -0,0 +1,235 @@
+use super::{
+    super::{
+        models::{
+            Board, User, ROLE_ADMIN, ROLE_COMMENTER, ROLE_EDITOR, ROLE_OWNER, ROLE_VIEWER,
+            VISIBILITY_PUBLIC, VISIBILITY_UNLISTED,
+        },
+        schema::{self, boards::dsl::*},
+    },
+    auth::user_for_token,
+    users::{Bearer, Error},
+};
+use actix_web::error;
+use diesel::{
+    pg::PgConnection, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl,
+    RunQueryDsl,
+};
+
+/// Something that a user may want to do with a board. Which roles are allowed to do what:
+///
+/// | Capability      | Viewer | Commenter | Editor | Admin | Owner |
+/// |-----------------|:------:|:---------:|:------:|:-----:|:-----:|
+/// | `Read`          |   ✓    |     ✓     |   ✓    |   ✓   |   ✓   |
+/// | `ViewMembers`   |   ✓    |     ✓     |   ✓    |   ✓   |   ✓   |
+/// | `Comment`       |        |     ✓     |   ✓    |   ✓   |   ✓   |
+/// | `Write`         |        |           |   ✓    |   ✓   |   ✓   |
+/// | `ManageMembers` |        |           |        |   ✓   |   ✓   |
+/// | `ManageBoard`   |        |           |        |   ✓   |   ✓   |
+/// | `TransferBoard` |        |           |        |       |   ✓   |
+/// | `DeleteBoard`   |        |           |        |       |   ✓   |
+///
+/// Unlisted boards can also be read by any authenticated user, and public boards by anyone.
+/// Admins can only manage members below them; only the owner can appoint or remove admins.
+/// Writing a note grants nothing on its own: authors need the same role as anyone else to read
+/// or change their notes, so they lose access to them when they're demoted or removed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Capability {
+    /// Read the board and its notes
+    Read,
+
+    /// See who has been invited to the board
+    ViewMembers,
+
+    /// Comment on the board's notes without changing them
+    Comment,
+
+    /// Create, edit and delete the board's notes
+    Write,
+
+    /// Invite users to the board, change their roles, and remove them
+    ManageMembers,
+
+    /// Change the board's title and visibility
+    ManageBoard,
+
+    /// Give the board to another user
+    TransferBoard,
+
+    /// Delete the board, along with everything on it
+    DeleteBoard,
+}
+
+impl Capability {
+    /// Describes the capability, for use in error messages.
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Read => "read from this board",
+            Self::ViewMembers => "view the members of this board",
+            Self::Comment => "comment on this board",
+            Self::Write => "write to this board",
+            Self::ManageMembers => "manage the members of this board",
+            Self::ManageBoard => "change the settings of this board",
+            Self::TransferBoard => "transfer this board",
+            Self::DeleteBoard => "delete this board",
+        }
+    }
+}
+
+/// Checks whether or not the given role grants the given capability (see `Capability` for the
+/// full matrix).
+///
+/// # Arguments
+///
+/// * `role` - The role that the user has on the board
+/// * `capability` - What the user wants to do
+pub fn role_allows(role: i16, capability: Capability) -> bool {
+    match capability {
+        Capability::Read | Capability::ViewMembers => role >= ROLE_VIEWER,
+        Capability::Comment => role >= ROLE_COMMENTER,
+        Capability::Write => role >= ROLE_EDITOR,
+        Capability::ManageMembers | Capability::ManageBoard => role >= ROLE_ADMIN,
+        Capability::TransferBoard | Capability::DeleteBoard => role == ROLE_OWNER,
+    }
+}
+
+/// Ensures that the provided bearer token was issued to the provided user.
+pub(crate) fn continue_if_authenticated(
+    conn: &PgConnection,
+    user: &User,
+    auth_token: Bearer,
+) -> Result<(), Error> {
+    // Return Ok if the token belongs to the user, otherwise unauth
+    if user_for_token(conn, auth_token)?.id != user.id {
+        Err(Error(error::ErrorUnauthorized(
+            "The provided access token does not match the recorded token for this user.",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Gets the role that the given user has on the given board, if they've been invited to it.
+pub(crate) fn role_on(
+    conn: &PgConnection,
+    board_uid: i32,
+    usr_id: i32,
+) -> Result<Option<i16>, Error> {
+    Ok(schema::permissions::table
+        .filter(
+            schema::permissions::board_id
+                .eq(board_uid)
+                .and(schema::permissions::user_id.eq(usr_id)),
+        )
+        .select(schema::permissions::role)
+        .first(conn)
+        .optional()?)
+}
+
+/// Ensures that the given user, who may be anonymous, is allowed to do something with a board.
+/// Every access check on a board goes through here. Returns the role that the user has on the
+/// board, if they've been invited to it.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the board and the user's role will be read with
+/// * `board_uid` - The ID of the board
+/// * `user` - The user making the request, if they provided a token
+/// * `capability` - What the user wants to do
+pub(crate) fn continue_if_allowed(
+    conn: &PgConnection,
+    board_uid: i32,
+    user: Option<&User>,
+    capability: Capability,
+) -> Result<Option<i16>, Error> {
+    // The board must exist for all to continue. Otherwise, throw a 404.
+    let matching_board: Board = match boards.find(board_uid).first(conn).optional()? {
+        Some(b) => b,
+        None => {
+            return Err(Error(error::ErrorNotFound(format!(
+                "No board with the id '{}' exists.",
+                board_uid
+            ))))
+        }
+    };
+
+    // Get the role that the user has been given on the board
+    let role = match user {
+        Some(u) => role_on(conn, board_uid, u.id)?,
+        None => None,
+    };
+
+    match role {
+        // The user is a member with a sufficient role
+        Some(r) if role_allows(r, capability) => Ok(role),
+
+        // The user is a member, but their role doesn't allow this
+        Some(_) => Err(Error(error::ErrorUnauthorized(format!(
+            "The provided access token does not match a user with the required privileges to {}.",
+            capability.describe()
+        )))),
+
+        // Anybody can read a public board, and having the ID or share slug of an unlisted board
+        // is enough to read it
+        None if capability == Capability::Read
+            && (matching_board.visibility == VISIBILITY_PUBLIC
+                || matching_board.visibility == VISIBILITY_UNLISTED && user.is_some()) =>
+        {
+            Ok(None)
+        }
+
+        // Anonymous users can't do anything else
+        None if user.is_none() => Err(Error(error::ErrorUnauthorized(
+            "No applicable bearer token was provided.",
+        ))),
+
+        // Everyone else has to have been invited to the board
+        None => Err(Error(error::ErrorNotFound(
+            "The provided access token does not match a user that has been invited to this board.",
+        ))),
+    }
+}
+
+/// Ensures that a member with the given role may give a user the requested role. Nobody can be
+/// given ownership of a board this way, the owner's role can't be changed, and only the owner can
+/// appoint or demote admins.
+///
+/// # Arguments
+///
+/// * `actor_role` - The role of the member making the change
+/// * `current_role` - The role that the affected user has now, if they're already a member
+/// * `new_role` - The role that the affected user will have after the change (`None` if they're
+/// being removed from the board)
+pub(crate) fn continue_if_can_assign(
+    actor_role: i16,
+    current_role: Option<i16>,
+    new_role: Option<i16>,
+) -> Result<(), Error> {
+    // The requested role must be one of the known roles, other than owner
+    if let Some(r) = new_role {
+        if r < ROLE_VIEWER || r >= ROLE_OWNER {
+            return Err(Error(error::ErrorBadRequest(format!(
+                "'{}' is not a role that can be given to a member (0 => viewer, 1 => commenter, 2 => editor, 3 => admin).",
+                r
+            ))));
+        }
+    }
+
+    // The owner always keeps full access to their own board
+    if current_role == Some(ROLE_OWNER) {
+        return Err(Error(error::ErrorBadRequest(
+            "The role of the board's owner can't be changed.",
+        )));
+    }
+
+    // Only the owner can hand out or take away admin rights
+    if actor_role != ROLE_OWNER
+        && (current_role.map_or(false, |r| r >= ROLE_ADMIN)
+            || new_role.map_or(false, |r| r >= ROLE_ADMIN))
+    {
+        return Err(Error(error::ErrorUnauthorized(
+            "Only the owner of a board can appoint or remove its admins.",
+        )));
+    }
+
+    Ok(())
+}
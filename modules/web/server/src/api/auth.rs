@@ -0,0 +1,355 @@
+use super::{
+    super::{
+        crypto::{random_token, SecretKey},
+        models::{
+            AuthSession, IssuedSession, NewAuthSession, OwnedAuthSession, User, UserIdentity,
+        },
+        schema::{
+            self, auth_sessions::dsl::auth_sessions, user_identities::dsl::user_identities,
+            users::dsl::users,
+        },
+    },
+    revalidation::revalidate,
+    server::OauthConfig,
+    tokens::{is_api_token, user_for_api_token},
+    two_factor::{
+        complete_challenge, confirm, disable, enroll, regenerate_recovery_codes, two_factor_status,
+    },
+    users::{extract_bearer, hash_token, Bearer, Error},
+};
+use actix_session::Session;
+use actix_web::{
+    client::Client,
+    error, http,
+    web::{Data, HttpRequest, HttpResponse, Json, Path},
+    Scope as ActixScope,
+};
+use chrono::{Duration, Utc};
+use diesel::{
+    dsl::update,
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+
+/// The number of days that a session lasts for after the user logs in.
+const SESSION_LIFETIME_DAYS: i64 = 30;
+
+/// How often, in seconds, the last use of a session is recorded. Recording every request would
+/// mean a write for every read.
+pub(crate) const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// Constructs an actix service group for the auth endpoint.
+pub fn build_service_group() -> ActixScope {
+    ActixScope::new("/auth")
+        .service(all_sessions)
+        .service(refresh_session)
+        .service(revoke_other_sessions)
+        .service(revoke_session)
+        .service(two_factor_status)
+        .service(enroll)
+        .service(confirm)
+        .service(regenerate_recovery_codes)
+        .service(disable)
+        .service(complete_challenge)
+}
+
+/// Gets the user agent of the device that sent the given request, so that users can tell their
+/// sessions apart.
+///
+/// # Arguments
+///
+/// * `req` - The user's request
+pub(crate) fn user_agent(req: &HttpRequest) -> &str {
+    req.headers()
+        .get(http::header::USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .unwrap_or("")
+}
+
+/// Starts a new session for the given user, returning the session's bearer token along with the
+/// session. The token is only ever stored as a hash, so it can't be recovered later.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the session will be stored with
+/// * `usr_id` - The ID of the user that logged in
+/// * `user_agent` - The user agent of the device that the user logged in on
+/// * `identity_uid` - The ID of the provider identity that the user logged in with, if any
+pub(crate) fn start_session(
+    conn: &PgConnection,
+    usr_id: i32,
+    user_agent: &str,
+    identity_uid: Option<i32>,
+) -> Result<(String, AuthSession), Error> {
+    let token = random_token();
+
+    let session = diesel::insert_into(auth_sessions)
+        .values(&NewAuthSession {
+            user_id: usr_id,
+            token_hash: &hash_token(&token),
+            user_agent,
+            expires_at: Utc::now().naive_utc() + Duration::days(SESSION_LIFETIME_DAYS),
+            identity_id: identity_uid,
+        })
+        .get_result(conn)?;
+
+    Ok((token, session))
+}
+
+/// Gets the unexpired session with the given bearer token, recording that it has been used.
+/// Personal access tokens aren't sessions, so they're rejected here.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the session will be read with
+/// * `token` - The session's bearer token
+pub(crate) fn session_for_token(conn: &PgConnection, token: &str) -> Result<AuthSession, Error> {
+    // Personal access tokens can't be used to manage the user's sessions or tokens
+    if is_api_token(token) {
+        return Err(Error(error::ErrorForbidden(
+            "Personal access tokens can't be used for this request. Log in instead.",
+        )));
+    }
+
+    let now = Utc::now().naive_utc();
+
+    let session: AuthSession = match auth_sessions
+        .filter(
+            schema::auth_sessions::token_hash
+                .eq(hash_token(token))
+                .and(schema::auth_sessions::expires_at.gt(now)),
+        )
+        .first(conn)
+        .optional()?
+    {
+        Some(s) => s,
+        None => {
+            return Err(Error(error::ErrorUnauthorized(
+                "The provided access token does not match any active session.",
+            )))
+        }
+    };
+
+    // Remember when the session was last used, so that users can spot stale devices
+    if session.last_used_at < now - Duration::seconds(LAST_USED_RESOLUTION_SECS) {
+        update(auth_sessions.find(session.id))
+            .set(schema::auth_sessions::last_used_at.eq(now))
+            .execute(conn)?;
+    }
+
+    Ok(session)
+}
+
+/// Gets the user that the given session token was issued to, as long as the session is still
+/// active.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the user will be read with
+/// * `token` - The session's bearer token
+pub(crate) fn user_for_session(conn: &PgConnection, token: &str) -> Result<User, Error> {
+    let session = session_for_token(conn, token)?;
+
+    Ok(users.find(session.user_id).first(conn)?)
+}
+
+/// Gets the user that the given bearer token was issued to, which may be either a session token
+/// or a personal access token. Every authenticated request goes through here, so this is also
+/// where the scopes and board restrictions of personal access tokens are enforced.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the user will be read with
+/// * `bearer` - The bearer token, along with the request that it was sent with
+pub(crate) fn user_for_token(conn: &PgConnection, bearer: Bearer) -> Result<User, Error> {
+    if is_api_token(bearer.token) {
+        user_for_api_token(conn, bearer)
+    } else {
+        user_for_session(conn, bearer.token)
+    }
+}
+
+/// Gets each of the user's active sessions, so that they can see where they're logged in.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("/sessions")]
+pub async fn all_sessions(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<Json<Vec<OwnedAuthSession>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    // Get each of the user's sessions that haven't expired yet
+    let sessions: Vec<AuthSession> = auth_sessions
+        .filter(
+            schema::auth_sessions::user_id
+                .eq(current.user_id)
+                .and(schema::auth_sessions::expires_at.gt(Utc::now().naive_utc())),
+        )
+        .order(schema::auth_sessions::last_used_at.desc())
+        .load(&conn)?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| OwnedAuthSession {
+                current: session.id == current.id,
+                session,
+            })
+            .collect(),
+    ))
+}
+
+/// Swaps the session making the request for a new one that lasts for another 30 days, after
+/// checking that the provider that the user logged in with hasn't revoked our access. The old
+/// session's token stops working.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `oauth_config` - The server's oauth configuration
+/// * `key` - The key that provider tokens are encrypted with
+/// * `session` - The user's cookie session, which is updated if it holds the old token
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/refresh")]
+pub async fn refresh_session(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    oauth_config: Data<OauthConfig>,
+    key: Data<SecretKey>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<Json<IssuedSession>, Error> {
+    // Get the session making the request, and the identity that it was started with. The
+    // connection is given back before the provider is asked, since that can take a while.
+    let (current, identity) = {
+        // Get a connection from the provided connection pool, so we can start using diesel
+        let conn = pool.get()?;
+
+        let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+        let identity: Option<UserIdentity> = match current.identity_id {
+            Some(identity_uid) => user_identities.find(identity_uid).first(&conn).optional()?,
+            None => None,
+        };
+
+        (current, identity)
+    };
+
+    // Make sure that the user hasn't revoked our access at the provider they logged in with
+    if let Some(identity) = identity {
+        if !revalidate(&pool, &oauth_config, &key, &Client::default(), &identity).await? {
+            return Err(Error(error::ErrorUnauthorized(
+                "Access to the account that this session was started with has been revoked.",
+            )));
+        }
+    }
+
+    // Get a fresh connection from the provided connection pool
+    let conn = pool.get()?;
+
+    // Replace the old session with the new one
+    let (token, refreshed) = conn.transaction::<_, Error, _>(|| {
+        diesel::delete(auth_sessions.find(current.id)).execute(&conn)?;
+
+        start_session(
+            &conn,
+            current.user_id,
+            &current.user_agent,
+            current.identity_id,
+        )
+    })?;
+
+    // Keep the session cookie in step with the new token, if the user has one
+    if session.get::<String>("token").map_err(Error)?.is_some() {
+        session
+            .set::<String>("token", token.clone())
+            .map_err(Error)?;
+    }
+
+    Ok(Json(IssuedSession {
+        token,
+        expires_at: refreshed.expires_at,
+    }))
+}
+
+/// Revokes one of the user's sessions, logging that device out. The session making the request
+/// can revoke itself to log out.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `session_id` - The ID of the session that will be revoked
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/sessions/{session_id}")]
+pub async fn revoke_session(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    session_id: Path<i32>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    // Users can only revoke their own sessions
+    if diesel::delete(
+        auth_sessions.filter(
+            schema::auth_sessions::id
+                .eq(*session_id)
+                .and(schema::auth_sessions::user_id.eq(current.user_id)),
+        ),
+    )
+    .execute(&conn)?
+        == 0
+    {
+        return Err(Error(error::ErrorNotFound(format!(
+            "The requested session (id: {}) does not exist.",
+            *session_id
+        ))));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Revokes each of the user's sessions, other than the one making the request. Useful after a
+/// device has been lost.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/sessions")]
+pub async fn revoke_other_sessions(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    // Delete everything but the current session
+    diesel::delete(
+        auth_sessions.filter(
+            schema::auth_sessions::user_id
+                .eq(current.user_id)
+                .and(schema::auth_sessions::id.ne(current.id)),
+        ),
+    )
+    .execute(&conn)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
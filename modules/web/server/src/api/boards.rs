@@ -1,119 +1,67 @@
 use super::{
     super::{
-        models::{Board, NewBoard, NewPermission, Note, Permission, UpdateBoard, User},
-        schema::{self, boards::dsl::*, permissions::dsl::*, users::dsl::*, notes::dsl::*},
+        crypto::random_token,
+        models::{
+            Board, GrantPermission, NewBoard, NewPermission, Note, Permission, UpdateBoard,
+            UpdatePermission, User, ROLE_OWNER, ROLE_VIEWER, VISIBILITY_PRIVATE, VISIBILITY_PUBLIC,
+        },
+        schema::{self, boards::dsl::*, permissions::dsl::*, users::dsl::*},
     },
-    users::{extract_bearer, hash_token, Error},
+    access::{continue_if_allowed, continue_if_authenticated, continue_if_can_assign, Capability},
+    auth::user_for_token,
+    etag::{continue_if_matches, precondition_failed, respond_with_tag, tagged_response},
+    invitations,
+    listing::{page_response, BoardFilter, Keyed, NoteFilter, Page, Sort, UserFilter},
+    transfers,
+    users::{extract_bearer, extract_optional_user, Error},
 };
 use actix_web::{
     error,
-    web::{Data, HttpRequest, HttpResponse, Json, Path},
+    web::{Data, HttpRequest, HttpResponse, Json, Path, Query},
     Scope as ActixScope,
 };
 use diesel::{
     dsl::{delete, exists, select, update},
     pg::PgConnection,
     r2d2::{ConnectionManager, Pool},
-    BelongingToDsl, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl,
+    BelongingToDsl, BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    QueryDsl, RunQueryDsl,
 };
 
-/// Ensures that the provided access token matches that of the provided user.
-pub(crate) fn continue_if_authenticated(user: &User, auth_token: &str) -> Result<(), Error> {
-    // Return Ok if the tokens match, otherwise unauth
-    if hash_token(auth_token) != user.oauth_token {
-        Err(Error(error::ErrorUnauthorized(
-            "The provided access token does not match the recorded token for this user.",
-        )))
+/// Ensures that the given visibility is one of the known privacy settings.
+fn continue_if_valid_visibility(vis: i16) -> Result<(), Error> {
+    if vis < VISIBILITY_PRIVATE || vis > VISIBILITY_PUBLIC {
+        Err(Error(error::ErrorBadRequest(format!(
+            "'{}' is not a valid visibility (0 => private, 1 => unlisted, 2 => public).",
+            vis
+        ))))
     } else {
         Ok(())
     }
 }
 
-/// Ensures that the user has the given permissions.
-pub(crate) fn continue_if_has_perms(
-    conn: &PgConnection,
-    board_uid: i32,
-    user: &User,
-    owner: bool,
-    can_read: bool,
-    can_write: bool,
-) -> Result<(), Error> {
-    // Construct a query to get the board with ID from the db
-    let b_query = boards.find(board_uid);
-
-    // The board must exist for all to continue. Otherwise, throw a 404.
-    if !select(exists(b_query)).get_result(conn)? {
-        // Return a 404
-        return Err(Error(error::ErrorNotFound(format!(
-            "No board with the id '{}' exists.",
-            board_uid
-        ))));
-    }
-
-    // Get the board that matches the given UID from the database
-    let matching_board: Board = b_query.first(conn)?;
-
-    // If the end-user wants to enforce that the token matches an owner, so be it
-    if owner {
-        // If the user is not registered as the owner, return an error.
-        if matching_board.user_id != user.id {
-            // Respond with an unauth
-            return Err(Error(error::ErrorUnauthorized(
-                "The provided access token does not match a user with the required privileges.",
-            )));
-        }
-
-        // The user is authenticated
-        return Ok(());
-    }
-
-    // Construct a query to get the permission belonging to the user with the board
-    let p_query =
-        Permission::belonging_to(user).filter(schema::permissions::board_id.eq(board_uid));
-
-    // If this permission doesn't exist, the user isn't even envited to the board
-    if !select(exists(p_query)).get_result(conn)? {
-        // Return a 404
-        return Err(Error(error::ErrorNotFound(
-            "The provided access token does not match a user that has been invited to this board.",
-        )));
-    }
-
-    // Get the permission belonging to the user
-    let permission: Permission = Permission::belonging_to(user)
-        .filter(schema::permissions::board_id.eq(board_uid))
-        .first(conn)?;
-
-    // Ensure the user has the proper permissions to be able to write & read to the file
-    if !(permission.write && permission.read || !can_write) {
-        // Respond with an unauth
-        return Err(Error(error::ErrorUnauthorized(
-            "The provided access token does not match a user with the required privileges (write).",
-        )));
-    }
-
-    // Ensure that the user has the proper permissions to be able to read to the file
-    if can_read && !permission.read {
-        // Respond with an unauth
-        return Err(Error(error::ErrorUnauthorized(
-            "THe provided access token does not match a user with the required privileges (read).",
-        )));
-    }
-
-    // The user should only have gotten this far if each of the preconditions were met--meaning
-    // that the user does have sufficient privileges
-    Ok(())
-}
-
 /// Constructs an actix service group for the boards endpoint.
 pub fn build_service_group() -> ActixScope {
     ActixScope::new("/boards")
         .service(viewable_boards)
+        .service(transfers::incoming_transfers)
+        .service(shared_board)
         .service(specific_board)
         .service(new_board)
         .service(update_specific_board)
         .service(delete_specific_board)
         .service(all_permissions)
+        .service(grant_permission)
+        .service(update_permission)
+        .service(revoke_permission)
+        .service(invitations::invite_by_email)
+        .service(invitations::all_invitations)
+        .service(invitations::revoke_invitation)
+        .service(transfers::offer_transfer)
+        .service(transfers::pending_transfer)
+        .service(transfers::cancel_transfer)
+        .service(transfers::accept_transfer)
+        .service(transfers::decline_transfer)
         .service(all_notes)
         .service(all_users)
 }
@@ -125,11 +73,17 @@ pub fn build_service_group() -> ActixScope {
 /// * `pool` - The connection pool that will be used to connect to the postgres database
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
+/// * `page` - Which page of results to return (e.g. ?limit=50&cursor=...)
+/// * `filter` - Which results to return (e.g. ?title_contains=roadmap)
 #[get("")]
 pub async fn viewable_boards(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     req: HttpRequest,
-) -> Result<Json<Vec<i32>>, Error> {
+    sort: Query<Sort>,
+    page: Query<Page>,
+    filter: Query<BoardFilter>,
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel.
     let conn = pool.get()?;
 
@@ -137,15 +91,10 @@ pub async fn viewable_boards(
     let token = extract_bearer(&req)?;
 
     // Get the currently authenticated user
-    let u: User = match users.filter(oauth_token.eq(hash_token(token))).first(&conn) {
-        Ok(u) => Ok(u),
-        Err(_) => Err(Error(error::ErrorNotFound(
-            "The requested user does not exist.",
-        ))),
-    }?;
+    let u: User = user_for_token(&conn, token)?;
 
-    // Get and return any of the boards belonging to the user (includes shared boards)
-    Ok(Json(
+    // Get any of the boards belonging to the user (includes shared boards) that match the filter
+    let viewable = filter.apply(
         boards
             .filter(
                 schema::boards::user_id.eq(u.id).or(exists(
@@ -156,9 +105,14 @@ pub async fn viewable_boards(
                     ),
                 )),
             )
-            .select(schema::boards::id)
-            .load::<i32>(&conn)?,
-    ))
+            .into_boxed(),
+    );
+
+    // Return a page of the boards' IDs
+    let rows = crate::paginated!(viewable, boards, schema::boards::id, *sort, &page)?
+        .load::<Keyed<i32>>(&conn)?;
+
+    Ok(page_response(&req, rows, *sort, &page))
 }
 
 /// Initializes and puts a new board in the currently authenticated user's db directory.
@@ -172,7 +126,7 @@ pub async fn viewable_boards(
 pub async fn new_board(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     req: HttpRequest,
-    board: Json<NewBoard>,
+    mut board: Json<NewBoard>,
 ) -> Result<Json<Board>, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel
     let conn = pool.get()?;
@@ -190,24 +144,37 @@ pub async fn new_board(
     }?;
 
     // Ensure that the user is who they say they are
-    continue_if_authenticated(&u, token)?;
+    continue_if_authenticated(&conn, &u, token)?;
 
-    // Put the board into the database, and save a reference to its associated JSON encoding
-    let written_board: Board = diesel::insert_into(boards)
-        .values(&*board)
-        .get_result(&conn)?;
+    // Make sure that the board's privacy setting actually means something
+    continue_if_valid_visibility(board.visibility)?;
 
-    // Put permissions for the owner of this board into the database
-    diesel::insert_into(permissions)
-        .values(&NewPermission {
-            user_id: u.id,
-            board_id: written_board.id,
-            read: true,
-            write: true,
-        })
-        .execute(&conn)?;
+    // Give the board a name that it can be shared by
+    board.share_slug = random_token();
+
+    // Remember who created the board
+    board.updated_by = Some(u.id);
+
+    // Put the board into the database along with its owner, so that it never exists without one
+    let written_board: Board = conn.transaction::<_, Error, _>(|| {
+        let written_board: Board = diesel::insert_into(boards)
+            .values(&*board)
+            .get_result(&conn)?;
+
+        // Make the user the owner of the board
+        diesel::insert_into(permissions)
+            .values(&NewPermission {
+                user_id: u.id,
+                board_id: written_board.id,
+                role: ROLE_OWNER,
+                updated_by: Some(u.id),
+            })
+            .execute(&conn)?;
+
+        Ok(written_board)
+    })?;
 
-    // Put the new board in the database and return it
+    // Return the new board
     Ok(Json(written_board))
 }
 
@@ -218,36 +185,28 @@ pub async fn new_board(
 /// * `pool` - The connection pool that will be used to connect to the postgres database
 /// * `board_uid` - The ID of the requested board
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
-/// token (required) of the user
+/// token (optional for public boards) of the user
 #[get("/{board_id}")]
 pub async fn specific_board(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     board_uid: Path<i32>,
     req: HttpRequest,
-) -> Result<Json<Board>, Error> {
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel.
     let conn = pool.get()?;
 
-    // Get an authorization token from the headers sent with the request
-    let token = extract_bearer(&req)?;
-
-    // Get the requested user from the database, and return a 404 if it doesn't exist.
-    let u: User = match users.filter(oauth_token.eq(hash_token(token))).first(&conn) {
-        Ok(u) => Ok(u),
-        Err(_) => Err(Error(error::ErrorNotFound(
-            "The requested user does not exist.",
-        ))),
-    }?;
+    // Get the user making the request, if they provided a token
+    let u = extract_optional_user(&conn, &req)?;
 
     // Get the requested board from the database
     match boards.find(*board_uid).first::<Board>(&conn) {
         // The board exists, let's return it
         Ok(board) => {
             // Ensure the user is able to read from the board
-            continue_if_has_perms(&conn, *board_uid, &u, false, true, false)?;
+            continue_if_allowed(&conn, board.id, u.as_ref(), Capability::Read)?;
 
-            // Return the board
-            Ok(Json(board))
+            // Return the board, unless the caller's copy is already up to date
+            respond_with_tag(&req, &board, board.version)
         }
 
         // The board does not exist, return a 404
@@ -258,6 +217,44 @@ pub async fn specific_board(
     }
 }
 
+/// Gets a specific board by its share slug.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `slug` - The share slug of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (optional for public boards) of the user
+#[get("/shared/{slug}")]
+pub async fn shared_board(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    slug: Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel.
+    let conn = pool.get()?;
+
+    // Get the user making the request, if they provided a token
+    let u = extract_optional_user(&conn, &req)?;
+
+    // Get the board with the matching slug from the database, and return a 404 if there isn't one
+    let board: Board = match boards
+        .filter(schema::boards::share_slug.eq(&*slug))
+        .first(&conn)
+    {
+        Ok(b) => Ok(b),
+        Err(_) => Err(Error(error::ErrorNotFound(
+            "No board has been shared with the requested slug.",
+        ))),
+    }?;
+
+    // Ensure the user is able to read from the board
+    continue_if_allowed(&conn, board.id, u.as_ref(), Capability::Read)?;
+
+    // Return the board, unless the caller's copy is already up to date
+    respond_with_tag(&req, &board, board.version)
+}
+
 /// Updates a specific board by its ID.
 ///
 /// # Arguments
@@ -273,7 +270,7 @@ pub async fn update_specific_board(
     board_uid: Path<i32>,
     mut update_to_board: Json<UpdateBoard>,
     req: HttpRequest,
-) -> Result<Json<Board>, Error> {
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel
     let conn = pool.get()?;
 
@@ -290,18 +287,61 @@ pub async fn update_specific_board(
     }?;
 
     // Get the matching user from the request so that we can authenticate
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(token)))
-        .first(&conn)?;
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the user is allowed to change the board's settings
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::ManageBoard,
+    )?;
+
+    // Boards can only change hands through a transfer that the recipient accepts
+    if update_to_board
+        .user_id
+        .map_or(false, |usr_id| usr_id != board_entry.user_id)
+    {
+        return Err(Error(error::ErrorBadRequest(format!(
+            "The owner of a board can't be changed directly. Use /boards/{}/transfer instead.",
+            *board_uid
+        ))));
+    }
 
-    // Ensure that the user is actually the owner of the board
-    continue_if_has_perms(&conn, *board_uid, &matching_user, true, false, false)?;
+    // Make sure that the caller's copy of the board is still the current one
+    continue_if_matches(&req, &board_entry, board_entry.version)?;
 
-    // Merge the old and new boards
-    let merged_boards: Board = update_to_board.new_board(board_entry);
+    // Make sure that the board's new privacy setting actually means something
+    if let Some(vis) = update_to_board.visibility {
+        continue_if_valid_visibility(vis)?;
+    }
 
-    // Update the board in the table
-    Ok(Json(update(boards).set(&merged_boards).get_result(&conn)?))
+    // Merge the old and new boards
+    let expected_version = board_entry.version;
+    let merged_boards: Board = update_to_board.new_board(board_entry, matching_user.id);
+
+    // Update the board in the table, as long as nobody else has updated it since it was read
+    match update(
+        boards.filter(
+            schema::boards::id
+                .eq(*board_uid)
+                .and(schema::boards::version.eq(expected_version)),
+        ),
+    )
+    .set(&merged_boards)
+    .get_result::<Board>(&conn)
+    .optional()?
+    {
+        // Return the board along with its new ETag
+        Some(written_board) => Ok(tagged_response(&written_board, written_board.version)),
+
+        // The board was changed out from under us, so give the caller the current copy
+        None => {
+            let current: Board = boards.find(*board_uid).first(&conn)?;
+
+            Err(precondition_failed(&current, current.version))
+        }
+    }
 }
 
 /// Deletes a board with the given ID.
@@ -325,23 +365,20 @@ pub async fn delete_specific_board(
     let token = extract_bearer(&req)?;
 
     // Get the user that was mentioned in the request
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(token)))
-        .first(&conn)?;
-
-    // Ensure that the user is the owner of the board
-    continue_if_has_perms(&conn, *board_uid, &matching_user, true, false, false)?;
-
-    // Delete the board
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the user is allowed to delete the board
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::DeleteBoard,
+    )?;
+
+    // Delete the board. Its notes (and their history), permissions, invitations and transfers are
+    // deleted along with it by the database, in the same statement.
     delete(boards.find(*board_uid)).execute(&conn)?;
 
-    // Delete the associated permissions
-    delete(permissions.filter(schema::permissions::board_id.eq(*board_uid))).execute(&conn)?;
-
-    // Delete the associated notes
-    delete(notes.filter(schema::notes::board_id.eq(*board_uid))).execute(&conn)?;
-
-    // Update the board in the table
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -353,11 +390,13 @@ pub async fn delete_specific_board(
 /// * `board_uid` - The ID of the requested board
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
 #[get("/{board_id}/permissions")]
 pub async fn all_permissions(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     board_uid: Path<i32>,
     req: HttpRequest,
+    sort: Query<Sort>,
 ) -> Result<Json<Vec<Permission>>, Error> {
     // Get a connection from the provided connection pool, so we can start using dieisel
     let conn = pool.get()?;
@@ -369,58 +408,250 @@ pub async fn all_permissions(
     let matching_board: Board = boards.find(*board_uid).first(&conn)?;
 
     // Get the user making the request
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(token)))
-        .first(&conn)?;
+    let matching_user: User = user_for_token(&conn, token)?;
 
-    // Ensure that the requesting user is in fact a user that is able to view the board
-    continue_if_has_perms(&conn, *board_uid, &matching_user, false, true, false)?;
+    // Ensure that the requesting user is in fact a member of the board
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::ViewMembers,
+    )?;
 
     // Return each of the permissions belonging to the board
     Ok(Json(
-        Permission::belonging_to(&matching_board).get_results(&conn)?,
+        crate::sorted!(
+            Permission::belonging_to(&matching_board).into_boxed(),
+            permissions,
+            sort.into_inner()
+        )
+        .get_results(&conn)?,
     ))
 }
 
-/// Gets a list of notes belonging to the board.
+/// Gets the permission that the given user has been granted on the given board, returning a 404
+/// if the user hasn't been invited to the board.
+fn find_permission(conn: &PgConnection, board_uid: i32, usr_id: i32) -> Result<Permission, Error> {
+    match permissions
+        .filter(
+            schema::permissions::board_id
+                .eq(board_uid)
+                .and(schema::permissions::user_id.eq(usr_id)),
+        )
+        .first(conn)
+    {
+        Ok(perm) => Ok(perm),
+        Err(_) => Err(Error(error::ErrorNotFound(format!(
+            "The requested user (id: {}) has not been invited to the requested board (id: {}).",
+            usr_id, board_uid
+        )))),
+    }
+}
+
+/// Grants a user access to a board. Only the owner and admins of the board may invite other
+/// users.
 ///
 /// # Arguments
 ///
 /// * `pool` - The connection pool that will be used to connect to the postgres database
 /// * `board_uid` - The ID of the requested board
+/// * `grant` - A JSON request detailing which user to invite, and what they may do
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
-#[get("/{board_id}/notes")]
-pub async fn all_notes(
+#[post("/{board_id}/permissions")]
+pub async fn grant_permission(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     board_uid: Path<i32>,
+    grant: Json<GrantPermission>,
     req: HttpRequest,
-) -> Result<Json<Vec<i32>>, Error> {
-    // Get a connection from the provided connection pool, so we can start using dieisel
+) -> Result<Json<Permission>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
     let conn = pool.get()?;
 
     // Get an authorization token from the headers sent with the request
     let token = extract_bearer(&req)?;
 
-    // Look at the request path, extract the board ID, and find the matching board.
-    let matching_board: Board = boards.find(*board_uid).first(&conn)?;
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to manage the board's members
+    let actor_role = continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::ManageMembers,
+    )?
+    .unwrap_or(ROLE_VIEWER);
+
+    // Make sure that the requesting user is allowed to hand out the requested role
+    continue_if_can_assign(actor_role, None, Some(grant.role))?;
+
+    // The invited user must exist
+    if !select(exists(users.find(grant.user_id))).get_result(&conn)? {
+        return Err(Error(error::ErrorNotFound(format!(
+            "The requested user (id: {}) does not exist.",
+            grant.user_id
+        ))));
+    }
+
+    // Put the permission in the database, unless the user has already been invited
+    match diesel::insert_into(permissions)
+        .values(&NewPermission {
+            user_id: grant.user_id,
+            board_id: *board_uid,
+            role: grant.role,
+            updated_by: Some(matching_user.id),
+        })
+        .on_conflict_do_nothing()
+        .get_result(&conn)
+        .optional()?
+    {
+        Some(perm) => Ok(Json(perm)),
+        None => Err(Error(error::ErrorConflict(format!(
+            "The requested user (id: {}) has already been invited to this board.",
+            grant.user_id
+        )))),
+    }
+}
+
+/// Changes the role that a user has on a board. Only the owner and admins of the board may change
+/// the roles of other users.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `context` - The ID of the requested board, followed by the ID of the invited user
+/// * `changes` - A JSON request detailing how to update the permission
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[patch("/{board_id}/permissions/{user_id}")]
+pub async fn update_permission(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    context: Path<(i32, i32)>,
+    mut changes: Json<UpdatePermission>,
+    req: HttpRequest,
+) -> Result<Json<Permission>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
 
     // Get the user making the request
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(token)))
-        .first(&conn)?;
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to manage the board's members
+    let actor_role = continue_if_allowed(
+        &conn,
+        context.0,
+        Some(&matching_user),
+        Capability::ManageMembers,
+    )?
+    .unwrap_or(ROLE_VIEWER);
 
-    // Ensure that the user is in fact the owner of the board
-    continue_if_has_perms(&conn, *board_uid, &matching_user, false, true, true)?;
+    // Get the permission that will be changed
+    let perm = find_permission(&conn, context.0, context.1)?;
 
-    // Return each of the notes belonging to the board (just their IDs)
+    // Make sure that the requesting user is allowed to make the change
+    continue_if_can_assign(actor_role, Some(perm.role), Some(changes.role))?;
+
+    // Remember who made the change
+    changes.updated_by = Some(matching_user.id);
+
+    // Update the permission in the table
     Ok(Json(
-        Note::belonging_to(&matching_board)
-            .select(schema::notes::id)
-            .get_results(&conn)?,
+        update(permissions.filter(schema::permissions::id.eq(perm.id)))
+            .set(&*changes)
+            .get_result(&conn)?,
     ))
 }
 
+/// Removes a user's access to a board. Only the owner and admins of the board may remove other
+/// users.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `context` - The ID of the requested board, followed by the ID of the invited user
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/{board_id}/permissions/{user_id}")]
+pub async fn revoke_permission(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    context: Path<(i32, i32)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to manage the board's members
+    let actor_role = continue_if_allowed(
+        &conn,
+        context.0,
+        Some(&matching_user),
+        Capability::ManageMembers,
+    )?
+    .unwrap_or(ROLE_VIEWER);
+
+    // Make sure that the user was actually invited, and that they can be removed, then remove them
+    let perm = find_permission(&conn, context.0, context.1)?;
+    continue_if_can_assign(actor_role, Some(perm.role), None)?;
+    delete(permissions.filter(schema::permissions::id.eq(perm.id))).execute(&conn)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Gets a list of notes belonging to the board.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (optional for public boards) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
+/// * `page` - Which page of results to return (e.g. ?limit=50&cursor=...)
+/// * `filter` - Which results to return (e.g. ?author=4&title_contains=todo)
+#[get("/{board_id}/notes")]
+pub async fn all_notes(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    req: HttpRequest,
+    sort: Query<Sort>,
+    page: Query<Page>,
+    filter: Query<NoteFilter>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using dieisel
+    let conn = pool.get()?;
+
+    // Look at the request path, extract the board ID, and find the matching board.
+    let matching_board: Board = boards.find(*board_uid).first(&conn)?;
+
+    // Get the user making the request, if they provided a token
+    let matching_user = extract_optional_user(&conn, &req)?;
+
+    // Ensure that the user is able to read from the board
+    continue_if_allowed(
+        &conn,
+        matching_board.id,
+        matching_user.as_ref(),
+        Capability::Read,
+    )?;
+
+    // Return a page of the notes belonging to the board that match the filter (just their IDs)
+    let board_notes = filter.apply(Note::belonging_to(&matching_board).into_boxed());
+    let rows = crate::paginated!(board_notes, notes, schema::notes::id, *sort, &page)?
+        .get_results::<Keyed<i32>>(&conn)?;
+
+    Ok(page_response(&req, rows, *sort, &page))
+}
+
 /// Gets a list of users associated with the board.
 ///
 /// # Arguments
@@ -429,12 +660,18 @@ pub async fn all_notes(
 /// * `board_uid` - The ID of the requested board
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
+/// * `page` - Which page of results to return (e.g. ?limit=50&cursor=...)
+/// * `filter` - Which results to return (e.g. ?updated_since=2020-05-27T00:00:00Z)
 #[get("/{board_id}/users")]
 pub async fn all_users(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     board_uid: Path<i32>,
     req: HttpRequest,
-) -> Result<Json<Vec<i32>>, Error> {
+    sort: Query<Sort>,
+    page: Query<Page>,
+    filter: Query<UserFilter>,
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using dieisel
     let conn = pool.get()?;
 
@@ -445,17 +682,26 @@ pub async fn all_users(
     let matching_board: Board = boards.find(*board_uid).first(&conn)?;
 
     // Get the user from the database with the provided oauth token
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(token)))
-        .first(&conn)?;
-
-    // Ensure that the requesting user has access to the board (read, at least)
-    continue_if_has_perms(&conn, *board_uid, &matching_user, false, true, false)?;
-
-    // Return a list of invited users
-    Ok(Json(
-        Permission::belonging_to(&matching_board)
-            .select(schema::permissions::user_id)
-            .get_results(&conn)?,
-    ))
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is a member of the board
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::ViewMembers,
+    )?;
+
+    // Return a page of the invited users that match the filter
+    let members = filter.apply_to_members(Permission::belonging_to(&matching_board).into_boxed());
+    let rows = crate::paginated!(
+        members,
+        permissions,
+        schema::permissions::user_id,
+        *sort,
+        &page
+    )?
+    .get_results::<Keyed<i32>>(&conn)?;
+
+    Ok(page_response(&req, rows, *sort, &page))
 }
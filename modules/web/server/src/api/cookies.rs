@@ -0,0 +1,162 @@
+use super::super::crypto::SecretKey;
+use actix_session::CookieSession;
+use actix_web::{
+    cookie::{Cookie, CookieJar, Key},
+    dev::ServiceRequest,
+    http::header::{HeaderValue, COOKIE},
+};
+use std::io;
+
+/// The name of the cookie that sessions are stored in.
+pub const SESSION_COOKIE: &str = "notedly-session";
+
+/// The fewest bytes that a cookie key can have.
+const MIN_KEY_LEN: usize = 32;
+
+/// The keys that session cookies are encrypted with. Every worker and replica of the server must
+/// use the same keys, or logins that are started on one and finished on another lose their
+/// state. Cookies are always encrypted with the primary key, but cookies encrypted with a
+/// previous key are still accepted, so that the primary key can be rotated without logging
+/// everybody out.
+#[derive(Clone)]
+pub struct CookieKeys {
+    /// The raw key that new cookies are encrypted with
+    primary: Vec<u8>,
+
+    /// The key that new cookies are encrypted with, as used by the cookie jar
+    primary_key: Key,
+
+    /// The keys that cookies may have been encrypted with before the last rotation
+    previous: Vec<Key>,
+}
+
+impl CookieKeys {
+    /// Initializes a new CookieKeys from the configured keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `primary` - The key that new cookies are encrypted with (at least 32 bytes long)
+    /// * `previous` - The keys that older cookies may still be encrypted with
+    pub fn new(primary: &str, previous: &[String]) -> io::Result<Self> {
+        // Short keys can't be stretched into the cookie jar's encryption key
+        for key in previous.iter().map(String::as_str).chain(Some(primary)) {
+            if key.len() < MIN_KEY_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cookie keys must be at least {} bytes long", MIN_KEY_LEN),
+                ));
+            }
+        }
+
+        Ok(Self::from_bytes(
+            primary.as_bytes().to_vec(),
+            previous.iter().map(|key| key.as_bytes()).collect(),
+        ))
+    }
+
+    /// Derives the cookie key from the server's secret key, for servers that haven't configured
+    /// one. The server's secret key is already shared by every replica.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret_key` - The server's secret key
+    pub fn derive(secret_key: &SecretKey) -> Self {
+        Self::from_bytes(secret_key.derive_key("session-cookie"), Vec::new())
+    }
+
+    /// Initializes a new CookieKeys from raw keys that are known to be long enough.
+    fn from_bytes(primary: Vec<u8>, previous: Vec<&[u8]>) -> Self {
+        Self {
+            primary_key: Key::from_master(&primary),
+            primary,
+            previous: previous.into_iter().map(Key::from_master).collect(),
+        } // Return the new instance
+    }
+
+    /// Makes the middleware that stores sessions in cookies encrypted with the primary key.
+    ///
+    /// # Arguments
+    ///
+    /// * `secure` - Whether or not the cookie should only be sent over TLS
+    pub(crate) fn session_middleware(&self, secure: bool) -> CookieSession {
+        CookieSession::private(&self.primary)
+            .name(SESSION_COOKIE)
+            .secure(secure)
+    }
+
+    /// Re-encrypts the session cookie sent with the given request with the primary key, if it was
+    /// encrypted with a previous key. Must run before the session middleware reads the cookie.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The request that the cookie was sent with
+    pub(crate) fn rekey(&self, req: &mut ServiceRequest) {
+        // Nothing needs to be done until a key has been rotated out
+        if self.previous.is_empty() {
+            return;
+        }
+
+        let header = match req.headers().get(COOKIE).and_then(|h| h.to_str().ok()) {
+            Some(h) => h.to_owned(),
+            None => return,
+        };
+
+        let mut rekeyed = false;
+        let pairs: Vec<String> = header
+            .split(';')
+            .map(|pair| {
+                let pair = pair.trim();
+
+                match split_pair(pair) {
+                    Some((SESSION_COOKIE, value)) => match self.reseal(value) {
+                        Some(resealed) => {
+                            rekeyed = true;
+
+                            format!("{}={}", SESSION_COOKIE, resealed)
+                        }
+                        None => pair.to_owned(),
+                    },
+                    _ => pair.to_owned(),
+                }
+            })
+            .collect();
+
+        if rekeyed {
+            if let Ok(value) = HeaderValue::from_str(&pairs.join("; ")) {
+                req.headers_mut().insert(COOKIE, value);
+            }
+        }
+    }
+
+    /// Gets the value of a session cookie encrypted with a previous key, encrypted with the
+    /// primary key instead. Returns `None` if the cookie is already encrypted with the primary
+    /// key, or wasn't encrypted with any of the keys.
+    fn reseal(&self, value: &str) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(SESSION_COOKIE, value.to_owned()));
+
+        if jar.private(&self.primary_key).get(SESSION_COOKIE).is_some() {
+            return None;
+        }
+
+        let plain = self
+            .previous
+            .iter()
+            .find_map(|key| jar.private(key).get(SESSION_COOKIE))?;
+
+        // Encrypt the cookie again in a fresh jar, so that its new value can be read back out
+        let mut resealed = CookieJar::new();
+        resealed.private(&self.primary_key).add(plain);
+
+        resealed
+            .get(SESSION_COOKIE)
+            .map(|cookie| cookie.value().to_owned())
+    }
+}
+
+/// Splits a `name=value` cookie pair at its first equals sign.
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+    let mut parts = pair.splitn(2, '=');
+
+    Some((parts.next()?.trim(), parts.next()?.trim()))
+}
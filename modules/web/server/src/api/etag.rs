@@ -0,0 +1,126 @@
+use super::users::Error;
+use actix_web::{
+    error::{self, InternalError},
+    http::header::{ETag, EntityTag, Header, IfMatch, IfNoneMatch},
+    web::HttpRequest,
+    HttpResponse,
+};
+use serde::Serialize;
+
+/// Gets the entity tag describing the given version of a note or board.
+///
+/// # Arguments
+///
+/// * `version` - The version of the entity, as stored in its `version` column
+pub(crate) fn entity_tag(version: i32) -> EntityTag {
+    EntityTag::strong(version.to_string())
+}
+
+/// Ensures that the `If-Match` header sent with the request (if any) matches the given version of
+/// an entity. If it doesn't, a 412 is returned containing the current copy of the entity, so the
+/// caller can merge their changes and try again.
+///
+/// # Arguments
+///
+/// * `req` - An HTTP request provided by the caller of this method
+/// * `current` - The entity, as it currently exists in the database
+/// * `version` - The current version of the entity
+pub(crate) fn continue_if_matches<T: Serialize>(
+    req: &HttpRequest,
+    current: &T,
+    version: i32,
+) -> Result<(), Error> {
+    // Requests without the header are unconditional
+    if !req.headers().contains_key(IfMatch::name()) {
+        return Ok(());
+    }
+
+    // The entity tags must be compared strongly, since the header is protecting a write
+    let tag = entity_tag(version);
+    let matches = match IfMatch::parse(req).map_err(|e| Error(error::ErrorBadRequest(e)))? {
+        IfMatch::Any => true,
+        IfMatch::Items(tags) => tags.iter().any(|t| t.strong_eq(&tag)),
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(precondition_failed(current, version))
+    }
+}
+
+/// Checks whether the `If-None-Match` header sent with the request matches the given version of
+/// an entity, meaning that the caller's copy is already up to date.
+///
+/// # Arguments
+///
+/// * `req` - An HTTP request provided by the caller of this method
+/// * `version` - The current version of the entity
+pub(crate) fn is_not_modified(req: &HttpRequest, version: i32) -> Result<bool, Error> {
+    // Requests without the header always get a full response
+    if !req.headers().contains_key(IfNoneMatch::name()) {
+        return Ok(false);
+    }
+
+    // Reads may compare entity tags weakly
+    let tag = entity_tag(version);
+    Ok(
+        match IfNoneMatch::parse(req).map_err(|e| Error(error::ErrorBadRequest(e)))? {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|t| t.weak_eq(&tag)),
+        },
+    )
+}
+
+/// Builds a response for a read of the given entity, honoring any `If-None-Match` header sent
+/// with the request.
+///
+/// # Arguments
+///
+/// * `req` - An HTTP request provided by the caller of this method
+/// * `entity` - The entity that was requested
+/// * `version` - The current version of the entity
+pub(crate) fn respond_with_tag<T: Serialize>(
+    req: &HttpRequest,
+    entity: &T,
+    version: i32,
+) -> Result<HttpResponse, Error> {
+    // The caller already has this version, so don't bother sending it again
+    if is_not_modified(req, version)? {
+        return Ok(HttpResponse::NotModified()
+            .set(ETag(entity_tag(version)))
+            .finish());
+    }
+
+    Ok(tagged_response(entity, version))
+}
+
+/// Builds a 200 response containing the given entity and its entity tag.
+///
+/// # Arguments
+///
+/// * `entity` - The entity that will be sent to the caller
+/// * `version` - The current version of the entity
+pub(crate) fn tagged_response<T: Serialize>(entity: &T, version: i32) -> HttpResponse {
+    HttpResponse::Ok()
+        .set(ETag(entity_tag(version)))
+        .json(entity)
+}
+
+/// Constructs a 412 error containing the current copy of an entity.
+///
+/// # Arguments
+///
+/// * `current` - The entity, as it currently exists in the database
+/// * `version` - The current version of the entity
+pub(crate) fn precondition_failed<T: Serialize>(current: &T, version: i32) -> Error {
+    Error(
+        InternalError::from_response(
+            "The entity has been modified since it was last read.",
+            HttpResponse::PreconditionFailed()
+                .set(ETag(entity_tag(version)))
+                .json(current),
+        )
+        .into(),
+    )
+}
@@ -0,0 +1,238 @@
+use super::{
+    super::{
+        models::{NewUser, NewUserIdentity, UpdateUser, User, UserIdentity},
+        schema::{
+            self, auth_sessions::dsl::auth_sessions,
+            password_credentials::dsl::password_credentials, user_identities::dsl::user_identities,
+            users::dsl::users,
+        },
+    },
+    auth::user_for_token,
+    users::{extract_bearer, Error},
+};
+use actix_web::{
+    error,
+    web::{Data, HttpRequest, HttpResponse, Json, Path},
+};
+use diesel::{
+    dsl::{exists, select},
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    BelongingToDsl, BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    QueryDsl, RunQueryDsl,
+};
+
+/// Gets the identity issued by the given provider, if anybody has linked it.
+fn find_identity(
+    conn: &PgConnection,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<UserIdentity>, Error> {
+    Ok(user_identities
+        .filter(
+            schema::user_identities::provider
+                .eq(provider)
+                .and(schema::user_identities::subject.eq(subject)),
+        )
+        .first(conn)
+        .optional()?)
+}
+
+/// Gets the account that the given provider identity belongs to, creating a new account for it if
+/// it hasn't been seen before. The account's email is updated with the given details. Returns the
+/// account along with the identity.
+///
+/// If `link_to` is provided, the identity is first linked to that account instead. Linking fails
+/// if the identity already belongs to another account, or if the account has already linked
+/// another identity from the same provider.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the account will be read and written with
+/// * `provider` - The name of the provider that the user logged in with
+/// * `subject` - The provider's unique identifier for the user
+/// * `link_to` - The ID of the signed-in user linking the identity, if any
+/// * `details` - The user's latest email
+pub(crate) fn resolve_account(
+    conn: &PgConnection,
+    provider: &str,
+    subject: &str,
+    link_to: Option<i32>,
+    details: &UpdateUser,
+) -> Result<(User, UserIdentity), Error> {
+    // Link, log in, or sign up atomically, so that a failed link can't leave a half-updated account
+    conn.transaction::<_, Error, _>(|| {
+        let account = match (find_identity(conn, provider, subject)?, link_to) {
+            // An identity can only belong to one account
+            (Some(identity), Some(usr_id)) if identity.user_id != usr_id => {
+                return Err(Error(error::ErrorConflict(format!(
+                    "This {} account is already linked to another user.",
+                    provider
+                ))))
+            }
+
+            // The identity has been seen before, so log into its account
+            (Some(identity), _) => Some(identity),
+
+            // Attach the identity to the signed-in user
+            (None, Some(usr_id)) => {
+                if select(exists(
+                    user_identities.filter(
+                        schema::user_identities::user_id
+                            .eq(usr_id)
+                            .and(schema::user_identities::provider.eq(provider)),
+                    ),
+                ))
+                .get_result(conn)?
+                {
+                    return Err(Error(error::ErrorConflict(format!(
+                        "Another {} account is already linked to this user. Unlink it first.",
+                        provider
+                    ))));
+                }
+
+                Some(
+                    diesel::insert_into(user_identities)
+                        .values(&NewUserIdentity {
+                            user_id: usr_id,
+                            provider,
+                            subject,
+                        })
+                        .get_result(conn)?,
+                )
+            }
+
+            // Nobody has logged in with this identity yet
+            (None, None) => None,
+        };
+
+        match account {
+            // Update the existing account with the user's latest details
+            Some(identity) => match diesel::update(users.find(identity.user_id))
+                .set(details)
+                .get_result(conn)
+                .optional()?
+            {
+                Some(u) => Ok((u, identity)),
+                None => Err(Error(error::ErrorNotFound(format!(
+                    "The requested user (id: {}) does not exist.",
+                    identity.user_id
+                )))),
+            },
+
+            // Make a new account, along with the identity used to log into it
+            None => {
+                let u: User = diesel::insert_into(users)
+                    .values(&NewUser {
+                        email: details.email,
+                    })
+                    .get_result(conn)?;
+
+                let identity: UserIdentity = diesel::insert_into(user_identities)
+                    .values(&NewUserIdentity {
+                        user_id: u.id,
+                        provider,
+                        subject,
+                    })
+                    .get_result(conn)?;
+
+                Ok((u, identity))
+            }
+        }
+    })
+}
+
+/// Gets each of the provider accounts that the user can log in with.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("/user/identities")]
+pub async fn identities(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<Json<Vec<UserIdentity>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Return each of the user's identities
+    Ok(Json(
+        UserIdentity::belonging_to(&matching_user)
+            .order(schema::user_identities::id)
+            .load(&conn)?,
+    ))
+}
+
+/// Unlinks the user's account at the given provider, so that it can no longer be used to log in.
+/// Users must keep at least one linked account.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `provider` - The name of the provider whose account will be unlinked
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/user/identities/{provider}")]
+pub async fn unlink_identity(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    provider: Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Remove the identity, as long as it isn't the last one
+    conn.transaction::<_, Error, _>(|| {
+        let linked: Vec<UserIdentity> = UserIdentity::belonging_to(&matching_user)
+            .for_update()
+            .load(&conn)?;
+
+        let unlinked = match linked
+            .iter()
+            .find(|identity| identity.provider == *provider)
+        {
+            Some(identity) => identity.id,
+            None => {
+                return Err(Error(error::ErrorNotFound(format!(
+                    "No {} account is linked to this user.",
+                    *provider
+                ))))
+            }
+        };
+
+        // Users with a password can still log in without any linked accounts
+        if linked.len() == 1
+            && !select(exists(password_credentials.find(matching_user.id))).get_result(&conn)?
+        {
+            return Err(Error(error::ErrorConflict(
+                "The only account that this user can log in with can't be unlinked.",
+            )));
+        }
+
+        // Sessions started with the identity stay logged in, but can no longer be revalidated
+        // with its provider
+        diesel::update(auth_sessions.filter(schema::auth_sessions::identity_id.eq(Some(unlinked))))
+            .set(schema::auth_sessions::identity_id.eq(None::<i32>))
+            .execute(&conn)?;
+
+        diesel::delete(user_identities.find(unlinked)).execute(&conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,486 @@
+use super::{
+    super::{
+        crypto::{random_token, SecretKey},
+        models::{
+            BoardInvitation, InvitationResponse, InviteByEmail, IssuedInvitation,
+            NewBoardInvitation, NewPermission, User, INVITATION_ACCEPTED, INVITATION_DECLINED,
+            INVITATION_EXPIRED, INVITATION_PENDING, INVITATION_REVOKED, ROLE_VIEWER,
+        },
+        schema::{self, board_invitations::dsl::*},
+    },
+    access::{continue_if_allowed, continue_if_can_assign, Capability},
+    auth::user_for_token,
+    users::{extract_bearer, hash_token, Error},
+};
+use actix_web::{
+    error,
+    web::{Data, HttpRequest, HttpResponse, Json, Path},
+    Scope as ActixScope,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    dsl::update,
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    result::Error as DieselError,
+    sql_types::Text,
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult,
+    RunQueryDsl,
+};
+
+/// The number of days that an invitation can be accepted for after it has been sent.
+const INVITATION_LIFETIME_DAYS: i64 = 7;
+
+/// What invitation token signatures are made for.
+const INVITATION_PURPOSE: &str = "board-invitation";
+
+sql_function!(fn lower(x: Text) -> Text);
+
+/// Constructs an actix service group for the invitations endpoint.
+pub fn build_service_group() -> ActixScope {
+    ActixScope::new("/invitations")
+        .service(my_invitations)
+        .service(accept_invitation)
+        .service(decline_invitation)
+}
+
+/// Gets the message that an invitation's token signs. Binding the signature to the row's contents
+/// means that a token can't be used for an invitation that has been tampered with.
+fn signed_contents(board: i32, invitee: &str, expiry: NaiveDateTime, nonce: &str) -> String {
+    format!("{}:{}:{}:{}", board, invitee, expiry.timestamp(), nonce)
+}
+
+/// Generates a new, signed invitation token of the form `nonce.signature`.
+fn issue_token(key: &SecretKey, board: i32, invitee: &str, expiry: NaiveDateTime) -> String {
+    let nonce = random_token();
+    let sig = key.sign(
+        INVITATION_PURPOSE,
+        &signed_contents(board, invitee, expiry, &nonce),
+    );
+
+    format!("{}.{}", nonce, sig)
+}
+
+/// Gets the pending invitation with the given ID, returning a 404 if the token wasn't issued for
+/// it, or if the invitation has already been responded to, revoked, or has expired.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the invitation will be read with
+/// * `key` - The key that the token was signed with
+/// * `invitation_uid` - The ID of the invitation
+/// * `token` - The token provided by the invitee
+fn find_pending_invitation(
+    conn: &PgConnection,
+    key: &SecretKey,
+    invitation_uid: i32,
+    token: &str,
+) -> Result<BoardInvitation, Error> {
+    let not_found = || {
+        Error(error::ErrorNotFound(
+            "The provided token does not match a pending invitation.",
+        ))
+    };
+
+    // Tokens are made up of a nonce, and a signature over the invitation
+    let mut parts = token.splitn(2, '.');
+    let (nonce, sig) = match (parts.next(), parts.next()) {
+        (Some(nonce), Some(sig)) => (nonce, sig),
+        _ => return Err(not_found()),
+    };
+
+    // Only hashes of tokens are stored, so look the invitation up by the token's hash
+    let invitation: BoardInvitation = board_invitations
+        .find(invitation_uid)
+        .filter(token_hash.eq(hash_token(token)))
+        .first(conn)
+        .map_err(|_| not_found())?;
+
+    // Make sure that the token was issued by us, for this exact invitation
+    if !key.verify(
+        INVITATION_PURPOSE,
+        &signed_contents(
+            invitation.board_id,
+            &invitation.email,
+            invitation.expires_at,
+            nonce,
+        ),
+        sig,
+    ) {
+        return Err(not_found());
+    }
+
+    // The invitation can only be used once, and only until it expires
+    if invitation.status != INVITATION_PENDING || invitation.expires_at <= Utc::now().naive_utc() {
+        return Err(not_found());
+    }
+
+    Ok(invitation)
+}
+
+/// Turns an invitation into a permission for the given user, and marks it as accepted. If the
+/// user already has access to the board, their existing permission is left alone. This should be
+/// called inside a transaction.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the permission will be written with
+/// * `invitation` - The pending invitation that will be accepted
+/// * `invitee` - The user that accepted the invitation
+fn convert_invitation(
+    conn: &PgConnection,
+    invitation: &BoardInvitation,
+    invitee: &User,
+) -> QueryResult<()> {
+    // Make sure that nobody else has responded to the invitation in the meantime
+    if update(board_invitations.filter(id.eq(invitation.id).and(status.eq(INVITATION_PENDING))))
+        .set(status.eq(INVITATION_ACCEPTED))
+        .execute(conn)?
+        == 0
+    {
+        return Err(DieselError::NotFound);
+    }
+
+    diesel::insert_into(schema::permissions::table)
+        .values(&NewPermission {
+            user_id: invitee.id,
+            board_id: invitation.board_id,
+            role: invitation.role,
+            updated_by: Some(invitation.inviter_id),
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Accepts each of the pending, unexpired invitations that were sent to the given user's email
+/// address. Called whenever a user logs in, so that invitations sent before the invitee had an
+/// account take effect as soon as they sign up.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the invitations will be accepted with
+/// * `invitee` - The user that has just logged in
+pub(crate) fn claim_invitations(conn: &PgConnection, invitee: &User) -> QueryResult<usize> {
+    conn.transaction::<_, DieselError, _>(|| {
+        let pending: Vec<BoardInvitation> = board_invitations
+            .filter(
+                status
+                    .eq(INVITATION_PENDING)
+                    .and(expires_at.gt(Utc::now().naive_utc()))
+                    .and(lower(email).eq(invitee.email.to_lowercase())),
+            )
+            .get_results(conn)?;
+
+        for invitation in &pending {
+            convert_invitation(conn, invitation, invitee)?;
+        }
+
+        Ok(pending.len())
+    })
+}
+
+/// Invites someone to a board by their email address. Only the owner and admins of the board may
+/// invite other users. The returned token should be sent to the invitee; they can use it to accept
+/// or decline the invitation, or simply log in with an account using the same email address.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the invitation token will be signed with
+/// * `board_uid` - The ID of the requested board
+/// * `invite` - A JSON request detailing who to invite, and what they may do
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/{board_id}/invitations")]
+pub async fn invite_by_email(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    board_uid: Path<i32>,
+    invite: Json<InviteByEmail>,
+    req: HttpRequest,
+) -> Result<Json<IssuedInvitation>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to manage the board's members
+    let actor_role = continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::ManageMembers,
+    )?
+    .unwrap_or(ROLE_VIEWER);
+
+    // Make sure that the requesting user is allowed to hand out the requested role
+    continue_if_can_assign(actor_role, None, Some(invite.role))?;
+
+    // Emails are matched case-insensitively, so store them in a consistent form
+    let invitee = invite.email.trim().to_lowercase();
+    if !invitee.contains('@') {
+        return Err(Error(error::ErrorBadRequest(format!(
+            "'{}' is not a valid email address.",
+            invite.email
+        ))));
+    }
+
+    // Sign a token for the invitation. Postgres stores timestamps with microsecond precision, so
+    // truncate the expiry to whole seconds to keep the signed contents the same after a round trip.
+    let expiry = NaiveDateTime::from_timestamp(
+        (Utc::now().naive_utc() + Duration::days(INVITATION_LIFETIME_DAYS)).timestamp(),
+        0,
+    );
+    let invitation_token = issue_token(&key, *board_uid, &invitee, expiry);
+
+    let issued: Option<BoardInvitation> = conn.transaction::<_, DieselError, _>(|| {
+        // An invitation that ran out can be replaced by a new one
+        update(
+            board_invitations.filter(
+                board_id
+                    .eq(*board_uid)
+                    .and(lower(email).eq(&invitee))
+                    .and(status.eq(INVITATION_PENDING))
+                    .and(expires_at.le(Utc::now().naive_utc())),
+            ),
+        )
+        .set(status.eq(INVITATION_EXPIRED))
+        .execute(&conn)?;
+
+        // Put the invitation in the database, unless the email has already been invited
+        diesel::insert_into(board_invitations)
+            .values(&NewBoardInvitation {
+                board_id: *board_uid,
+                inviter_id: matching_user.id,
+                email: &invitee,
+                token_hash: &hash_token(&invitation_token),
+                expires_at: expiry,
+                role: invite.role,
+            })
+            .on_conflict_do_nothing()
+            .get_result(&conn)
+            .optional()
+    })?;
+
+    match issued {
+        Some(invitation) => Ok(Json(IssuedInvitation {
+            invitation,
+            token: invitation_token,
+        })),
+        None => Err(Error(error::ErrorConflict(format!(
+            "'{}' already has a pending invitation to this board.",
+            invitee
+        )))),
+    }
+}
+
+/// Gets a list of the pending invitations to the board, including any that have run out without
+/// being replaced (so that they can still be revoked). Only the owner and admins of the board may
+/// view its invitations.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("/{board_id}/invitations")]
+pub async fn all_invitations(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    req: HttpRequest,
+) -> Result<Json<Vec<BoardInvitation>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to manage the board's members
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::ManageMembers,
+    )?;
+
+    // Return each of the board's pending invitations
+    Ok(Json(
+        board_invitations
+            .filter(board_id.eq(*board_uid).and(status.eq(INVITATION_PENDING)))
+            .order(id.asc())
+            .get_results(&conn)?,
+    ))
+}
+
+/// Withdraws a pending invitation to a board. Only the owner and admins of the board may revoke its
+/// invitations.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `context` - The ID of the requested board, followed by the ID of the invitation
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/{board_id}/invitations/{invitation_id}")]
+pub async fn revoke_invitation(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    context: Path<(i32, i32)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to manage the board's members
+    continue_if_allowed(
+        &conn,
+        context.0,
+        Some(&matching_user),
+        Capability::ManageMembers,
+    )?;
+
+    // Only pending invitations to this board can be revoked
+    let revoked = update(
+        board_invitations.filter(
+            id.eq(context.1)
+                .and(board_id.eq(context.0))
+                .and(status.eq(INVITATION_PENDING)),
+        ),
+    )
+    .set(status.eq(INVITATION_REVOKED))
+    .execute(&conn)?;
+
+    if revoked == 0 {
+        return Err(Error(error::ErrorNotFound(format!(
+            "No pending invitation with the id '{}' exists for this board.",
+            context.1
+        ))));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Gets a list of the pending invitations that were sent to the authenticated user's email
+/// address.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("")]
+pub async fn my_invitations(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<Json<Vec<BoardInvitation>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Return each of the invitations sent to the user's email
+    Ok(Json(
+        board_invitations
+            .filter(
+                status
+                    .eq(INVITATION_PENDING)
+                    .and(expires_at.gt(Utc::now().naive_utc()))
+                    .and(lower(email).eq(matching_user.email.to_lowercase())),
+            )
+            .order(id.asc())
+            .get_results(&conn)?,
+    ))
+}
+
+/// Accepts an invitation, granting the authenticated user access to the board. The invitation
+/// must have been sent to the user's email address.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the invitation token was signed with
+/// * `invitation_uid` - The ID of the invitation
+/// * `response` - The token that was issued for the invitation
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/{invitation_id}/accept")]
+pub async fn accept_invitation(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    invitation_uid: Path<i32>,
+    response: Json<InvitationResponse>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Get the invitation that the token was issued for
+    let invitation = find_pending_invitation(&conn, &key, *invitation_uid, &response.token)?;
+
+    // Invitations can't be forwarded to somebody else
+    if invitation.email.to_lowercase() != matching_user.email.to_lowercase() {
+        return Err(Error(error::ErrorForbidden(
+            "This invitation was sent to a different email address.",
+        )));
+    }
+
+    conn.transaction::<_, DieselError, _>(|| {
+        convert_invitation(&conn, &invitation, &matching_user)
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Declines an invitation. Anybody holding the token may decline it, so that invitees without an
+/// account can turn an invitation down.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the invitation token was signed with
+/// * `invitation_uid` - The ID of the invitation
+/// * `response` - The token that was issued for the invitation
+#[post("/{invitation_id}/decline")]
+pub async fn decline_invitation(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    invitation_uid: Path<i32>,
+    response: Json<InvitationResponse>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the invitation that the token was issued for
+    let invitation = find_pending_invitation(&conn, &key, *invitation_uid, &response.token)?;
+
+    update(board_invitations.find(invitation.id))
+        .set(status.eq(INVITATION_DECLINED))
+        .execute(&conn)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,516 @@
+use super::{
+    super::schema::{boards, notes, permissions, users},
+    users::Error,
+};
+use actix_web::{error, http::header::LINK, web::HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::{pg::Pg, ExpressionMethods, PgTextExpressionMethods, QueryDsl};
+use serde::{Deserialize, Serialize};
+
+/// The number of items on each page of a list when the caller doesn't ask for a specific amount.
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// The largest number of items that a single page of a list can contain.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// The format that timestamps are written in inside of cursors (precise to the microsecond, like
+/// postgres).
+const CURSOR_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+/// A row loaded by `paginated!`: the value being listed, followed by the ID and timestamps of the
+/// row that it came from, which are used to make the cursor of the next page.
+pub type Keyed<T> = (T, i32, NaiveDateTime, NaiveDateTime);
+
+/// The column that a list of boards, notes, permissions or users is sorted by.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// The order that the items were created in (the default)
+    Id,
+
+    /// When each item was created
+    CreatedAt,
+
+    /// When each item was last changed
+    UpdatedAt,
+}
+
+impl Default for SortKey {
+    fn default() -> Self {
+        Self::Id
+    }
+}
+
+impl SortKey {
+    /// Gets the name of the key, as it appears in query strings.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::CreatedAt => "created_at",
+            Self::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+/// The direction that a list is sorted in.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Oldest first (the default)
+    Asc,
+
+    /// Newest first
+    Desc,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self::Asc
+    }
+}
+
+impl SortOrder {
+    /// Gets the name of the order, as it appears in query strings.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
+}
+
+/// How the results of a list endpoint are sorted (e.g. ?sort=updated_at&order=desc).
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+pub struct Sort {
+    /// The column that the list is sorted by
+    #[serde(default)]
+    pub sort: SortKey,
+
+    /// The direction that the list is sorted in
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// Which page of a list is returned (e.g. ?limit=50&cursor=...).
+#[derive(Deserialize, Default, Debug)]
+pub struct Page {
+    /// The maximum number of items to return
+    pub limit: Option<i64>,
+
+    /// The cursor from the `Link` header of the previous page, if this isn't the first page
+    pub cursor: Option<String>,
+}
+
+impl Page {
+    /// Gets the number of items that the page should contain, keeping it within reason.
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .max(1)
+            .min(MAX_PAGE_SIZE)
+    }
+
+    /// Gets the position in the list that the page starts after, if this isn't the first page.
+    ///
+    /// # Arguments
+    ///
+    /// * `sort` - How the list is sorted (must be the same as when the cursor was made)
+    pub fn after(&self, sort: Sort) -> Result<Option<Cursor>, Error> {
+        self.cursor
+            .as_ref()
+            .map(|cursor| {
+                Cursor::decode(cursor, sort).ok_or_else(|| {
+                    Error(error::ErrorBadRequest(
+                        "The provided cursor is invalid, or belongs to a list that was sorted \
+                         differently.",
+                    ))
+                })
+            })
+            .transpose()
+    }
+}
+
+/// A position in a sorted list: the sort key and ID of the last item on the previous page.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cursor {
+    /// The value of the sort key of the last item (unused when sorting by ID)
+    pub at: NaiveDateTime,
+
+    /// The ID of the last item
+    pub id: i32,
+}
+
+impl Cursor {
+    /// Encodes the cursor, along with how the list is sorted, as an opaque string.
+    ///
+    /// # Arguments
+    ///
+    /// * `sort` - How the list is sorted
+    pub fn encode(&self, sort: Sort) -> String {
+        base64::encode_config(
+            format!(
+                "{} {} {} {}",
+                sort.sort.name(),
+                sort.order.name(),
+                self.at.format(CURSOR_TIME_FORMAT),
+                self.id
+            ),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// Decodes a cursor made by `encode`. Returns `None` if the cursor is malformed, or was made
+    /// for a list that was sorted differently.
+    ///
+    /// # Arguments
+    ///
+    /// * `cursor` - The encoded cursor
+    /// * `sort` - How the list is sorted
+    pub fn decode(cursor: &str, sort: Sort) -> Option<Self> {
+        let decoded =
+            String::from_utf8(base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?).ok()?;
+        let mut parts = decoded.split(' ');
+
+        // Positions in one ordering mean nothing in another
+        if parts.next()? != sort.sort.name() || parts.next()? != sort.order.name() {
+            return None;
+        }
+
+        let at = NaiveDateTime::parse_from_str(parts.next()?, CURSOR_TIME_FORMAT).ok()?;
+        let id = parts.next()?.parse().ok()?;
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self { at, id })
+    }
+}
+
+/// Filters that can be applied when listing boards (e.g. ?title_contains=roadmap).
+#[derive(Deserialize, Default, Debug)]
+pub struct BoardFilter {
+    /// Only include boards whose titles contain the given text (case-insensitive)
+    pub title_contains: Option<String>,
+
+    /// Only include boards that have changed since the given time
+    pub updated_since: Option<DateTime<Utc>>,
+}
+
+impl BoardFilter {
+    /// Narrows down a query on the boards table to the boards matching the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query that will be filtered
+    pub fn apply<'a>(&self, mut query: boards::BoxedQuery<'a, Pg>) -> boards::BoxedQuery<'a, Pg> {
+        if let Some(ref text) = self.title_contains {
+            query = query.filter(boards::title.ilike(contains_pattern(text)));
+        }
+
+        if let Some(since) = self.updated_since {
+            query = query.filter(boards::updated_at.ge(since.naive_utc()));
+        }
+
+        query
+    }
+}
+
+/// Filters that can be applied when listing notes (e.g. ?author=4&updated_since=...).
+#[derive(Deserialize, Default, Debug)]
+pub struct NoteFilter {
+    /// Only include notes on the board with the given ID
+    pub board_id: Option<i32>,
+
+    /// Only include notes written by the user with the given ID
+    pub author: Option<i32>,
+
+    /// Only include notes whose titles contain the given text (case-insensitive)
+    pub title_contains: Option<String>,
+
+    /// Only include notes that have changed since the given time
+    pub updated_since: Option<DateTime<Utc>>,
+}
+
+impl NoteFilter {
+    /// Narrows down a query on the notes table to the notes matching the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query that will be filtered
+    pub fn apply<'a>(&self, mut query: notes::BoxedQuery<'a, Pg>) -> notes::BoxedQuery<'a, Pg> {
+        if let Some(board) = self.board_id {
+            query = query.filter(notes::board_id.eq(board));
+        }
+
+        if let Some(author) = self.author {
+            query = query.filter(notes::user_id.eq(author));
+        }
+
+        if let Some(ref text) = self.title_contains {
+            query = query.filter(notes::title.ilike(contains_pattern(text)));
+        }
+
+        if let Some(since) = self.updated_since {
+            query = query.filter(notes::updated_at.ge(since.naive_utc()));
+        }
+
+        query
+    }
+}
+
+/// Filters that can be applied when listing users, or the members of a board (e.g.
+/// ?updated_since=2020-05-27T00:00:00Z).
+#[derive(Deserialize, Default, Debug)]
+pub struct UserFilter {
+    /// Only include users (or memberships) that have changed since the given time
+    pub updated_since: Option<DateTime<Utc>>,
+}
+
+impl UserFilter {
+    /// Narrows down a query on the users table to the users matching the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query that will be filtered
+    pub fn apply<'a>(&self, mut query: users::BoxedQuery<'a, Pg>) -> users::BoxedQuery<'a, Pg> {
+        if let Some(since) = self.updated_since {
+            query = query.filter(users::updated_at.ge(since.naive_utc()));
+        }
+
+        query
+    }
+
+    /// Narrows down a query on the permissions table to the memberships matching the filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query that will be filtered
+    pub fn apply_to_members<'a>(
+        &self,
+        mut query: permissions::BoxedQuery<'a, Pg>,
+    ) -> permissions::BoxedQuery<'a, Pg> {
+        if let Some(since) = self.updated_since {
+            query = query.filter(permissions::updated_at.ge(since.naive_utc()));
+        }
+
+        query
+    }
+}
+
+/// Makes a LIKE pattern matching any text that contains the given text.
+fn contains_pattern(text: &str) -> String {
+    // Wildcards in the text should be matched literally
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+/// Builds a 200 response containing a page of a list loaded by `paginated!`. If there are more
+/// items after the page, a `Link` header pointing to the next page is included.
+///
+/// # Arguments
+///
+/// * `req` - The HTTP request that asked for the page
+/// * `rows` - The rows loaded by `paginated!`
+/// * `sort` - How the list is sorted
+/// * `page` - The page that was requested
+pub fn page_response<T: Serialize>(
+    req: &HttpRequest,
+    mut rows: Vec<Keyed<T>>,
+    sort: Sort,
+    page: &Page,
+) -> HttpResponse {
+    // One more row than was asked for is loaded, so any extra row means there's another page
+    let limit = page.limit() as usize;
+    let next = if rows.len() > limit {
+        rows.truncate(limit);
+        rows.last().map(|(_, id, created_at, updated_at)| {
+            Cursor {
+                at: match sort.sort {
+                    SortKey::CreatedAt => *created_at,
+                    _ => *updated_at,
+                },
+                id: *id,
+            }
+            .encode(sort)
+        })
+    } else {
+        None
+    };
+
+    let items: Vec<T> = rows.into_iter().map(|(item, _, _, _)| item).collect();
+
+    let mut resp = HttpResponse::Ok();
+    if let Some(cursor) = next {
+        resp.header(
+            LINK,
+            format!("<{}>; rel=\"next\"", next_page_url(req, &cursor)),
+        );
+    }
+
+    resp.json(items)
+}
+
+/// Gets the URL of the page after the requested one, which keeps every query parameter of the
+/// request except for its cursor.
+fn next_page_url(req: &HttpRequest, cursor: &str) -> String {
+    // Cursors are URL-safe base64, so they don't need to be escaped
+    let mut params: Vec<&str> = req
+        .query_string()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+        .collect();
+    let cursor_param = format!("cursor={}", cursor);
+    params.push(&cursor_param);
+
+    format!("{}?{}", req.path(), params.join("&"))
+}
+
+/// Orders a boxed query on the given table (boards, notes, permissions or users) as requested by
+/// a `Sort`. Ties are broken by ID, so that the order is always stable.
+///
+/// # Arguments
+///
+/// * `query` - The boxed query that will be sorted
+/// * `table` - The name of the table in the schema that the query selects from
+/// * `sort` - How the query should be sorted
+#[macro_export]
+macro_rules! sorted {
+    ($query:expr, $table:ident, $sort:expr) => {{
+        let query = $query;
+        let sort: $crate::api::listing::Sort = $sort;
+
+        // Imported in their own block, so that they can't change what the arguments refer to
+        {
+            use diesel::{ExpressionMethods, QueryDsl};
+            use $crate::{
+                api::listing::{SortKey, SortOrder},
+                schema::$table,
+            };
+
+            match (sort.sort, sort.order) {
+                (SortKey::Id, SortOrder::Asc) => query.order($table::id.asc()),
+                (SortKey::Id, SortOrder::Desc) => query.order($table::id.desc()),
+                (SortKey::CreatedAt, SortOrder::Asc) => {
+                    query.order(($table::created_at.asc(), $table::id.asc()))
+                }
+                (SortKey::CreatedAt, SortOrder::Desc) => {
+                    query.order(($table::created_at.desc(), $table::id.desc()))
+                }
+                (SortKey::UpdatedAt, SortOrder::Asc) => {
+                    query.order(($table::updated_at.asc(), $table::id.asc()))
+                }
+                (SortKey::UpdatedAt, SortOrder::Desc) => {
+                    query.order(($table::updated_at.desc(), $table::id.desc()))
+                }
+            }
+        }
+    }};
+}
+
+/// Loads a page of a sorted list from a boxed query on the given table (boards, notes,
+/// permissions or users). Rows are loaded as `Keyed` values, with one more row than the page can
+/// hold, so that `page_response` can tell whether there's another page. Pages are found by the
+/// position of the previous page's last row rather than by an offset, so they stay consistent
+/// while the list changes, and are just as fast to load at the end of a long list.
+///
+/// # Arguments
+///
+/// * `query` - The boxed query that will be paginated
+/// * `table` - The name of the table in the schema that the query selects from
+/// * `value` - The column that is listed (e.g. the table's ID)
+/// * `sort` - How the query should be sorted
+/// * `page` - The page that was requested
+#[macro_export]
+macro_rules! paginated {
+    ($query:expr, $table:ident, $value:expr, $sort:expr, $page:expr) => {{
+        let query = $query;
+        let value = $value;
+        let sort: $crate::api::listing::Sort = $sort;
+        let page: &$crate::api::listing::Page = $page;
+
+        match page.after(sort) {
+            Ok(after) => {
+                // Imported in their own block, so that they can't change what the arguments
+                // refer to
+                use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+                use $crate::{
+                    api::listing::{SortKey, SortOrder},
+                    schema::$table,
+                };
+
+                let query =
+                    query.select((value, $table::id, $table::created_at, $table::updated_at));
+
+                // Skip to the row after the previous page's last row
+                let query = match after {
+                    None => query,
+                    Some(after) => match (sort.sort, sort.order) {
+                        (SortKey::Id, SortOrder::Asc) => query.filter($table::id.gt(after.id)),
+                        (SortKey::Id, SortOrder::Desc) => query.filter($table::id.lt(after.id)),
+                        (SortKey::CreatedAt, SortOrder::Asc) => query.filter(
+                            $table::created_at
+                                .gt(after.at)
+                                .or($table::created_at.eq(after.at).and($table::id.gt(after.id))),
+                        ),
+                        (SortKey::CreatedAt, SortOrder::Desc) => query.filter(
+                            $table::created_at
+                                .lt(after.at)
+                                .or($table::created_at.eq(after.at).and($table::id.lt(after.id))),
+                        ),
+                        (SortKey::UpdatedAt, SortOrder::Asc) => query.filter(
+                            $table::updated_at
+                                .gt(after.at)
+                                .or($table::updated_at.eq(after.at).and($table::id.gt(after.id))),
+                        ),
+                        (SortKey::UpdatedAt, SortOrder::Desc) => query.filter(
+                            $table::updated_at
+                                .lt(after.at)
+                                .or($table::updated_at.eq(after.at).and($table::id.lt(after.id))),
+                        ),
+                    },
+                };
+
+                Ok($crate::sorted!(query, $table, sort).limit(page.limit() + 1))
+            }
+            Err(e) => Err(e),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let sort = Sort {
+            sort: SortKey::UpdatedAt,
+            order: SortOrder::Desc,
+        };
+        let cursor = Cursor {
+            at: NaiveDateTime::from_timestamp(1_590_573_600, 123_456_000),
+            id: 42,
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode(sort), sort), Some(cursor));
+
+        // Cursors can't be reused with a different ordering
+        let reversed = Sort {
+            order: SortOrder::Asc,
+            ..sort
+        };
+        assert_eq!(Cursor::decode(&cursor.encode(sort), reversed), None);
+        assert_eq!(Cursor::decode("not a cursor", sort), None);
+    }
+
+    #[test]
+    fn test_contains_pattern_escapes_wildcards() {
+        assert_eq!(contains_pattern("todo"), "%todo%");
+        assert_eq!(contains_pattern("100%_done\\"), "%100\\%\\_done\\\\%");
+    }
+}
@@ -0,0 +1,789 @@
+use super::{
+    super::{
+        crdt::{CharId, Op, Text, TooManyPending},
+        diff::{diff, Change},
+        models::{Note, UpdateNote, User},
+        schema::notes::dsl::notes,
+    },
+    access::{role_allows, role_on, Capability},
+    auth::user_for_token,
+    notes::{continue_if_can_write_note, write_note},
+    users::{extract_bearer, Error},
+};
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, CloseCode, CloseReason, Codec, Frame, Message};
+use actix_web::{
+    error,
+    web::{self, Data, HttpRequest, Path, Payload},
+    Error as ActixError, HttpResponse,
+};
+use bytes::BytesMut;
+use diesel::{
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// How often the merged contents of each live note are written back to the database.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The number of times in a row that saving a live note can fail before the session is ended.
+const MAX_SAVE_FAILURES: u32 = 6;
+
+/// A message sent by a client connected to a live note.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// A batch of edits made by the client
+    Ops {
+        /// The edits, in the order that they were made
+        ops: Vec<Op>,
+    },
+
+    /// The client's cursor moved
+    Cursor {
+        /// The ID of the character that the cursor is placed after (none for the start of the
+        /// note)
+        position: Option<CharId>,
+    },
+}
+
+/// A message sent to the clients connected to a live note.
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The full state of the note. Sent when a client connects.
+    Sync {
+        /// The site ID that the receiving client must use for its edits
+        site: u32,
+
+        /// The version of the note that the state is based on
+        version: i32,
+
+        /// The operations that reproduce the note's current contents
+        ops: Vec<Op>,
+
+        /// Each of the other clients connected to the note
+        peers: Vec<Presence>,
+    },
+
+    /// A batch of edits made by another client, or by the server (when the note is changed
+    /// outside of the live session)
+    Ops {
+        /// The site ID of the client that made the edits (0 for the server)
+        site: u32,
+
+        /// The edits, in the order that they were made
+        ops: Vec<Op>,
+    },
+
+    /// Another client connected, or moved its cursor
+    Presence(Presence),
+
+    /// Another client disconnected
+    Leave {
+        /// The site ID of the client that disconnected
+        site: u32,
+
+        /// The ID of the user that disconnected
+        user_id: i32,
+    },
+}
+
+/// The presence of a client connected to a live note.
+#[derive(Serialize, Clone)]
+pub struct Presence {
+    /// The site ID of the client
+    pub site: u32,
+
+    /// The ID of the user using the client
+    pub user_id: i32,
+
+    /// The ID of the character that the client's cursor is placed after
+    pub position: Option<CharId>,
+}
+
+/// A client connected to a live note.
+struct Peer {
+    /// The ID of the user using the client
+    user_id: i32,
+
+    /// The ID of the character that the client's cursor is placed after
+    position: Option<CharId>,
+
+    /// A channel to the client's websocket
+    sender: UnboundedSender<Message>,
+}
+
+/// The shared state of a note that is being edited live.
+struct Room {
+    /// The merged contents of the note
+    doc: Text,
+
+    /// The version of the note in the database that `doc` is based on
+    version: i32,
+
+    /// The characters of the note as it is stored in the database at `version`, which outside
+    /// changes to the note are merged relative to
+    base: Vec<(CharId, char)>,
+
+    /// Whether or not `doc` has changed since it was last written to the database
+    dirty: bool,
+
+    /// The number of batches of edits that have been applied to `doc`, used to tell whether the
+    /// note was edited while it was being saved
+    edits: u64,
+
+    /// The ID of the user that made the most recent edit
+    last_editor: i32,
+
+    /// The site ID that will be handed to the next client to connect (0 is the server)
+    next_site: u32,
+
+    /// Each of the connected clients, by site ID
+    peers: HashMap<u32, Peer>,
+}
+
+impl Room {
+    /// Sends a message to every connected client, except for the client with the given site ID.
+    fn broadcast(&self, msg: &ServerMessage, except: Option<u32>) {
+        // Encode the message once for every client
+        let encoded = match serde_json::to_string(msg) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                error!("Failed to encode a live note message: {}", e);
+
+                return;
+            }
+        };
+
+        for (site, peer) in &self.peers {
+            if Some(*site) != except {
+                // A failure here just means that the client is disconnecting
+                let _ = peer.sender.unbounded_send(Message::Text(encoded.clone()));
+            }
+        }
+    }
+
+    /// Sends the full state of the note to the client with the given site ID.
+    fn sync(&self, site: u32) {
+        if let Some(peer) = self.peers.get(&site) {
+            let msg = ServerMessage::Sync {
+                site,
+                version: self.version,
+                ops: self.doc.ops(),
+                peers: self.presences(Some(site)),
+            };
+
+            if let Ok(encoded) = serde_json::to_string(&msg) {
+                let _ = peer.sender.unbounded_send(Message::Text(encoded));
+            }
+        }
+    }
+
+    /// Merges a change made to the note outside of the live session (e.g. through a PATCH) into
+    /// `doc`, keeping any live edits that haven't been saved yet, and sends the resulting edits to
+    /// every client.
+    ///
+    /// # Arguments
+    ///
+    /// * `stored` - The note as it is now stored in the database
+    fn merge_outside_change(&mut self, stored: &Note) {
+        let old: Vec<char> = self.base.iter().map(|(_, value)| *value).collect();
+        let new: Vec<char> = stored.body.chars().collect();
+
+        let mut ops: Vec<Op> = Vec::new();
+        let mut base: Vec<(CharId, char)> = Vec::with_capacity(new.len());
+        let mut old_chars = self.base.iter();
+        let mut after: Option<CharId> = None;
+
+        // Replay the outside change character by character, relative to the stored characters
+        // that it was made to
+        for change in diff(&old, &new) {
+            match change {
+                Change::Equal(_) => {
+                    if let Some(&(id, value)) = old_chars.next() {
+                        base.push((id, value));
+                        after = Some(id);
+                    }
+                }
+                Change::Delete(_) => {
+                    if let Some(&(id, _)) = old_chars.next() {
+                        ops.extend(self.doc.remove(&[id]));
+                    }
+                }
+                Change::Insert(value) => {
+                    let inserted = self.doc.insert_after(after, &value.to_string());
+
+                    if let Some(Op::Insert { id, .. }) = inserted.first() {
+                        base.push((*id, value));
+                        after = Some(*id);
+                    }
+
+                    ops.extend(inserted);
+                }
+            }
+        }
+
+        self.base = base;
+        self.version = stored.version;
+
+        // Any live edits that weren't saved before the change still need to be
+        self.dirty = self.doc.to_string() != stored.body;
+
+        if !ops.is_empty() {
+            self.broadcast(&ServerMessage::Ops { site: 0, ops }, None);
+        }
+    }
+
+    /// Handles a message sent by the client with the given site ID. Returns false if the client
+    /// misbehaved badly enough that it should be disconnected.
+    fn handle(&mut self, site: u32, msg: ClientMessage) -> bool {
+        let user_id = match self.peers.get(&site) {
+            Some(peer) => peer.user_id,
+            None => return true,
+        };
+
+        match msg {
+            ClientMessage::Ops { ops } => {
+                // Drop any edits that no honest client could have made, and disconnect clients
+                // that keep sending edits to characters that never arrive
+                let mut applied: Vec<Op> = Vec::with_capacity(ops.len());
+                for op in ops {
+                    match self.doc.apply_from(site, op.clone()) {
+                        Ok(true) => applied.push(op),
+                        Ok(false) => debug!("Ignoring invalid live note edit from site {}", site),
+                        Err(TooManyPending) => return false,
+                    }
+                }
+                let ops = applied;
+
+                if !ops.is_empty() {
+                    self.dirty = true;
+                    self.edits += 1;
+                    self.last_editor = user_id;
+
+                    // Forward the edits to everyone else
+                    self.broadcast(&ServerMessage::Ops { site, ops }, Some(site));
+                }
+            }
+
+            ClientMessage::Cursor { position } => {
+                if let Some(peer) = self.peers.get_mut(&site) {
+                    peer.position = position;
+                }
+
+                self.broadcast(
+                    &ServerMessage::Presence(Presence {
+                        site,
+                        user_id,
+                        position,
+                    }),
+                    Some(site),
+                );
+            }
+        }
+
+        true
+    }
+
+    /// Disconnects the client with the given site ID from the note.
+    fn leave(&mut self, site: u32) {
+        // Let everyone else know that the client is gone
+        if let Some(peer) = self.peers.remove(&site) {
+            self.broadcast(
+                &ServerMessage::Leave {
+                    site,
+                    user_id: peer.user_id,
+                },
+                None,
+            );
+        }
+    }
+
+    /// Disconnects each of the clients belonging to the given users, telling them why.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The IDs of the users whose clients will be disconnected
+    /// * `code` - The close code sent to each client
+    /// * `description` - Why the clients were disconnected
+    fn kick(&mut self, user_ids: &[i32], code: CloseCode, description: &str) {
+        let sites: Vec<u32> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| user_ids.contains(&peer.user_id))
+            .map(|(site, _)| *site)
+            .collect();
+
+        for site in sites {
+            if let Some(peer) = self.peers.get(&site) {
+                let _ = peer.sender.unbounded_send(Message::Close(Some(CloseReason {
+                    code,
+                    description: Some(description.to_owned()),
+                })));
+            }
+
+            self.leave(site);
+        }
+    }
+
+    /// Gets the presence of each connected client, except for the client with the given site ID.
+    fn presences(&self, except: Option<u32>) -> Vec<Presence> {
+        self.peers
+            .iter()
+            .filter(|(site, _)| Some(**site) != except)
+            .map(|(site, peer)| Presence {
+                site: *site,
+                user_id: peer.user_id,
+                position: peer.position,
+            })
+            .collect()
+    }
+}
+
+/// Every note that is currently being edited live, shared between each of the server's workers.
+#[derive(Default)]
+pub struct LiveNotes {
+    /// The state of each live note, by note ID
+    rooms: Mutex<HashMap<i32, Arc<Mutex<Room>>>>,
+}
+
+impl LiveNotes {
+    /// Connects a new client to the given note, starting a live session for the note if there
+    /// isn't one already. Returns the site ID assigned to the client, along with the session.
+    fn join(
+        live: &Data<LiveNotes>,
+        pool: &Data<Pool<ConnectionManager<PgConnection>>>,
+        note: &Note,
+        user_id: i32,
+        sender: UnboundedSender<Message>,
+    ) -> (u32, Arc<Mutex<Room>>) {
+        let mut rooms = live.rooms.lock().unwrap();
+
+        // Start a new session from the note's stored contents if nobody is editing it yet
+        let room = rooms
+            .entry(note.id)
+            .or_insert_with(|| {
+                // Periodically write the session back to the database until everyone leaves
+                actix_rt::spawn(persist_periodically(live.clone(), pool.clone(), note.id));
+
+                let doc = Text::with_text(0, &note.body);
+
+                Arc::new(Mutex::new(Room {
+                    base: doc.visible(),
+                    doc,
+                    version: note.version,
+                    dirty: false,
+                    edits: 0,
+                    last_editor: user_id,
+                    next_site: 1,
+                    peers: HashMap::new(),
+                }))
+            })
+            .clone();
+
+        let shared = room.clone();
+        let mut room = room.lock().unwrap();
+
+        // Hand the client a site ID that nobody else in the session has used
+        let site = room.next_site;
+        room.next_site += 1;
+
+        room.peers.insert(
+            site,
+            Peer {
+                user_id,
+                position: None,
+                sender,
+            },
+        );
+
+        // Give the client the current state of the note, and let everyone else know it's here
+        room.sync(site);
+        room.broadcast(
+            &ServerMessage::Presence(Presence {
+                site,
+                user_id,
+                position: None,
+            }),
+            Some(site),
+        );
+
+        (site, shared)
+    }
+
+    /// Ends the given live session early, disconnecting every client with the given reason. Any
+    /// edits that haven't been saved are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `note_uid` - The ID of the note
+    /// * `room` - The session that will be ended
+    /// * `code` - The close code sent to each client
+    /// * `description` - Why the session was ended
+    fn close(&self, note_uid: i32, room: &Arc<Mutex<Room>>, code: CloseCode, description: &str) {
+        let mut rooms = self.rooms.lock().unwrap();
+
+        // Clients that join from now on get a fresh session
+        if rooms
+            .get(&note_uid)
+            .map_or(false, |current| Arc::ptr_eq(current, room))
+        {
+            rooms.remove(&note_uid);
+        }
+
+        let mut room = room.lock().unwrap();
+        let user_ids: Vec<i32> = room.peers.values().map(|peer| peer.user_id).collect();
+
+        room.kick(&user_ids, code, description);
+        room.dirty = false;
+    }
+}
+
+/// The result of writing a snapshot of a live note back to the database.
+enum Saved {
+    /// The snapshot hadn't changed since the note was last saved
+    Unchanged,
+
+    /// The snapshot was written, producing the given version of the note
+    Written(i32),
+
+    /// The note was changed outside of the live session, so the snapshot wasn't written
+    Outside(Note),
+
+    /// The note has been deleted
+    Deleted,
+}
+
+/// Writes the merged contents of a live note back to the database every `PERSIST_INTERVAL`, and
+/// ends the live session once every client has disconnected and the contents have been saved.
+///
+/// The session is only locked while it is being read or updated. The database is written to on
+/// a blocking thread in between, so that a slow write never holds up the session's clients (or
+/// any other session on the same worker).
+///
+/// If the note was changed outside of the live session (e.g. through a PATCH) since it was last
+/// saved, the outside change is merged into the session as a set of edits, so that neither the
+/// outside change nor any unsaved live edits are lost.
+///
+/// Clients whose users can no longer write to the note are disconnected. The whole session is
+/// ended if the note is deleted, or if it can't be saved `MAX_SAVE_FAILURES` times in a row.
+async fn persist_periodically(
+    live: Data<LiveNotes>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    note_uid: i32,
+) {
+    let mut interval = actix_rt::time::interval(PERSIST_INTERVAL);
+    let mut failures = 0;
+
+    loop {
+        interval.tick().await;
+
+        // Stop once the session is over
+        let room = {
+            let mut rooms = live.rooms.lock().unwrap();
+
+            let room = match rooms.get(&note_uid) {
+                Some(room) => room.clone(),
+                None => return,
+            };
+
+            // Everyone has left, and there is nothing left to save
+            {
+                let r = room.lock().unwrap();
+                if r.peers.is_empty() && !r.dirty {
+                    rooms.remove(&note_uid);
+
+                    return;
+                }
+            }
+
+            room
+        };
+
+        // Take a snapshot of the session to save
+        let (version, snapshot, edits, editor, writers) = {
+            let r = room.lock().unwrap();
+
+            let mut writers: Vec<i32> = r.peers.values().map(|peer| peer.user_id).collect();
+            writers.sort();
+            writers.dedup();
+
+            (
+                r.version,
+                if r.dirty { Some(r.doc.visible()) } else { None },
+                r.edits,
+                r.last_editor,
+                writers,
+            )
+        };
+        let body: Option<String> = snapshot
+            .as_ref()
+            .map(|chars| chars.iter().map(|(_, value)| value).collect());
+
+        let conn_pool = pool.clone();
+        let saved = web::block(move || {
+            save(&conn_pool, note_uid, version, body, editor, &writers)
+                .map_err(|e| Into::<ActixError>::into(e).to_string())
+        })
+        .await;
+
+        let (saved, revoked) = match saved {
+            Ok(result) => {
+                failures = 0;
+
+                result
+            }
+
+            // Give up on the session if the database stays unreachable, rather than trying forever
+            Err(e) => {
+                failures += 1;
+
+                if failures < MAX_SAVE_FAILURES {
+                    warn!("Failed to save live note (id: {}): {}", note_uid, e);
+                } else {
+                    error!(
+                        "Ending the session of live note (id: {}) after {} failed saves: {}",
+                        note_uid, failures, e
+                    );
+
+                    live.close(
+                        note_uid,
+                        &room,
+                        CloseCode::Error,
+                        "The note couldn't be saved.",
+                    );
+
+                    return;
+                }
+
+                continue;
+            }
+        };
+
+        // Nobody can keep editing a note that no longer exists
+        if let Saved::Deleted = saved {
+            live.close(
+                note_uid,
+                &room,
+                CloseCode::Away,
+                "The note has been deleted.",
+            );
+
+            return;
+        }
+
+        let mut room = room.lock().unwrap();
+
+        // Users that can no longer write to the note have to leave the session
+        room.kick(
+            &revoked,
+            CloseCode::Policy,
+            "You can no longer write to this note.",
+        );
+
+        match saved {
+            Saved::Unchanged | Saved::Deleted => (),
+            Saved::Written(written_version) => {
+                room.version = written_version;
+                room.base = snapshot.unwrap_or_default();
+
+                // Anything edited while the snapshot was being written still needs to be saved
+                room.dirty = room.edits != edits;
+            }
+            Saved::Outside(stored) => room.merge_outside_change(&stored),
+        }
+    }
+}
+
+/// Writes a snapshot of a live note's contents back to the database, unless the note has been
+/// changed outside of the live session since the version that the snapshot is based on. Also
+/// returns which of the connected users can no longer write to the note. Blocks until the
+/// database responds.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `note_uid` - The ID of the note
+/// * `version` - The version of the note that the snapshot is based on
+/// * `body` - The contents of the snapshot, if they have changed since the note was last saved
+/// * `editor` - The ID of the user that made the most recent edit
+/// * `writers` - The IDs of the users connected to the session
+fn save(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    note_uid: i32,
+    version: i32,
+    body: Option<String>,
+    editor: i32,
+    writers: &[i32],
+) -> Result<(Saved, Vec<i32>), Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note as it is currently stored
+    let stored: Note = match notes.find(note_uid).first(&conn).optional()? {
+        Some(n) => n,
+        None => return Ok((Saved::Deleted, Vec::new())),
+    };
+
+    // Find everyone whose write access was taken away since they joined
+    let mut revoked: Vec<i32> = Vec::new();
+    for &usr_id in writers {
+        match role_on(&conn, stored.board_id, usr_id)? {
+            Some(role) if role_allows(role, Capability::Write) => (),
+            _ => revoked.push(usr_id),
+        }
+    }
+
+    // Someone changed the note outside of the live session, so their change must be merged first
+    if stored.version != version {
+        return Ok((Saved::Outside(stored), revoked));
+    }
+
+    let body = match body {
+        Some(body) => body,
+        None => return Ok((Saved::Unchanged, revoked)),
+    };
+
+    // Merge the session's contents into the stored note
+    let final_note: Note = UpdateNote {
+        user_id: None,
+        board_id: None,
+        title: None,
+        body: Some(body),
+    }
+    .new_note(stored, editor);
+
+    let written_note = write_note(&conn, version, &final_note, editor)?;
+
+    Ok((Saved::Written(written_note.version), revoked))
+}
+
+/// Opens a websocket for editing a note together with everyone else editing it. Only a user with
+/// write permissions on the note's parent board may connect.
+///
+/// Messages are JSON text frames. Clients send `ClientMessage`s containing edits to their local
+/// replica of the note (see `crdt::Text`) and their cursor position, and receive
+/// `ServerMessage`s containing everyone else's edits and presence. The merged note is
+/// periodically saved back to the database.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `live` - Every note that is currently being edited live
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `note_id` - The unique identifier assigned to the note that the user wishes to edit
+/// * `payload` - The stream of data sent by the client after the websocket is opened
+#[get("/{note_id}/live")]
+pub async fn live_note(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    live: Data<LiveNotes>,
+    req: HttpRequest,
+    note_id: Path<i32>,
+    mut payload: Payload,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the note from the database, and return a 404 if it doesn't exist
+    let matching_note: Note = match notes.find(*note_id).first(&conn) {
+        Ok(n) => Ok(n),
+        Err(_) => Err(Error(error::ErrorNotFound(format!(
+            "The requested note (id: {}) does not exist.",
+            *note_id
+        )))),
+    }?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the user can write to the board that the note belongs to
+    continue_if_can_write_note(&conn, &matching_note, &matching_user)?;
+
+    // Make sure that the client is actually asking for a websocket
+    let mut response = ws::handshake(req.head()).map_err(|e| Error(e.into()))?;
+
+    // Connect the client to the note's live session
+    let (sender, receiver) = mpsc::unbounded::<Message>();
+    let (site, room) = LiveNotes::join(
+        &live,
+        &pool,
+        &matching_note,
+        matching_user.id,
+        sender.clone(),
+    );
+
+    // Read messages from the client until it disconnects
+    actix_rt::spawn(async move {
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+
+        'read: while let Some(Ok(chunk)) = payload.next().await {
+            buf.extend_from_slice(&chunk);
+
+            // Handle every complete frame that has been received so far
+            loop {
+                match codec.decode(&mut buf) {
+                    Ok(Some(Frame::Text(text))) => {
+                        match serde_json::from_slice::<ClientMessage>(&text) {
+                            Ok(msg) => {
+                                if !room.lock().unwrap().handle(site, msg) {
+                                    let _ =
+                                        sender.unbounded_send(Message::Close(Some(CloseReason {
+                                            code: CloseCode::Policy,
+                                            description: Some(
+                                                "Too many edits are waiting for characters that \
+                                                 don't exist."
+                                                    .to_owned(),
+                                            ),
+                                        })));
+
+                                    break 'read;
+                                }
+                            }
+                            Err(e) => debug!("Ignoring malformed live note message: {}", e),
+                        }
+                    }
+                    Ok(Some(Frame::Ping(data))) => {
+                        let _ = sender.unbounded_send(Message::Pong(data));
+                    }
+                    Ok(Some(Frame::Close(reason))) => {
+                        let _ = sender.unbounded_send(Message::Close(reason));
+
+                        break 'read;
+                    }
+                    Ok(Some(_)) => (),
+                    Ok(None) => break,
+                    Err(_) => break 'read,
+                }
+            }
+        }
+
+        room.lock().unwrap().leave(site);
+    });
+
+    // Send messages to the client until it leaves the session
+    let mut codec = Codec::new();
+    Ok(response.streaming(receiver.map(move |msg| {
+        let mut buf = BytesMut::new();
+        codec
+            .encode(msg, &mut buf)
+            .map(|_| buf.freeze())
+            .map_err(ActixError::from)
+    })))
+}
@@ -1,6 +1,22 @@
+pub mod access;
+pub mod auth;
 pub mod boards;
+pub mod cookies;
+pub mod etag;
+pub mod identities;
+pub mod invitations;
+pub mod listing;
+pub mod live;
 pub mod notes;
 pub mod oauth;
+pub mod oidc;
+pub mod passwords;
+pub mod providers;
+pub mod revalidation;
+pub mod search;
 pub mod server;
+pub mod tokens;
+pub mod transfers;
+pub mod two_factor;
 pub mod users;
 pub mod wrapper;
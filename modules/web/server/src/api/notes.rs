@@ -1,20 +1,31 @@
 use super::{
     super::{
-        models::{NewNote, Note, Permission, UpdateNote, User},
-        schema::{self, notes::dsl::*, users::dsl::*},
+        diff::diff_lines,
+        models::{
+            NewComment, NewNote, NewNoteComment, NewNoteRevision, Note, NoteComment, NoteDiff,
+            NoteRevision, UpdateNote, User,
+        },
+        schema::{self, notes::dsl::*},
     },
-    users::{extract_bearer, hash_token, Error},
+    access::{continue_if_allowed, Capability},
+    auth::user_for_token,
+    etag::{continue_if_matches, precondition_failed, respond_with_tag, tagged_response},
+    live,
+    tokens::continue_if_token_covers,
+    users::{extract_bearer, extract_optional_user, Error},
 };
 use actix_web::{
     error,
-    web::{Data, HttpRequest, Json, Path},
+    web::{Data, HttpRequest, HttpResponse, Json, Path},
     Scope as ActixScope,
 };
 use diesel::{
-    dsl::{exists, select, update},
+    dsl::{delete, update},
     pg::PgConnection,
     r2d2::{ConnectionManager, Pool},
-    BelongingToDsl, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl,
+    result::Error as DieselError,
+    BelongingToDsl, BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    QueryDsl, QueryResult, RunQueryDsl,
 };
 
 /// Constructs an actix service group for the notes endpoint.
@@ -22,7 +33,143 @@ pub fn build_service_group() -> ActixScope {
     ActixScope::new("/notes")
         .service(specific_note)
         .service(update_specific_note)
+        .service(delete_specific_note)
         .service(new_note)
+        .service(note_revisions)
+        .service(note_revision_diff)
+        .service(specific_note_revision)
+        .service(restore_note_revision)
+        .service(note_comments)
+        .service(new_note_comment)
+        .service(delete_note_comment)
+        .service(live::live_note)
+}
+
+/// Ensures that the user, who may be anonymous, is able to read the board that the given note is
+/// part of.
+pub(crate) fn continue_if_can_read_note(
+    conn: &PgConnection,
+    note: &Note,
+    user: Option<&User>,
+) -> Result<(), Error> {
+    continue_if_allowed(conn, note.board_id, user, Capability::Read).map(|_| ())
+}
+
+/// Ensures that the user is able to write to the board that the given note is part of.
+pub(crate) fn continue_if_can_write_note(
+    conn: &PgConnection,
+    note: &Note,
+    user: &User,
+) -> Result<(), Error> {
+    continue_if_allowed(conn, note.board_id, Some(user), Capability::Write).map(|_| ())
+}
+
+/// Records the current contents of the given note as its newest revision. This should be called
+/// inside the same transaction as whatever write produced the note. The note is locked until the
+/// transaction ends, so that concurrent writers can't both claim the same revision number.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the revision will be written with
+/// * `note` - The note, as it exists in the database after the write
+/// * `editor` - The ID of the user that made the change
+pub(crate) fn record_revision(
+    conn: &PgConnection,
+    note: &Note,
+    editor: i32,
+) -> QueryResult<NoteRevision> {
+    // Hold on to the note, so that nobody else records a revision of it in the meantime
+    notes
+        .find(note.id)
+        .select(schema::notes::id)
+        .for_update()
+        .first::<i32>(conn)?;
+
+    // Get the number of the note's newest revision, if there is one
+    let latest: Option<i32> = schema::note_revisions::table
+        .filter(schema::note_revisions::note_id.eq(note.id))
+        .select(schema::note_revisions::revision)
+        .order(schema::note_revisions::revision.desc())
+        .first(conn)
+        .optional()?;
+
+    // Put the next revision into the database
+    diesel::insert_into(schema::note_revisions::table)
+        .values(&NewNoteRevision {
+            note_id: note.id,
+            revision: latest.unwrap_or(0) + 1,
+            user_id: editor,
+            title: &note.title,
+            body: &note.body,
+        })
+        .get_result(conn)
+}
+
+/// Writes the given note over the note with the same ID in the database, as long as the stored
+/// note is still at the expected version, and records the new contents as a revision. If someone
+/// else wrote to the note in the meantime, a 412 containing the current copy is returned instead.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the note will be written with
+/// * `expected_version` - The version of the note that the update was based on
+/// * `final_note` - The note that will be written to the database
+/// * `editor` - The ID of the user that made the change
+pub(crate) fn write_note(
+    conn: &PgConnection,
+    expected_version: i32,
+    final_note: &Note,
+    editor: i32,
+) -> Result<Note, Error> {
+    // Only update the note if nobody else has updated it since it was read
+    let written_note: Option<Note> = conn.transaction::<_, DieselError, _>(|| {
+        let written_note: Option<Note> = update(
+            notes.filter(
+                schema::notes::id
+                    .eq(final_note.id)
+                    .and(schema::notes::version.eq(expected_version)),
+            ),
+        )
+        .set(final_note)
+        .get_result(conn)
+        .optional()?;
+
+        // Keep a copy of the note's new contents in its history
+        if let Some(ref n) = written_note {
+            record_revision(conn, n, editor)?;
+        }
+
+        Ok(written_note)
+    })?;
+
+    match written_note {
+        Some(n) => Ok(n),
+
+        // The note was changed out from under us, so give the caller the current copy
+        None => {
+            let current: Note = notes.find(final_note.id).first(conn)?;
+
+            Err(precondition_failed(&current, current.version))
+        }
+    }
+}
+
+/// Gets a specific revision of a note, returning a 404 if it doesn't exist.
+fn find_revision(conn: &PgConnection, note_uid: i32, rev: i32) -> Result<NoteRevision, Error> {
+    match schema::note_revisions::table
+        .filter(
+            schema::note_revisions::note_id
+                .eq(note_uid)
+                .and(schema::note_revisions::revision.eq(rev)),
+        )
+        .first(conn)
+    {
+        Ok(r) => Ok(r),
+        Err(_) => Err(Error(error::ErrorNotFound(format!(
+            "The requested revision (note: {}, revision: {}) does not exist.",
+            note_uid, rev
+        )))),
+    }
 }
 
 /// Gets a specific note from the database.
@@ -31,14 +178,14 @@ pub fn build_service_group() -> ActixScope {
 ///
 /// * `pool` - The connection pool that will be used to connect to the postgres database
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
-/// token (required) of the user
+/// token (optional for public boards) of the user
 /// * `note_id` - The unique identifier assigned to the note that the user wishes to read
 #[get("/{note_id}")]
 pub async fn specific_note(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     req: HttpRequest,
     note_id: Path<i32>,
-) -> Result<Json<Note>, Error> {
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel.
     let conn = pool.get()?;
 
@@ -46,31 +193,18 @@ pub async fn specific_note(
     // provided token.
     let matching_note: Note = notes.find(*note_id).first(&conn)?;
 
-    // Get the user's details from the provided token
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(extract_bearer(&req)?)))
-        .first(&conn)?;
-
-    // Ensure that the user is in fact the owner of the note or has the proper permissions to view
-    // the board that the note is part of
-    if matching_note.user_id != matching_user.id
-        && !select(exists(
-            Permission::belonging_to(&matching_user).filter(
-                schema::permissions::board_id
-                    .eq(matching_note.board_id)
-                    .and(schema::permissions::read.eq(true)),
-            ),
-        ))
-        .get_result(&conn)?
-    {
-        return Err(Error(error::ErrorUnauthorized("The provided access token does not match a user with sufficient privileges to read this note.")));
-    }
+    // Get the user's details from the provided token, if there is one
+    let matching_user = extract_optional_user(&conn, &req)?;
+
+    // Ensure that the user is able to view the board that the note is part of
+    continue_if_can_read_note(&conn, &matching_note, matching_user.as_ref())?;
 
-    // Reteurn the note
-    Ok(Json(matching_note))
+    // Reteurn the note, unless the caller's copy is already up to date
+    respond_with_tag(&req, &matching_note, matching_note.version)
 }
 
-/// Updates a specific note.
+/// Updates a specific note. Only a user that can write to the note's parent board may update it.
+/// The author of a note can't be changed.
 ///
 /// # Arguments
 ///
@@ -84,7 +218,7 @@ pub async fn update_specific_note(
     req: HttpRequest,
     note_id: Path<i32>,
     mut updated_note: Json<UpdateNote>,
-) -> Result<Json<Note>, Error> {
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel
     let conn = pool.get()?;
 
@@ -93,20 +227,86 @@ pub async fn update_specific_note(
     let matching_note: Note = notes.find(*note_id).first(&conn)?;
 
     // Get the user's details from the provided token
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(extract_bearer(&req)?)))
-        .first(&conn)?;
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
 
-    // Ensure that the user is in fact the owner of the ntoe
-    if matching_note.user_id != matching_user.id {
-        return Err(Error(error::ErrorUnauthorized("The provided access token does not match a user with sufficient privileges to update this note.")));
+    // Ensure that the user can write to the note's board
+    continue_if_can_write_note(&conn, &matching_note, &matching_user)?;
+
+    // Notes always belong to whoever wrote them
+    if updated_note
+        .user_id
+        .map_or(false, |usr_id| usr_id != matching_note.user_id)
+    {
+        return Err(Error(error::ErrorBadRequest(
+            "The author of a note can't be changed.",
+        )));
+    }
+
+    // Notes can only be moved to boards that the user can write to
+    if let Some(dest) = updated_note.board_id {
+        if dest != matching_note.board_id {
+            continue_if_token_covers(&req, dest)?;
+            continue_if_allowed(&conn, dest, Some(&matching_user), Capability::Write)?;
+        }
     }
 
+    // Make sure that the caller's copy of the note is still the current one
+    continue_if_matches(&req, &matching_note, matching_note.version)?;
+
     // Merge the updated note and the old note, in case the user didn't update some of the fields
-    let final_note: Note = updated_note.new_note(matching_note);
+    let expected_version = matching_note.version;
+    let final_note: Note = updated_note.new_note(matching_note, matching_user.id);
+
+    // Update the note, and return it along with its new ETag
+    let written_note = write_note(&conn, expected_version, &final_note, matching_user.id)?;
+
+    Ok(tagged_response(&written_note, written_note.version))
+}
+
+/// Deletes a specific note. Only a user with write permissions on the note's parent board may
+/// delete it.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `note_id` - The unique identifier assigned to the note that the user wishes to delete
+#[delete("/{note_id}")]
+pub async fn delete_specific_note(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    note_id: Path<i32>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
 
-    // Return the new note after updating whatever is already in the database
-    Ok(Json(update(notes).set(&final_note).get_result(&conn)?))
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the note from the database, and return a 404 if it doesn't exist
+    let matching_note: Note = match notes.find(*note_id).first(&conn) {
+        Ok(n) => Ok(n),
+        Err(_) => Err(Error(error::ErrorNotFound(format!(
+            "The requested note (id: {}) does not exist.",
+            *note_id
+        )))),
+    }?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the user can write to the board that the note belongs to
+    continue_if_can_write_note(&conn, &matching_note, &matching_user)?;
+
+    // Delete the note, along with its history
+    conn.transaction::<_, DieselError, _>(|| {
+        delete(schema::note_revisions::table.filter(schema::note_revisions::note_id.eq(*note_id)))
+            .execute(&conn)?;
+        delete(notes.find(*note_id)).execute(&conn)
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Puts a given note into the working database.
@@ -121,25 +321,344 @@ pub async fn update_specific_note(
 pub async fn new_note(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     req: HttpRequest,
-    note: Json<NewNote>,
+    mut note: Json<NewNote>,
 ) -> Result<Json<Note>, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel
     let conn = pool.get()?;
 
     // Get the user's details from the provided token
-    let matching_user: User = users
-        .filter(schema::users::oauth_token.eq(hash_token(extract_bearer(&req)?)))
-        .first(&conn)?;
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
 
     // Ensure that the user posting the note is actually the user making the request
     if matching_user.id != note.user_id {
         return Err(Error(error::ErrorUnauthorized("The provided access token does not match a user with an ID matching that provided in the request.")));
     }
 
-    // Put the note in the database, and return the JSON-encoded note value
-    Ok(Json(
-        diesel::insert_into(notes)
+    // Ensure that the user is able to write to the board that the note will be posted to
+    continue_if_token_covers(&req, note.board_id)?;
+    continue_if_allowed(
+        &conn,
+        note.board_id,
+        Some(&matching_user),
+        Capability::Write,
+    )?;
+
+    // Remember who created the note
+    note.updated_by = Some(matching_user.id);
+
+    // Put the note in the database along with its first revision, and return the JSON-encoded
+    // note value
+    Ok(Json(conn.transaction::<_, DieselError, _>(|| {
+        let written_note: Note = diesel::insert_into(notes)
             .values(&*note)
+            .get_result(&conn)?;
+        record_revision(&conn, &written_note, matching_user.id)?;
+
+        Ok(written_note)
+    })?))
+}
+
+/// Gets the revision numbers of every recorded revision of a note, oldest first.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `note_id` - The unique identifier assigned to the note that the user wishes to read
+#[get("/{note_id}/revisions")]
+pub async fn note_revisions(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    note_id: Path<i32>,
+) -> Result<Json<Vec<i32>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(*note_id).first(&conn)?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
+
+    // Anyone who can read the note can read its history
+    continue_if_can_read_note(&conn, &matching_note, Some(&matching_user))?;
+
+    // Return the number of each of the note's revisions
+    Ok(Json(
+        NoteRevision::belonging_to(&matching_note)
+            .select(schema::note_revisions::revision)
+            .order(schema::note_revisions::revision.asc())
+            .load::<i32>(&conn)?,
+    ))
+}
+
+/// Gets a specific revision of a note.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `context` - The ID of the note, followed by the number of the requested revision
+#[get("/{note_id}/revisions/{revision}")]
+pub async fn specific_note_revision(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    context: Path<(i32, i32)>,
+) -> Result<Json<NoteRevision>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(context.0).first(&conn)?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
+
+    // Anyone who can read the note can read its history
+    continue_if_can_read_note(&conn, &matching_note, Some(&matching_user))?;
+
+    // Return the revision
+    Ok(Json(find_revision(&conn, context.0, context.1)?))
+}
+
+/// Gets a line-by-line diff between two revisions of a note.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `context` - The ID of the note, followed by the numbers of the two revisions to compare
+#[get("/{note_id}/revisions/{from}/diff/{to}")]
+pub async fn note_revision_diff(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    context: Path<(i32, i32, i32)>,
+) -> Result<Json<NoteDiff>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(context.0).first(&conn)?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
+
+    // Anyone who can read the note can read its history
+    continue_if_can_read_note(&conn, &matching_note, Some(&matching_user))?;
+
+    // Get both of the revisions that will be compared
+    let from = find_revision(&conn, context.0, context.1)?;
+    let to = find_revision(&conn, context.0, context.2)?;
+
+    // Return the differences between the two revisions
+    Ok(Json(NoteDiff {
+        from: from.revision,
+        to: to.revision,
+        title: diff_lines(&from.title, &to.title),
+        body: diff_lines(&from.body, &to.body),
+    }))
+}
+
+/// Restores a note to the contents it had at the given revision. The restored contents are
+/// recorded as a new revision, so the history before the restore is kept.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `context` - The ID of the note, followed by the number of the revision to restore
+#[post("/{note_id}/revisions/{revision}/restore")]
+pub async fn restore_note_revision(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    context: Path<(i32, i32)>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(context.0).first(&conn)?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
+
+    // Restoring a note is an update, so the same rules apply
+    continue_if_can_write_note(&conn, &matching_note, &matching_user)?;
+
+    // Get the revision that the note will be restored to
+    let restored = find_revision(&conn, context.0, context.1)?;
+
+    // Make sure that the caller's copy of the note is still the current one
+    continue_if_matches(&req, &matching_note, matching_note.version)?;
+
+    // Merge the revision's contents into the current note
+    let expected_version = matching_note.version;
+    let final_note: Note = UpdateNote {
+        user_id: None,
+        board_id: None,
+        title: Some(restored.title),
+        body: Some(restored.body),
+    }
+    .new_note(matching_note, matching_user.id);
+
+    // Update the note, and record the restored contents as the newest revision
+    let written_note = write_note(&conn, expected_version, &final_note, matching_user.id)?;
+
+    Ok(tagged_response(&written_note, written_note.version))
+}
+
+/// Gets every comment left on a note, oldest first.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (optional for public boards) of the user
+/// * `note_id` - The unique identifier assigned to the note that the user wishes to read
+#[get("/{note_id}/comments")]
+pub async fn note_comments(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    note_id: Path<i32>,
+) -> Result<Json<Vec<NoteComment>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(*note_id).first(&conn)?;
+
+    // Get the user's details from the provided token, if there is one
+    let matching_user = extract_optional_user(&conn, &req)?;
+
+    // Anyone who can read the note can read its comments
+    continue_if_can_read_note(&conn, &matching_note, matching_user.as_ref())?;
+
+    // Return each of the note's comments
+    Ok(Json(
+        NoteComment::belonging_to(&matching_note)
+            .order((
+                schema::note_comments::created_at.asc(),
+                schema::note_comments::id.asc(),
+            ))
+            .load(&conn)?,
+    ))
+}
+
+/// Leaves a comment on a note. Commenters can comment on the notes of a board without being able
+/// to change them.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `note_id` - The unique identifier assigned to the note that the user wishes to comment on
+/// * `comment` - The comment
+#[post("/{note_id}/comments")]
+pub async fn new_note_comment(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    note_id: Path<i32>,
+    comment: Json<NewComment>,
+) -> Result<Json<NoteComment>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(*note_id).first(&conn)?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
+
+    // Ensure that the user is able to comment on the board that the note is part of
+    continue_if_allowed(
+        &conn,
+        matching_note.board_id,
+        Some(&matching_user),
+        Capability::Comment,
+    )?;
+
+    // Empty comments don't say anything
+    if comment.body.trim().is_empty() {
+        return Err(Error(error::ErrorBadRequest("A comment can't be empty.")));
+    }
+
+    // Put the comment in the database, and return it
+    Ok(Json(
+        diesel::insert_into(schema::note_comments::table)
+            .values(&NewNoteComment {
+                note_id: matching_note.id,
+                user_id: matching_user.id,
+                body: &comment.body,
+            })
             .get_result(&conn)?,
     ))
 }
+
+/// Deletes a comment from a note. Commenters can delete their own comments, and users that can
+/// write to the note's parent board can delete anyone's.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `context` - The ID of the note, followed by the ID of the comment to delete
+#[delete("/{note_id}/comments/{comment_id}")]
+pub async fn delete_note_comment(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    context: Path<(i32, i32)>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the note from the database, then do some authentication checking with the
+    // provided token.
+    let matching_note: Note = notes.find(context.0).first(&conn)?;
+
+    // Get the user's details from the provided token
+    let matching_user: User = user_for_token(&conn, extract_bearer(&req)?)?;
+
+    // Get the comment from the database, and return a 404 if it isn't on the note
+    let comment: NoteComment = match NoteComment::belonging_to(&matching_note)
+        .find(context.1)
+        .first(&conn)
+        .optional()?
+    {
+        Some(c) => c,
+        None => {
+            return Err(Error(error::ErrorNotFound(format!(
+                "The requested comment (id: {}) does not exist on this note.",
+                context.1
+            ))))
+        }
+    };
+
+    // Taking back a comment only needs the role that was needed to leave it
+    let capability = if comment.user_id == matching_user.id {
+        Capability::Comment
+    } else {
+        Capability::Write
+    };
+    continue_if_allowed(
+        &conn,
+        matching_note.board_id,
+        Some(&matching_user),
+        capability,
+    )?;
+
+    delete(schema::note_comments::table.find(comment.id)).execute(&conn)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,33 +1,98 @@
 use super::{
-    super::{models, schema::users::dsl::*},
+    super::{
+        crypto::{random_token, SecretKey},
+        models::{self, LoginOutcome},
+    },
+    auth::{user_agent, user_for_session},
+    identities::resolve_account,
+    invitations::claim_invitations,
+    providers::Provider,
+    revalidation::{save_tokens, ProviderTokens},
     server::OauthConfig,
+    two_factor::finish_login,
+    users::extract_optional_user,
     wrapper,
 };
 use actix_session::Session;
 use actix_web::{
     error, http,
-    web::{Data, Json, Path, Query},
+    web::{Data, HttpRequest, Path, Query},
     Error, HttpResponse, Scope as ActixScope,
 };
 use diesel::{
     pg::PgConnection,
     r2d2::{ConnectionManager, Pool},
-    RunQueryDsl,
 };
 use oauth2::{
     reqwest::http_client, AuthorizationCode, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, Scope,
     TokenResponse,
 };
 use serde::{Deserialize, Serialize};
-use sha3::{Digest, Sha3_256};
 
 /// Constructs an actix service group for the oauth endpoint.     
 pub fn build_service_group() -> ActixScope {
     ActixScope::new("/oauth")
         .service(authenticate)
+        .service(link)
         .service(callback)
 }
 
+/// Generates a pkce challenge, and forwards the user to the given provider's authentication
+/// portal.
+///
+/// # Arguments
+///
+/// * `provider` - The provider that the user will log in with
+/// * `session` - The session that the login's verification state will be stored in
+/// * `link_to` - The ID of the signed-in user that the provider's account will be linked to, if
+/// this login is linking a new account
+fn begin_login(
+    provider: &Provider,
+    session: &Session,
+    link_to: Option<i32>,
+) -> Result<HttpResponse, Error> {
+    // Generate a key exchange challenge that the client must solve
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    // Generate a nonce that the provider's ID token must echo back, so that it can't be
+    // replayed in another login
+    let nonce = random_token();
+
+    // Get an auth URL, with any extra parameters that the provider needs
+    let (auth_url, csrf_state) = provider
+        .auth_params
+        .iter()
+        .fold(
+            provider.scopes.iter().fold(
+                provider.client.authorize_url(CsrfToken::new_random),
+                |req, scope| req.add_scope(Scope::new(scope.clone())),
+            ),
+            |req, (param, value)| req.add_extra_param(param.as_str(), value.as_str()),
+        )
+        .add_extra_param("nonce", nonce.clone())
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    // Put all of the required verification state vars into the redis session
+    // storage db
+    session.set::<String>("state", csrf_state.secret().clone())?;
+    session.set::<PkceCodeVerifier>("verifier", pkce_verifier)?;
+    session.set::<String>("provider", provider.name.clone())?;
+    session.set::<String>("nonce", nonce)?;
+
+    // Remember who the new account should be linked to. A plain login must never inherit this
+    // from an abandoned link.
+    match link_to {
+        Some(usr_id) => session.set::<i32>("link_user", usr_id)?,
+        None => session.remove("link_user"),
+    }
+
+    // Redirect the user to the auth url
+    Ok(HttpResponse::TemporaryRedirect()
+        .header(http::header::LOCATION, auth_url.as_str())
+        .finish())
+}
+
 /// Generates a pkce challenge, and forwards the user to the respective authentication portal.
 #[get("/login/{provider}")]
 pub async fn authenticate(
@@ -35,39 +100,58 @@ pub async fn authenticate(
     data: Data<OauthConfig>,
     session: Session,
 ) -> Result<HttpResponse, Error> {
-    let provider = &*info; // Get the provider from the path
-
     // If the provider is invalid, respond with a bad request code
-    if provider != "google" && provider != "github" {
-        Ok(HttpResponse::BadRequest().finish()) // Respond with a 400
+    if let Some(provider) = data.provider(&*info) {
+        begin_login(provider, &session, None)
     } else {
-        let (client, scopes) = match provider.as_ref() {
-            // If the request asked for a google auth, use the Google API client
-            "google" => (&data.google_api_client, "profile"),
-            // If the request asked for a GitHub auth, use the GitHub API client
-            _ => (&data.github_api_client, "user:email"),
-        }; // Get an appropriate client based on the given OauthConfig
-
-        // Generate a key exchange challenge that the client must solve
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-
-        // Get an auth URL
-        let (auth_url, csrf_state) = client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new(scopes.to_owned()))
-            .set_pkce_challenge(pkce_challenge)
-            .url();
-
-        // Put all of the required verification state vars into the redis session
-        // storage db
-        session.set::<String>("state", csrf_state.secret().clone())?;
-        session.set::<PkceCodeVerifier>("verifier", pkce_verifier)?;
-        session.set::<String>("provider", provider.to_owned())?;
-
-        // Redirect the user to the auth url
-        Ok(HttpResponse::TemporaryRedirect()
-            .header(http::header::LOCATION, auth_url.as_str())
-            .finish())
+        Ok(HttpResponse::BadRequest().finish()) // Respond with a 400
+    }
+}
+
+/// Forwards a signed-in user to the respective authentication portal, so that their account at
+/// the provider can be linked to their Notedly account. The user is identified by their bearer
+/// token, or by the token saved in their session when they last logged in.
+///
+/// # Arguments
+///
+/// * `info` - The name of the provider whose account will be linked
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `data` - The server's oauth configuration
+/// * `session` - The user's session
+/// * `req` - An HTTP request provided by the caller of this method
+#[get("/link/{provider}")]
+pub async fn link(
+    info: Path<String>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    data: Data<OauthConfig>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // If the provider is invalid, respond with a bad request code
+    let provider = match data.provider(&*info) {
+        Some(p) => p,
+        None => return Ok(HttpResponse::BadRequest().finish()), // Respond with a 400
+    };
+
+    // Get a connection from the pool
+    let conn = pool.get().map_err(error::ErrorInternalServerError)?;
+
+    // Find the signed-in user, from either their bearer token or their session
+    let signed_in = match extract_optional_user(&conn, &req).map_err(|e| e.0)? {
+        Some(u) => Some(u),
+        None => match session.get::<String>("token")? {
+            Some(token) => user_for_session(&conn, &token).ok(),
+            None => None,
+        },
+    };
+
+    match signed_in {
+        Some(u) => begin_login(provider, &session, Some(u.id)),
+
+        // Only signed-in users have an account to link to
+        None => Err(error::ErrorUnauthorized(
+            "No applicable bearer token was provided.",
+        )),
     }
 }
 
@@ -81,14 +165,20 @@ pub struct CallbackRequest {
     pub state: String,
 }
 
-/// Authenticates the user with a given authorization code.
+/// Authenticates the user with a given authorization code. The user is logged into the account
+/// that their provider account is linked to, or a new account if it hasn't been linked yet. If
+/// the login was started from /oauth/link, the provider account is linked to the signed-in user
+/// first. Once logged in, the user is redirected to the front-end with their new session token,
+/// or with a two-factor challenge if they have two-factor authentication enabled.
 #[get("/cb")]
 pub async fn callback(
     info: Query<CallbackRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     data: Data<OauthConfig>,
+    key: Data<SecretKey>,
     session: Session,
-) -> Result<Json<models::OwnedUser>, Error> {
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
     // Abort the request if the state has been corrupted
     if info.state
         != session
@@ -100,19 +190,20 @@ pub async fn callback(
             "The state challenge was not completed successfully.",
         ))
     } else {
-        let client = match session
+        let provider_name = session
             .get::<String>("provider")?
-            .unwrap_or_else(|| "".to_owned())
-            .as_ref()
-        {
-            "google" => &data.google_api_client,
-            _ => &data.github_api_client,
-        }; // Get an appropriate client based on the session provider
+            .unwrap_or_else(|| "".to_owned());
+
+        // Get the provider that the user started logging in with
+        let provider = data.provider(&provider_name).ok_or_else(|| {
+            error::ErrorBadRequest("The login was not started with a known provider.")
+        })?;
 
         // Get the pkce code verifier from session storage
         if let Some(verifier) = session.get::<PkceCodeVerifier>("verifier")? {
             // Exchange the authorization code for an access token
-            match client
+            match provider
+                .client
                 .exchange_code(AuthorizationCode::new(info.code.clone()))
                 .set_pkce_verifier(verifier)
                 .request(http_client)
@@ -124,54 +215,98 @@ pub async fn callback(
                             // Get an access token from the response
                             let access_token = response.access_token();
 
-                            // Hash the user's access token
-                            let mut token_hasher = Sha3_256::new();
-                            token_hasher.input(access_token.secret());
+                            let mut user =
+                                wrapper::User::new(access_token.secret().to_owned(), provider); // Generate a new wrapper for the user API from the acess token and provider
+
+                            // OpenID Connect logins must come with an ID token proving who the
+                            // user is
+                            if provider.oidc.is_some() {
+                                let id_token =
+                                    response.extra_fields().id_token.as_ref().ok_or_else(|| {
+                                        error::ErrorUnauthorized(
+                                            "The provider did not return an ID token.",
+                                        )
+                                    })?;
+                                let nonce = session
+                                    .get::<String>("nonce")?
+                                    .unwrap_or_else(|| "".to_owned());
 
-                            let mut user = wrapper::User::new(
-                                access_token.secret().to_owned(),
-                                session
-                                    .get::<String>("provider")?
-                                    .unwrap_or_else(|| "".to_owned()),
-                            ); // Generate a new wrapper for the user API from the acess token and provider
+                                user.verify_identity(id_token, &nonce).await?;
+                            }
 
                             // Get the user's oauth ID
                             let id_oauth = user.oauth_id().await?;
 
-                            // Generate a user with an empty UID (postgres will figure this out)
-                            let schema_user = models::NewUser {
-                                oauth_id: id_oauth,
-                                oauth_token: &hex::encode(token_hasher.result()),
+                            // The user's latest details
+                            let details = models::UpdateUser {
                                 email: user.email().await?,
                             };
 
-                            // Put the new user in the DB
-                            match diesel::insert_into(users)
-                                .values(&schema_user)
-                                .on_conflict(oauth_id)
-                                .do_update()
-                                .set(&models::UpdateUser {
-                                    oauth_token: &schema_user.oauth_token,
-                                    email: &schema_user.email,
-                                })
-                                .execute(&conn)
-                            {
+                            // Get the account that this login is linking to, if any
+                            let link_to = session.get::<i32>("link_user")?;
+                            session.remove("link_user");
+
+                            // Find, link or create the user's account in the DB
+                            match resolve_account(
+                                &conn,
+                                &provider.name,
+                                &id_oauth,
+                                link_to,
+                                &details,
+                            ) {
                                 // The operation was completed successfully, 200
-                                Ok(_) => {
-                                    // Save the token in a session cookie
-                                    session
-                                        .set::<String>("token", access_token.secret().to_owned())?;
-
-                                    // Respond with the user's details
-                                    Ok(Json(models::OwnedUser {
-                                        oauth_id: id_oauth.to_owned(),
-                                        oauth_token: access_token.secret().to_owned(),
-                                        email: schema_user.email.to_owned(),
-                                    }))
+                                Ok((u, identity)) => {
+                                    // Give the user access to any boards they were invited to
+                                    // by email
+                                    claim_invitations(&conn, &u)
+                                        .map_err(error::ErrorInternalServerError)?;
+
+                                    // Keep the provider's tokens, so that we can tell if the user
+                                    // revokes our access later
+                                    save_tokens(
+                                        &conn,
+                                        &key,
+                                        identity.id,
+                                        &ProviderTokens::from_response(&response),
+                                    )
+                                    .map_err(|e| e.0)?;
+
+                                    // Start a new session for the device that the user logged
+                                    // in on, unless they have a second factor to enter first. The
+                                    // provider's tokens never leave the server.
+                                    let fragment = match finish_login(
+                                        &conn,
+                                        &key,
+                                        u.id,
+                                        user_agent(&req),
+                                        Some(identity.id),
+                                    )
+                                    .map_err(|e| e.0)?
+                                    {
+                                        LoginOutcome::Session(issued) => {
+                                            // Save the token in a session cookie
+                                            session.set::<String>("token", issued.token.clone())?;
+
+                                            format!("token={}", issued.token)
+                                        }
+                                        LoginOutcome::Challenge(challenge) => {
+                                            format!("challenge={}", challenge.challenge)
+                                        }
+                                    };
+
+                                    // Send the user back to the front-end, which reads the token
+                                    // or challenge from the fragment (fragments are never sent to
+                                    // servers)
+                                    Ok(HttpResponse::Found()
+                                        .header(
+                                            http::header::LOCATION,
+                                            format!("{}#{}", data.frontend_url(), fragment),
+                                        )
+                                        .finish())
                                 }
 
-                                // Since an error was thrown, return a 300
-                                Err(e) => Err(error::ErrorInternalServerError(e)),
+                                // Return the error in a response
+                                Err(e) => Err(e.0),
                             }
                         }
 
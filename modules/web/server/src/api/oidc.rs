@@ -0,0 +1,521 @@
+use super::providers::FieldMapping;
+use actix_web::{client::Client, error, Error};
+use chrono::Utc;
+use oauth2::{
+    basic::{BasicErrorResponse, BasicTokenType},
+    ExtraTokenFields, StandardTokenResponse,
+};
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+/// The number of seconds of clock skew tolerated between us and the provider when checking when
+/// an ID token was issued and when it expires.
+const CLOCK_SKEW_SECS: i64 = 60;
+
+/// The fields that an OpenID Connect provider adds to its token responses.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdTokenFields {
+    /// The signed JSON web token describing the authenticated user (absent for plain oauth
+    /// providers, like GitHub)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+/// An oauth2 client whose token responses may include an ID token.
+pub type OidcClient = oauth2::Client<
+    BasicErrorResponse,
+    StandardTokenResponse<IdTokenFields, BasicTokenType>,
+    BasicTokenType,
+>;
+
+/// The endpoints and credentials used to verify a user's identity with an OpenID Connect
+/// provider.
+#[derive(Clone, Debug)]
+pub struct OidcProvider {
+    /// The issuer that ID tokens must be issued by
+    pub issuer: String,
+
+    /// The ID that the server is registered with. ID tokens must be intended for it.
+    pub client_id: String,
+
+    /// Where the provider publishes the keys that it signs ID tokens with
+    pub jwks_uri: String,
+
+    /// Where the details of an authenticated user can be fetched from
+    pub userinfo_endpoint: String,
+
+    /// Where the user's details can be found in the provider's userinfo responses
+    pub fields: FieldMapping,
+}
+
+/// A set of JSON web keys, as published by an OpenID Connect provider.
+#[derive(Deserialize)]
+struct JwkSet {
+    /// Each of the keys that the provider may sign ID tokens with
+    keys: Vec<Jwk>,
+}
+
+/// A single JSON web key. Only RSA keys are supported.
+#[derive(Deserialize)]
+struct Jwk {
+    /// The identifier that ID tokens use to refer to the key
+    kid: Option<String>,
+
+    /// The type of the key (e.g. "RSA")
+    kty: String,
+
+    /// The key's modulus (base64url-encoded)
+    #[serde(default)]
+    n: String,
+
+    /// The key's public exponent (base64url-encoded)
+    #[serde(default)]
+    e: String,
+}
+
+/// The header of a JSON web token.
+#[derive(Deserialize)]
+struct JwtHeader {
+    /// The algorithm that the token was signed with
+    alg: String,
+
+    /// The identifier of the key that the token was signed with
+    kid: Option<String>,
+}
+
+/// The audience of an ID token, which may be a single client or a list of clients.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    /// Checks whether or not the token is intended for the given client.
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Self::One(aud) => aud == client_id,
+            Self::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+/// The claims made by a verified ID token.
+#[derive(Deserialize, Debug)]
+pub struct IdTokenClaims {
+    /// The issuer of the token
+    pub iss: String,
+
+    /// The provider's unique identifier for the user
+    pub sub: String,
+
+    /// The client(s) that the token is intended for
+    aud: Audience,
+
+    /// When the token expires (seconds since the unix epoch)
+    pub exp: i64,
+
+    /// When the token was issued (seconds since the unix epoch)
+    pub iat: i64,
+
+    /// The value that ties the token to a specific login attempt
+    pub nonce: Option<String>,
+}
+
+/// A user whose identity has been verified by an OpenID Connect provider.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Identity {
+    /// The provider's unique identifier for the user
+    pub subject: String,
+
+    /// The user's verified email address
+    pub email: String,
+}
+
+/// Generates an error explaining why an ID token was rejected.
+fn invalid_token(reason: &str) -> Error {
+    error::ErrorUnauthorized(format!("The provided ID token is invalid: {}.", reason))
+}
+
+/// Decodes a single base64url-encoded segment of a JSON web token.
+fn decode_segment(segment: &str) -> Option<Vec<u8>> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// Decodes a single base64url-encoded, JSON segment of a JSON web token.
+fn decode_json_segment<T: DeserializeOwned>(segment: &str) -> Option<T> {
+    serde_json::from_slice(&decode_segment(segment)?).ok()
+}
+
+impl OidcProvider {
+    /// Checks that the given ID token was signed by the provider, for this server, during the
+    /// login attempt that used the given nonce, and that it hasn't expired. Returns the token's
+    /// claims.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The HTTP client used to fetch the provider's signing keys
+    /// * `id_token` - The ID token returned alongside the user's access token
+    /// * `nonce` - The nonce that was sent to the provider when the login began
+    pub async fn verify_id_token(
+        &self,
+        client: &Client,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, Error> {
+        // A JSON web token is made up of a header, a payload and a signature
+        let segments: Vec<&str> = id_token.split('.').collect();
+        if segments.len() != 3 {
+            return Err(invalid_token("it is not a JSON web token"));
+        }
+
+        let header: JwtHeader = decode_json_segment(segments[0])
+            .ok_or_else(|| invalid_token("its header is malformed"))?;
+
+        // Providers sign ID tokens with RS256 unless told otherwise
+        if header.alg != "RS256" {
+            return Err(invalid_token("it is not signed with RS256"));
+        }
+
+        // Find the key that the token claims to have been signed with
+        let keys: JwkSet = client
+            .get(self.jwks_uri.as_str())
+            .set_header("User-Agent", "Notedly")
+            .send()
+            .await?
+            .json()
+            .await?;
+        let key = keys
+            .keys
+            .iter()
+            .find(|k| k.kty == "RSA" && (header.kid.is_none() || k.kid == header.kid))
+            .ok_or_else(|| invalid_token("it is signed with an unknown key"))?;
+
+        // Make sure that the token was actually signed by the provider
+        let signature = decode_segment(segments[2])
+            .ok_or_else(|| invalid_token("its signature is malformed"))?;
+        match (decode_segment(&key.n), decode_segment(&key.e)) {
+            (Some(n), Some(e)) => RsaPublicKeyComponents { n, e }
+                .verify(
+                    &RSA_PKCS1_2048_8192_SHA256,
+                    format!("{}.{}", segments[0], segments[1]).as_bytes(),
+                    &signature,
+                )
+                .map_err(|_| invalid_token("its signature does not match"))?,
+            _ => return Err(invalid_token("it is signed with a malformed key")),
+        }
+
+        let claims: IdTokenClaims = decode_json_segment(segments[1])
+            .ok_or_else(|| invalid_token("its claims are malformed"))?;
+
+        // Google leaves the scheme off of the issuer in some of its tokens
+        if claims.iss != self.issuer && format!("https://{}", claims.iss) != self.issuer {
+            return Err(invalid_token("it was issued by another provider"));
+        }
+
+        // The token must have been issued to us, not some other application
+        if !claims.aud.contains(&self.client_id) {
+            return Err(invalid_token("it was issued to another client"));
+        }
+
+        // The token is only good for a limited time
+        let now = Utc::now().timestamp();
+        if claims.exp + CLOCK_SKEW_SECS < now {
+            return Err(invalid_token("it has expired"));
+        }
+        if claims.iat - CLOCK_SKEW_SECS > now {
+            return Err(invalid_token("it was issued in the future"));
+        }
+
+        // The token must belong to this login attempt, so that it can't be replayed
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid_token("it belongs to another login attempt"));
+        }
+
+        Ok(claims)
+    }
+
+    /// Gets the identity of the user that the given access token was issued to, as described by
+    /// the provider's userinfo endpoint. The user must be the same user that the ID token was
+    /// issued for, and must have a verified email address.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The HTTP client used to contact the provider
+    /// * `access_token` - The access token issued to the user
+    /// * `claims` - The claims of the user's verified ID token
+    pub async fn identity(
+        &self,
+        client: &Client,
+        access_token: &str,
+        claims: &IdTokenClaims,
+    ) -> Result<Identity, Error> {
+        // Ask the provider who the access token belongs to
+        let info: Value = client
+            .get(self.userinfo_endpoint.as_str())
+            .set_header("Authorization", format!("Bearer {}", access_token))
+            .set_header("User-Agent", "Notedly")
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        // The userinfo response must describe the same user as the ID token
+        let subject = match self.fields.subject(&info) {
+            Some(sub) if sub == claims.sub => sub,
+            _ => {
+                return Err(error::ErrorUnauthorized(
+                    "The provider's user details do not match the provided ID token.",
+                ))
+            }
+        };
+
+        // Invitations and other features trust the email, so it has to be verified
+        match self.fields.verified_email(&info) {
+            Some(email) => Ok(Identity { subject, email }),
+            None => Err(error::ErrorUnauthorized(
+                "The provider has not verified the user's email address.",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use ring::{
+        rand::SystemRandom,
+        signature::{KeyPair, RsaKeyPair, RSA_PKCS1_SHA256},
+    };
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+
+    /// The client ID that the mock provider issues tokens to.
+    const CLIENT_ID: &str = "notedly-test";
+
+    /// The nonce used by the login attempts in these tests.
+    const NONCE: &str = "some-nonce";
+
+    /// The key that the mock provider signs ID tokens with (a throwaway 2048-bit RSA key).
+    const SIGNING_KEY: &[u8] = include_bytes!("testdata/oidc-test-key.pk8");
+
+    /// Encodes the given bytes as base64url, without padding.
+    fn encode(bytes: &[u8]) -> String {
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Signs an RS256 JSON web token with the mock provider's key.
+    fn sign(header: Value, claims: Value) -> String {
+        let key = RsaKeyPair::from_pkcs8(SIGNING_KEY).unwrap();
+        let msg = format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims.to_string().as_bytes())
+        );
+
+        let mut sig = vec![0; key.public_modulus_len()];
+        key.sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            msg.as_bytes(),
+            &mut sig,
+        )
+        .unwrap();
+
+        format!("{}.{}", msg, encode(&sig))
+    }
+
+    /// Gets a set of claims that the mock provider would make for a valid login.
+    fn valid_claims(issuer: &str) -> Value {
+        let now = Utc::now().timestamp();
+
+        json!({
+            "iss": issuer,
+            "sub": "1234",
+            "aud": CLIENT_ID,
+            "exp": now + 3600,
+            "iat": now,
+            "nonce": NONCE,
+        })
+    }
+
+    /// Gets the header of an ID token signed with the mock provider's key.
+    fn valid_header() -> Value {
+        json!({ "alg": "RS256", "kid": "test-key" })
+    }
+
+    /// Starts a mock OpenID Connect provider on a random local port, returning a description of
+    /// it. The userinfo endpoint describes a different user depending on the access token used.
+    fn start_provider() -> OidcProvider {
+        let key = RsaKeyPair::from_pkcs8(SIGNING_KEY).unwrap();
+        let jwks = json!({
+            "keys": [{
+                "kid": "test-key",
+                "kty": "RSA",
+                "alg": "RS256",
+                "use": "sig",
+                "n": encode(key.public_key().modulus().big_endian_without_leading_zero()),
+                "e": encode(key.public_key().exponent().big_endian_without_leading_zero()),
+            }]
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        HttpServer::new(move || {
+            let jwks = jwks.clone();
+
+            App::new()
+                .route(
+                    "/jwks",
+                    web::get().to(move || {
+                        let jwks = jwks.clone();
+                        async move { HttpResponse::Ok().json(jwks) }
+                    }),
+                )
+                .route(
+                    "/userinfo",
+                    web::get().to(|req: HttpRequest| async move {
+                        let token = req
+                            .headers()
+                            .get("Authorization")
+                            .and_then(|h| h.to_str().ok())
+                            .unwrap_or("");
+
+                        HttpResponse::Ok().json(match token {
+                            "Bearer verified" => json!({
+                                "sub": "1234",
+                                "email": "someone@example.com",
+                                "email_verified": true,
+                            }),
+                            "Bearer unverified" => json!({
+                                "sub": "1234",
+                                "email": "someone@example.com",
+                                "email_verified": false,
+                            }),
+                            _ => json!({
+                                "sub": "5678",
+                                "email": "someone.else@example.com",
+                                "email_verified": true,
+                            }),
+                        })
+                    }),
+                )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+
+        let base = format!("http://{}", addr);
+
+        OidcProvider {
+            issuer: base.clone(),
+            client_id: CLIENT_ID.to_owned(),
+            jwks_uri: format!("{}/jwks", base),
+            userinfo_endpoint: format!("{}/userinfo", base),
+            fields: FieldMapping::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_valid_id_token() {
+        let provider = start_provider();
+        let token = sign(valid_header(), valid_claims(&provider.issuer));
+
+        let claims = provider
+            .verify_id_token(&Client::default(), &token, NONCE)
+            .await
+            .unwrap();
+
+        assert_eq!(claims.sub, "1234");
+    }
+
+    #[actix_rt::test]
+    async fn test_reject_invalid_id_tokens() {
+        let provider = start_provider();
+        let client = Client::default();
+
+        // Each of these tokens is wrong in exactly one way
+        let mut wrong_aud = valid_claims(&provider.issuer);
+        wrong_aud["aud"] = json!("someone-else");
+        let mut wrong_iss = valid_claims(&provider.issuer);
+        wrong_iss["iss"] = json!("https://evil.example.com");
+        let mut expired = valid_claims(&provider.issuer);
+        expired["exp"] = json!(Utc::now().timestamp() - 3600);
+        let mut wrong_nonce = valid_claims(&provider.issuer);
+        wrong_nonce["nonce"] = json!("another-nonce");
+
+        let forged = {
+            let token = sign(valid_header(), valid_claims(&provider.issuer));
+            let segments: Vec<&str> = token.split('.').collect();
+            let mut claims = valid_claims(&provider.issuer);
+            claims["sub"] = json!("5678");
+
+            format!(
+                "{}.{}.{}",
+                segments[0],
+                encode(claims.to_string().as_bytes()),
+                segments[2]
+            )
+        };
+
+        for token in &[
+            sign(valid_header(), wrong_aud),
+            sign(valid_header(), wrong_iss),
+            sign(valid_header(), expired),
+            sign(valid_header(), wrong_nonce),
+            sign(
+                json!({ "alg": "RS256", "kid": "unknown-key" }),
+                valid_claims(&provider.issuer),
+            ),
+            sign(json!({ "alg": "none" }), valid_claims(&provider.issuer)),
+            forged,
+            "not a token".to_owned(),
+        ] {
+            assert!(provider
+                .verify_id_token(&client, token, NONCE)
+                .await
+                .is_err());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_identity() {
+        let provider = start_provider();
+        let client = Client::default();
+        let token = sign(valid_header(), valid_claims(&provider.issuer));
+        let claims = provider
+            .verify_id_token(&client, &token, NONCE)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            provider
+                .identity(&client, "verified", &claims)
+                .await
+                .unwrap(),
+            Identity {
+                subject: "1234".to_owned(),
+                email: "someone@example.com".to_owned(),
+            }
+        );
+
+        // Unverified emails can't be trusted
+        assert!(provider
+            .identity(&client, "unverified", &claims)
+            .await
+            .is_err());
+
+        // The access token must belong to the same user as the ID token
+        assert!(provider
+            .identity(&client, "someone-else", &claims)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,544 @@
+use super::{
+    super::{
+        crypto::{hash_password, verify_password, SecretKey},
+        mail::Mailer,
+        models::{
+            EmailRequest, EmailVerification, LoginOutcome, NewPasswordCredential, NewUser,
+            PasswordCredential, PasswordLogin, PasswordReset, User,
+        },
+        schema::{
+            self, api_tokens::dsl::api_tokens, auth_sessions::dsl::auth_sessions,
+            password_credentials::dsl::password_credentials, users::dsl::users,
+        },
+    },
+    auth::user_agent,
+    invitations::claim_invitations,
+    two_factor::finish_login,
+    users::Error,
+};
+use actix_session::Session;
+use actix_web::{
+    error,
+    web::{self, Data, HttpRequest, HttpResponse, Json},
+    Scope as ActixScope,
+};
+use chrono::{Duration, Utc};
+use diesel::{
+    dsl::{exists, select, update},
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    sql_types::Text,
+    Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+
+/// The fewest characters that a password can have.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// The most characters that a password can have, so that hashing can't be used to tie the server
+/// up.
+const MAX_PASSWORD_LENGTH: usize = 1024;
+
+sql_function!(fn lower(x: Text) -> Text);
+
+/// The configuration of email/password logins, for servers that allow them.
+#[derive(Clone)]
+pub struct PasswordConfig {
+    /// The URL of the front-end, which email verification and password reset links point to
+    frontend_url: String,
+
+    /// Sends email verification and password reset links to users
+    mailer: Mailer,
+}
+
+impl PasswordConfig {
+    /// Initializes a new PasswordConfig.
+    ///
+    /// # Arguments
+    ///
+    /// * `frontend_url` - The URL of the front-end, which emailed links point to
+    /// * `mailer` - Sends email verification and password reset links to users
+    pub fn new(frontend_url: String, mailer: Mailer) -> Self {
+        Self {
+            frontend_url: frontend_url.trim_end_matches('/').to_owned(),
+            mailer,
+        } // Return the new instance
+    }
+}
+
+/// Something that a password credential's emailed tokens can be used for.
+#[derive(Clone, Copy)]
+enum TokenKind {
+    /// Proving that the user owns their email address
+    Verification,
+
+    /// Setting a new password
+    Reset,
+}
+
+impl TokenKind {
+    /// Gets what tokens of this kind are signed for.
+    fn purpose(self) -> &'static str {
+        match self {
+            Self::Verification => "email-verification",
+            Self::Reset => "password-reset",
+        }
+    }
+
+    /// Gets how long tokens of this kind can be used for.
+    fn lifetime(self) -> Duration {
+        match self {
+            Self::Verification => Duration::hours(48),
+            Self::Reset => Duration::hours(1),
+        }
+    }
+
+    /// Gets the part of the credential that tokens of this kind are bound to. Tokens stop working
+    /// once it changes, so a password reset token can only be used once.
+    fn bound_to(self, credential: &PasswordCredential) -> &str {
+        match self {
+            Self::Verification => &credential.email,
+            Self::Reset => &credential.password_hash,
+        }
+    }
+}
+
+/// Constructs an actix service group for the password endpoint.
+pub fn build_service_group() -> ActixScope {
+    ActixScope::new("/password")
+        .service(signup)
+        .service(verify_email)
+        .service(resend_verification)
+        .service(login)
+        .service(request_reset)
+        .service(reset_password)
+}
+
+/// Gets the message that a credential's token signs.
+fn signed_contents(kind: TokenKind, credential: &PasswordCredential, expiry: i64) -> String {
+    format!(
+        "{}:{}:{}",
+        credential.user_id,
+        kind.bound_to(credential),
+        expiry
+    )
+}
+
+/// Generates a new, signed token for a credential, of the form `user_id.expiry.signature`.
+fn issue_token(key: &SecretKey, kind: TokenKind, credential: &PasswordCredential) -> String {
+    let expiry = (Utc::now().naive_utc() + kind.lifetime()).timestamp();
+    let sig = key.sign(kind.purpose(), &signed_contents(kind, credential, expiry));
+
+    format!("{}.{}.{}", credential.user_id, expiry, sig)
+}
+
+/// Gets the credential that the given token was issued for, returning a 404 if the token is
+/// invalid or has expired.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the credential will be read with
+/// * `key` - The key that the token was signed with
+/// * `kind` - What the token should have been issued for
+/// * `token` - The token from the user's email
+fn redeem_token(
+    conn: &PgConnection,
+    key: &SecretKey,
+    kind: TokenKind,
+    token: &str,
+) -> Result<PasswordCredential, Error> {
+    let invalid = || {
+        Error(error::ErrorNotFound(
+            "The provided token is invalid or has expired.",
+        ))
+    };
+
+    // Tokens are made up of the user's ID, an expiry, and a signature over the credential
+    let mut parts = token.splitn(3, '.');
+    let (usr_id, expiry, sig) = match (
+        parts.next().and_then(|part| part.parse::<i32>().ok()),
+        parts.next().and_then(|part| part.parse::<i64>().ok()),
+        parts.next(),
+    ) {
+        (Some(usr_id), Some(expiry), Some(sig)) => (usr_id, expiry, sig),
+        _ => return Err(invalid()),
+    };
+
+    if expiry <= Utc::now().timestamp() {
+        return Err(invalid());
+    }
+
+    let credential: PasswordCredential = password_credentials
+        .find(usr_id)
+        .first(conn)
+        .optional()?
+        .ok_or_else(invalid)?;
+
+    // Make sure that the token was issued by us, for the credential as it is now
+    if !key.verify(
+        kind.purpose(),
+        &signed_contents(kind, &credential, expiry),
+        sig,
+    ) {
+        return Err(invalid());
+    }
+
+    Ok(credential)
+}
+
+/// Gets the credential that logs in with the given email address, if there is one.
+fn find_credential(conn: &PgConnection, email: &str) -> Result<Option<PasswordCredential>, Error> {
+    Ok(password_credentials
+        .filter(lower(schema::password_credentials::email).eq(email.trim().to_lowercase()))
+        .first(conn)
+        .optional()?)
+}
+
+/// Ensures that the given email address could be delivered to.
+fn continue_if_valid_email(email: &str) -> Result<(), Error> {
+    let mut parts = email.rsplitn(2, '@');
+
+    match (parts.next(), parts.next()) {
+        (Some(domain), Some(local))
+            if !domain.is_empty()
+                && !local.is_empty()
+                && email.len() <= 254
+                && !email.contains(|c: char| c.is_whitespace() || c.is_control()) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error(error::ErrorBadRequest(
+            "The provided email address is invalid.",
+        ))),
+    }
+}
+
+/// Ensures that the given password is long enough to be worth having, but not so long that it
+/// takes too long to hash.
+fn continue_if_acceptable_password(password: &str) -> Result<(), Error> {
+    let length = password.chars().count();
+
+    if length < MIN_PASSWORD_LENGTH || length > MAX_PASSWORD_LENGTH {
+        Err(Error(error::ErrorBadRequest(format!(
+            "Passwords must be between {} and {} characters long.",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ))))
+    } else {
+        Ok(())
+    }
+}
+
+/// Hashes a password on the blocking thread pool, since hashing is deliberately slow and would
+/// otherwise hold up every other request on the worker.
+async fn hash_password_off_worker(password: &str) -> Result<String, Error> {
+    let password = password.to_owned();
+
+    web::block(move || hash_password(&password))
+        .await
+        .map_err(|e| Error(e.into()))
+}
+
+/// Sends a plain text email on the blocking thread pool, since the mail command is waited on.
+///
+/// # Arguments
+///
+/// * `config` - The server's password login configuration
+/// * `to` - The address of the recipient
+/// * `subject` - The subject of the email
+/// * `body` - The plain text body of the email
+async fn send_mail(
+    config: &PasswordConfig,
+    to: &str,
+    subject: &'static str,
+    body: String,
+) -> Result<(), Error> {
+    let mailer = config.mailer.clone();
+    let to = to.to_owned();
+
+    web::block(move || mailer.send(&to, subject, &body))
+        .await
+        .map_err(|e| Error(e.into()))
+}
+
+/// Emails a link to the given credential's email address, which the user can verify it with.
+async fn send_verification(
+    config: &PasswordConfig,
+    key: &SecretKey,
+    credential: &PasswordCredential,
+) -> Result<(), Error> {
+    send_mail(
+        config,
+        &credential.email,
+        "Verify your Notedly email address",
+        format!(
+            "Welcome to Notedly! Open this link to verify your email address:\n\n\
+             {}/verify-email#token={}\n\n\
+             The link expires in 48 hours.",
+            config.frontend_url,
+            issue_token(key, TokenKind::Verification, credential)
+        ),
+    )
+    .await
+}
+
+/// Signs a new user up with an email address and password. The user can log in once they've
+/// verified their email address, with the link that is emailed to them.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `config` - The server's password login configuration
+/// * `key` - The key that the verification token will be signed with
+/// * `details` - The new user's email address and password
+#[post("/signup")]
+pub async fn signup(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<PasswordConfig>,
+    key: Data<SecretKey>,
+    details: Json<PasswordLogin>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Make sure that the details are usable
+    let email = details.email.trim();
+    continue_if_valid_email(email)?;
+    continue_if_acceptable_password(&details.password)?;
+
+    let password_hash = hash_password_off_worker(&details.password).await?;
+
+    // Make the account along with its credential
+    let credential = conn.transaction::<_, Error, _>(|| {
+        // Each email address can only belong to one account
+        if select(exists(
+            users.filter(lower(schema::users::email).eq(email.to_lowercase())),
+        ))
+        .get_result(&conn)?
+            || find_credential(&conn, email)?.is_some()
+        {
+            return Err(Error(error::ErrorConflict(
+                "An account with this email address already exists.",
+            )));
+        }
+
+        let u: User = diesel::insert_into(users)
+            .values(&NewUser { email })
+            .get_result(&conn)?;
+
+        Ok(diesel::insert_into(password_credentials)
+            .values(&NewPasswordCredential {
+                user_id: u.id,
+                email,
+                password_hash: &password_hash,
+            })
+            .get_result(&conn)?)
+    })?;
+
+    send_verification(&config, &key, &credential).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Verifies a user's email address with the token that was emailed to it.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the verification token was signed with
+/// * `verification` - The token from the verification email
+#[post("/verify")]
+pub async fn verify_email(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    verification: Json<EmailVerification>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the credential that the token was issued for, and mark it as verified
+    let credential = redeem_token(&conn, &key, TokenKind::Verification, &verification.token)?;
+
+    update(password_credentials.find(credential.user_id))
+        .set(schema::password_credentials::verified.eq(true))
+        .execute(&conn)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Emails a new verification link to an unverified email address. Always succeeds, so that it
+/// can't be used to find out who has an account.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `config` - The server's password login configuration
+/// * `key` - The key that the verification token will be signed with
+/// * `email_req` - The email address that the link will be sent to
+#[post("/verify/resend")]
+pub async fn resend_verification(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<PasswordConfig>,
+    key: Data<SecretKey>,
+    email_req: Json<EmailRequest>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    if let Some(credential) = find_credential(&conn, &email_req.email)? {
+        if !credential.verified {
+            send_verification(&config, &key, &credential).await?;
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Logs a user in with their email address and password, starting the same kind of session as
+/// an oauth login. Users with two-factor authentication enabled are given a challenge instead.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that any two-factor challenge will be signed with
+/// * `session` - The user's cookie session, which the new session token is stored in
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the user agent
+/// of the device that the user is logging in on
+/// * `details` - The user's email address and password
+#[post("/login")]
+pub async fn login(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    session: Session,
+    req: HttpRequest,
+    details: Json<PasswordLogin>,
+) -> Result<Json<LoginOutcome>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Check the password, even if nobody has the email address, so that response times don't
+    // give away who has an account. Hashing is slow, so it's done on the blocking thread pool.
+    let found = find_credential(&conn, &details.email)?;
+    let stored_hash = found.as_ref().map(|c| c.password_hash.clone());
+    let password = details.password.clone();
+
+    let matches = web::block(move || match stored_hash {
+        Some(hash) => Ok(verify_password(&hash, &password)),
+        None => hash_password(&password).map(|_| false),
+    })
+    .await
+    .map_err(|e| Error(e.into()))?;
+
+    let credential = found.filter(|_| matches).ok_or_else(|| {
+        Error(error::ErrorUnauthorized(
+            "The provided email address or password is incorrect.",
+        ))
+    })?;
+
+    // Users have to prove that they own their email address first
+    if !credential.verified {
+        return Err(Error(error::ErrorForbidden(
+            "Verify your email address before logging in.",
+        )));
+    }
+
+    let u: User = users.find(credential.user_id).first(&conn)?;
+
+    // Give the user access to any boards they were invited to by email
+    claim_invitations(&conn, &u)?;
+
+    // Start a new session for the device that the user logged in on, unless they have a second
+    // factor to enter first
+    let outcome = finish_login(&conn, &key, u.id, user_agent(&req), None)?;
+
+    // Save the token in a session cookie
+    if let LoginOutcome::Session(issued) = &outcome {
+        session
+            .set::<String>("token", issued.token.clone())
+            .map_err(Error)?;
+    }
+
+    Ok(Json(outcome))
+}
+
+/// Emails a password reset link to the owner of an email address. Always succeeds, so that it
+/// can't be used to find out who has an account.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `config` - The server's password login configuration
+/// * `key` - The key that the reset token will be signed with
+/// * `email_req` - The email address that the link will be sent to
+#[post("/reset")]
+pub async fn request_reset(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<PasswordConfig>,
+    key: Data<SecretKey>,
+    email_req: Json<EmailRequest>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    if let Some(credential) = find_credential(&conn, &email_req.email)? {
+        send_mail(
+            &config,
+            &credential.email,
+            "Reset your Notedly password",
+            format!(
+                "Open this link to choose a new password:\n\n\
+                 {}/reset-password#token={}\n\n\
+                 The link expires in an hour. If you didn't ask to reset your password, you can \
+                 ignore this email.",
+                config.frontend_url,
+                issue_token(&key, TokenKind::Reset, &credential)
+            ),
+        )
+        .await?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Sets a new password with the token from a password reset email. Every existing session and
+/// personal access token of the user is revoked, in case somebody else had their old password.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the reset token was signed with
+/// * `reset` - The token from the password reset email, and the new password
+#[post("/reset/confirm")]
+pub async fn reset_password(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    reset: Json<PasswordReset>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    continue_if_acceptable_password(&reset.password)?;
+
+    let credential = redeem_token(&conn, &key, TokenKind::Reset, &reset.token)?;
+    let password_hash = hash_password_off_worker(&reset.password).await?;
+
+    conn.transaction::<_, Error, _>(|| {
+        // Receiving the email proves that the user owns the address, too
+        update(password_credentials.find(credential.user_id))
+            .set((
+                schema::password_credentials::password_hash.eq(&password_hash),
+                schema::password_credentials::verified.eq(true),
+                schema::password_credentials::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(&conn)?;
+
+        diesel::delete(auth_sessions.filter(schema::auth_sessions::user_id.eq(credential.user_id)))
+            .execute(&conn)?;
+
+        // Whoever had the old password could have minted tokens with it, too
+        diesel::delete(api_tokens.filter(schema::api_tokens::user_id.eq(credential.user_id)))
+            .execute(&conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
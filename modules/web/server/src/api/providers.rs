@@ -0,0 +1,383 @@
+use super::oidc::{OidcClient, OidcProvider};
+use actix_web::client::Client;
+use oauth2::{AuthUrl, ClientId, ClientSecret, TokenUrl};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, io};
+
+/// Describes where a provider puts each of the details we need in its userinfo responses.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct FieldMapping {
+    /// The field holding the provider's unique identifier for the user
+    pub id: String,
+
+    /// The field holding the user's email address
+    pub email: String,
+
+    /// The field stating whether or not the provider has verified the user's email address. If
+    /// `None`, every email address returned by the provider is trusted.
+    pub email_verified: Option<String>,
+}
+
+impl Default for FieldMapping {
+    /// Maps the standard OpenID Connect claims.
+    fn default() -> Self {
+        Self {
+            id: "sub".to_owned(),
+            email: "email".to_owned(),
+            email_verified: Some("email_verified".to_owned()),
+        } // Return the new instance
+    }
+}
+
+impl FieldMapping {
+    /// Reads the provider's unique identifier for the user from a userinfo response. Numeric IDs
+    /// (e.g. GitHub's) are converted to strings.
+    ///
+    /// # Arguments
+    ///
+    /// * `info` - The userinfo response
+    pub fn subject(&self, info: &Value) -> Option<String> {
+        match info.get(&self.id)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Reads the user's email address from a userinfo response, as long as the provider has
+    /// verified it.
+    ///
+    /// # Arguments
+    ///
+    /// * `info` - The userinfo response
+    pub fn verified_email(&self, info: &Value) -> Option<String> {
+        let email = info.get(&self.email)?.as_str()?;
+
+        match &self.email_verified {
+            // The provider must explicitly say that the email is verified
+            Some(field) if info.get(field).and_then(Value::as_bool) != Some(true) => None,
+            _ => Some(email.to_owned()),
+        }
+    }
+}
+
+/// The configuration of a single oauth provider that users can log in with, as it appears in the
+/// providers file. Any OpenID Connect provider only needs a name, an issuer and its client
+/// credentials; the rest is discovered from the issuer's `.well-known/openid-configuration`:
+///
+/// ```json
+/// [
+///     {
+///         "name": "company",
+///         "issuer": "https://sso.example.com/realms/company",
+///         "client_id": "notedly",
+///         "client_secret": "SOME_SECRET"
+///     },
+///     {
+///         "name": "gitlab",
+///         "issuer": "https://gitlab.com",
+///         "client_id": "SOME_ID",
+///         "client_secret": "SOME_SECRET"
+///     }
+/// ]
+/// ```
+///
+/// Plain oauth2 providers must list their endpoints explicitly, and say where to find the user's
+/// ID and email with `fields`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProviderConfig {
+    /// The name that the provider is referred to by in login URLs (e.g. /oauth/login/{name})
+    pub name: String,
+
+    /// The ID that the server is registered with at the provider
+    pub client_id: String,
+
+    /// The secret that the server authenticates with at the provider, if it has one
+    pub client_secret: Option<String>,
+
+    /// The OpenID Connect issuer of the provider. Providers without an issuer are treated as
+    /// plain oauth2 providers, and don't have to issue ID tokens.
+    pub issuer: Option<String>,
+
+    /// Where users are sent to log in (discovered if absent)
+    pub authorization_endpoint: Option<String>,
+
+    /// Where authorization codes are exchanged for access tokens (discovered if absent)
+    pub token_endpoint: Option<String>,
+
+    /// Where the details of an authenticated user can be fetched from (discovered if absent)
+    pub userinfo_endpoint: Option<String>,
+
+    /// Where the provider publishes the keys that it signs ID tokens with (discovered if absent)
+    pub jwks_uri: Option<String>,
+
+    /// Where a GitHub-style list of the user's emails can be fetched from, for providers that
+    /// don't include a verified email in their userinfo responses
+    pub emails_endpoint: Option<String>,
+
+    /// Where the provider sends users back to after they log in. Defaults to the server's
+    /// /oauth/cb route, under its public URL.
+    pub redirect_url: Option<String>,
+
+    /// The scopes requested when users log in. OpenID Connect providers default to
+    /// "openid email profile".
+    #[serde(default)]
+    pub scopes: Vec<String>,
+
+    /// Where the user's details can be found in the provider's userinfo responses
+    #[serde(default)]
+    pub fields: FieldMapping,
+
+    /// Any extra parameters that the provider needs in its authorization URL (e.g. Google only
+    /// issues refresh tokens with "access_type": "offline")
+    #[serde(default)]
+    pub auth_params: BTreeMap<String, String>,
+}
+
+/// The parts of an OpenID Connect discovery document that we need.
+#[derive(Deserialize)]
+struct DiscoveryDocument {
+    /// The issuer that the document describes
+    issuer: String,
+
+    /// Where users are sent to log in
+    authorization_endpoint: String,
+
+    /// Where authorization codes are exchanged for access tokens
+    token_endpoint: Option<String>,
+
+    /// Where the details of an authenticated user can be fetched from
+    userinfo_endpoint: Option<String>,
+
+    /// Where the provider publishes the keys that it signs ID tokens with
+    jwks_uri: String,
+}
+
+/// An oauth provider that users can log in with, ready to be used.
+#[derive(Clone)]
+pub struct Provider {
+    /// The name that the provider is referred to by
+    pub name: String,
+
+    /// The oauth2 client used to log users in with the provider
+    pub client: OidcClient,
+
+    /// The scopes requested when users log in
+    pub scopes: Vec<String>,
+
+    /// Where the details of an authenticated user can be fetched from
+    pub userinfo_endpoint: String,
+
+    /// Where a GitHub-style list of the user's emails can be fetched from, if anywhere
+    pub emails_endpoint: Option<String>,
+
+    /// Where the provider sends users back to after they log in, if not the default
+    pub redirect_url: Option<String>,
+
+    /// Where the user's details can be found in the provider's userinfo responses
+    pub fields: FieldMapping,
+
+    /// Any extra parameters that the provider needs in its authorization URL
+    pub auth_params: BTreeMap<String, String>,
+
+    /// The provider's ID token verification settings, if it's an OpenID Connect provider
+    pub oidc: Option<OidcProvider>,
+}
+
+/// Generates an error explaining why a provider couldn't be configured.
+fn config_error(provider: &str, reason: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("the oauth provider '{}' {}", provider, reason),
+    )
+}
+
+impl ProviderConfig {
+    /// Initializes a new ProviderConfig for GitHub.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - The GitHub oauth client ID of the server
+    /// * `client_secret` - The GitHub oauth client secret of the server
+    pub fn github(client_id: String, client_secret: String) -> Self {
+        Self {
+            name: "github".to_owned(),
+            client_id,
+            client_secret: Some(client_secret),
+            issuer: None,
+            authorization_endpoint: Some("https://github.com/login/oauth/authorize".to_owned()),
+            token_endpoint: Some("https://github.com/login/oauth/access_token".to_owned()),
+            userinfo_endpoint: Some("https://api.github.com/user".to_owned()),
+            jwks_uri: None,
+            emails_endpoint: Some("https://api.github.com/user/emails".to_owned()),
+            redirect_url: None,
+            scopes: vec!["user:email".to_owned()],
+            fields: FieldMapping {
+                id: "id".to_owned(),
+                email: "email".to_owned(),
+                email_verified: None,
+            },
+            auth_params: BTreeMap::new(),
+        } // Return the new instance
+    }
+
+    /// Initializes a new ProviderConfig for Google.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - The Google oauth client ID of the server
+    /// * `client_secret` - The Google oauth client secret of the server
+    pub fn google(client_id: String, client_secret: String) -> Self {
+        Self {
+            name: "google".to_owned(),
+            client_id,
+            client_secret: Some(client_secret),
+            issuer: Some("https://accounts.google.com".to_owned()),
+            authorization_endpoint: Some("https://accounts.google.com/o/oauth2/v2/auth".to_owned()),
+            token_endpoint: Some("https://oauth2.googleapis.com/token".to_owned()),
+            userinfo_endpoint: Some("https://openidconnect.googleapis.com/v1/userinfo".to_owned()),
+            jwks_uri: Some("https://www.googleapis.com/oauth2/v3/certs".to_owned()),
+            emails_endpoint: None,
+            redirect_url: None,
+            scopes: Vec::new(),
+            fields: FieldMapping::default(),
+            auth_params: vec![("access_type", "offline"), ("prompt", "consent")]
+                .into_iter()
+                .map(|(param, value)| (param.to_owned(), value.to_owned()))
+                .collect(),
+        } // Return the new instance
+    }
+
+    /// Reads a list of provider configurations from the JSON file at the given path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the providers file
+    pub fn from_file(path: &str) -> io::Result<Vec<Self>> {
+        serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Fills in any missing endpoints from the issuer's discovery document.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The HTTP client used to fetch the discovery document
+    async fn discover(&mut self, client: &Client) -> io::Result<()> {
+        let issuer = match &self.issuer {
+            Some(iss) => iss.trim_end_matches('/').to_owned(),
+            None => return Ok(()),
+        };
+
+        // Nothing to do if every endpoint has already been configured
+        if self.authorization_endpoint.is_some()
+            && self.token_endpoint.is_some()
+            && self.userinfo_endpoint.is_some()
+            && self.jwks_uri.is_some()
+        {
+            return Ok(());
+        }
+
+        info!(
+            "Discovering the endpoints of oauth provider '{}'",
+            self.name
+        );
+
+        // Fetch the issuer's discovery document
+        let doc: DiscoveryDocument = client
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .set_header("User-Agent", "Notedly")
+            .send()
+            .await
+            .map_err(|e| config_error(&self.name, format!("could not be discovered: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| config_error(&self.name, format!("could not be discovered: {}", e)))?;
+
+        // A provider can't vouch for another issuer
+        if doc.issuer.trim_end_matches('/') != issuer {
+            return Err(config_error(
+                &self.name,
+                format!("reported an unexpected issuer '{}'", doc.issuer),
+            ));
+        }
+
+        // Only fill in the endpoints that haven't been overridden
+        self.issuer = Some(doc.issuer);
+        self.authorization_endpoint = self
+            .authorization_endpoint
+            .take()
+            .or(Some(doc.authorization_endpoint));
+        self.token_endpoint = self.token_endpoint.take().or(doc.token_endpoint);
+        self.userinfo_endpoint = self.userinfo_endpoint.take().or(doc.userinfo_endpoint);
+        self.jwks_uri = self.jwks_uri.take().or(Some(doc.jwks_uri));
+
+        Ok(())
+    }
+
+    /// Turns the configuration into a usable provider, discovering any missing endpoints.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The HTTP client used to discover the provider's endpoints
+    pub async fn resolve(mut self, client: &Client) -> io::Result<Provider> {
+        self.discover(client).await?;
+
+        // Every provider needs these endpoints, whether configured or discovered
+        let name = self.name.clone();
+        let missing = |endpoint: &str| config_error(&name, format!("has no {}", endpoint));
+        let auth_url = self
+            .authorization_endpoint
+            .ok_or_else(|| missing("authorization_endpoint"))?;
+        let token_url = self
+            .token_endpoint
+            .ok_or_else(|| missing("token_endpoint"))?;
+        let userinfo_endpoint = self
+            .userinfo_endpoint
+            .ok_or_else(|| missing("userinfo_endpoint"))?;
+
+        // OpenID Connect providers need to tell us how to check their ID tokens
+        let oidc = match self.issuer {
+            Some(issuer) => Some(OidcProvider {
+                issuer,
+                client_id: self.client_id.clone(),
+                jwks_uri: self.jwks_uri.ok_or_else(|| missing("jwks_uri"))?,
+                userinfo_endpoint: userinfo_endpoint.clone(),
+                fields: self.fields.clone(),
+            }),
+            None => None,
+        };
+
+        // Ask for the user's identity and email if no other scopes were configured
+        let scopes = if self.scopes.is_empty() && oidc.is_some() {
+            vec![
+                "openid".to_owned(),
+                "email".to_owned(),
+                "profile".to_owned(),
+            ]
+        } else {
+            self.scopes
+        };
+
+        let invalid_url = |e| config_error(&name, format!("has an invalid URL: {}", e));
+
+        Ok(Provider {
+            client: OidcClient::new(
+                ClientId::new(self.client_id),
+                self.client_secret.map(ClientSecret::new),
+                AuthUrl::new(auth_url).map_err(invalid_url)?,
+                Some(TokenUrl::new(token_url).map_err(invalid_url)?),
+            ),
+            name: self.name,
+            scopes,
+            userinfo_endpoint,
+            emails_endpoint: self.emails_endpoint,
+            redirect_url: self.redirect_url,
+            fields: self.fields,
+            auth_params: self.auth_params,
+            oidc,
+        })
+    }
+}
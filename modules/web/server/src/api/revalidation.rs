@@ -0,0 +1,440 @@
+use super::{
+    super::{
+        crypto::SecretKey,
+        models::UserIdentity,
+        schema::{self, auth_sessions::dsl::auth_sessions, user_identities::dsl::user_identities},
+    },
+    providers::Provider,
+    server::OauthConfig,
+    users::Error,
+};
+use actix_web::{client::Client, error::BlockingError, http::StatusCode, web};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    dsl::update,
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, RunQueryDsl,
+};
+use oauth2::{
+    basic::{BasicErrorResponseType, BasicTokenType},
+    reqwest::http_client,
+    RefreshToken, RequestTokenError, TokenResponse,
+};
+use serde_json::Value;
+use std::time;
+
+/// How long, in seconds, a provider's grant is trusted before it's checked again.
+const REVALIDATION_INTERVAL_SECS: i64 = 60 * 60;
+
+/// How often, in seconds, the server looks for grants that need to be checked again.
+const REVALIDATION_TICK_SECS: u64 = 5 * 60;
+
+/// The most grants that are checked at once, so that a backlog doesn't hammer the providers.
+const REVALIDATION_BATCH_SIZE: i64 = 50;
+
+/// How long, in seconds, before an access token expires that it's treated as expired, so that
+/// it can't expire mid-request.
+const EXPIRY_LEEWAY_SECS: i64 = 60;
+
+/// What stored provider access tokens are encrypted for.
+const ACCESS_TOKEN_PURPOSE: &str = "provider-access-token";
+
+/// What stored provider refresh tokens are encrypted for.
+const REFRESH_TOKEN_PURPOSE: &str = "provider-refresh-token";
+
+/// The tokens that a provider issued for one of its users.
+pub(crate) struct ProviderTokens {
+    /// The token used to access the user's account at the provider
+    pub access_token: String,
+
+    /// The token used to get a new access token once the current one expires, if any
+    pub refresh_token: Option<String>,
+
+    /// When the access token expires, if the provider said
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl ProviderTokens {
+    /// Reads the tokens from a provider's token response.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The response to an authorization code or refresh token exchange
+    pub fn from_response<TR: TokenResponse<BasicTokenType>>(response: &TR) -> Self {
+        Self {
+            access_token: response.access_token().secret().to_owned(),
+            refresh_token: response.refresh_token().map(|t| t.secret().to_owned()),
+            expires_at: response
+                .expires_in()
+                .and_then(|lifetime| Duration::from_std(lifetime).ok())
+                .map(|lifetime| Utc::now().naive_utc() + lifetime),
+        } // Return the new instance
+    }
+
+    /// Decrypts the tokens stored alongside an identity, if it has any.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key that the tokens were encrypted with
+    /// * `identity` - The identity that the tokens were issued for
+    fn decrypt(key: &SecretKey, identity: &UserIdentity) -> Option<Self> {
+        Some(Self {
+            access_token: key.decrypt(ACCESS_TOKEN_PURPOSE, identity.access_token.as_ref()?)?,
+            refresh_token: identity
+                .refresh_token
+                .as_ref()
+                .and_then(|t| key.decrypt(REFRESH_TOKEN_PURPOSE, t)),
+            expires_at: identity.token_expires_at,
+        })
+    }
+
+    /// Determines whether or not the access token has expired, or is about to.
+    fn expired(&self) -> bool {
+        self.expires_at.map_or(false, |t| {
+            t <= Utc::now().naive_utc() + Duration::seconds(EXPIRY_LEEWAY_SECS)
+        })
+    }
+}
+
+/// What a provider said about a user's grant.
+enum Validity {
+    /// The grant is still valid, and these are its latest tokens
+    Valid(ProviderTokens),
+
+    /// The user revoked the grant, or it otherwise stopped working
+    Revoked,
+
+    /// The provider couldn't be asked (e.g. it's down), or the grant can't be checked
+    Unknown,
+}
+
+/// Stores the tokens that a provider issued for an identity, encrypted with the server's secret
+/// key. The identity is marked as just checked.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the tokens will be stored with
+/// * `key` - The key that the tokens will be encrypted with
+/// * `identity_uid` - The ID of the identity that the tokens were issued for
+/// * `tokens` - The tokens issued by the provider
+pub(crate) fn save_tokens(
+    conn: &PgConnection,
+    key: &SecretKey,
+    identity_uid: i32,
+    tokens: &ProviderTokens,
+) -> Result<(), Error> {
+    update(user_identities.find(identity_uid))
+        .set((
+            schema::user_identities::access_token.eq(Some(
+                key.encrypt(ACCESS_TOKEN_PURPOSE, &tokens.access_token),
+            )),
+            schema::user_identities::refresh_token.eq(tokens
+                .refresh_token
+                .as_ref()
+                .map(|t| key.encrypt(REFRESH_TOKEN_PURPOSE, t))),
+            schema::user_identities::token_expires_at.eq(tokens.expires_at),
+            schema::user_identities::checked_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Forgets the provider tokens of an identity whose grant has been revoked, and revokes every
+/// session that was started with it.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the identity will be updated with
+/// * `identity` - The identity whose grant was revoked
+fn revoke_identity(conn: &PgConnection, identity: &UserIdentity) -> Result<(), Error> {
+    info!(
+        "The {} grant of user {} was revoked; logging them out",
+        identity.provider, identity.user_id
+    );
+
+    conn.transaction::<_, Error, _>(|| {
+        update(user_identities.find(identity.id))
+            .set((
+                schema::user_identities::access_token.eq(None::<String>),
+                schema::user_identities::refresh_token.eq(None::<String>),
+                schema::user_identities::token_expires_at.eq(None::<NaiveDateTime>),
+                schema::user_identities::checked_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+
+        diesel::delete(auth_sessions.filter(schema::auth_sessions::identity_id.eq(identity.id)))
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+/// Exchanges a refresh token for a new access token. The oauth2 HTTP client blocks, so the
+/// exchange is made on the blocking thread pool.
+///
+/// # Arguments
+///
+/// * `provider` - The provider that issued the tokens
+/// * `tokens` - The tokens that will be refreshed
+async fn refresh(provider: &Provider, tokens: &ProviderTokens) -> Validity {
+    // Without a refresh token, there's no way to get a new access token
+    let refresh_token = match &tokens.refresh_token {
+        Some(t) => RefreshToken::new(t.clone()),
+        None => return Validity::Unknown,
+    };
+
+    let client = provider.client.clone();
+
+    match web::block(move || {
+        client
+            .exchange_refresh_token(&refresh_token)
+            .request(http_client)
+    })
+    .await
+    {
+        Ok(response) => {
+            // Providers don't have to issue a new refresh token every time
+            let mut refreshed = ProviderTokens::from_response(&response);
+            refreshed.refresh_token = refreshed
+                .refresh_token
+                .or_else(|| tokens.refresh_token.clone());
+
+            Validity::Valid(refreshed)
+        }
+
+        // The provider refuses to refresh grants that have been revoked
+        Err(BlockingError::Error(RequestTokenError::ServerResponse(e)))
+            if *e.error() == BasicErrorResponseType::InvalidGrant =>
+        {
+            Validity::Revoked
+        }
+
+        Err(BlockingError::Error(e)) => {
+            warn!("Failed to refresh a {} access token: {}", provider.name, e);
+
+            Validity::Unknown
+        }
+        Err(BlockingError::Canceled) => {
+            warn!("Refreshing a {} access token was canceled", provider.name);
+
+            Validity::Unknown
+        }
+    }
+}
+
+/// Asks the provider whether or not an access token still grants access to the identity's
+/// account. Only an explicit rejection counts as revoked; errors and rate limits don't.
+///
+/// # Arguments
+///
+/// * `client` - The HTTP client used to ask the provider
+/// * `provider` - The provider that issued the access token
+/// * `identity` - The identity that the access token was issued for
+/// * `access_token` - The access token
+async fn check_access_token(
+    client: &Client,
+    provider: &Provider,
+    identity: &UserIdentity,
+    access_token: &str,
+) -> Validity {
+    let mut response = match client
+        .get(&provider.userinfo_endpoint)
+        .set_header("Authorization", format!("Bearer {}", access_token))
+        .set_header("User-Agent", "Notedly")
+        .send()
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Failed to reach oauth provider '{}': {}", provider.name, e);
+
+            return Validity::Unknown;
+        }
+    };
+
+    match response.status() {
+        StatusCode::UNAUTHORIZED => Validity::Revoked,
+        status if status.is_success() => match response.json::<Value>().await {
+            // The token has to still belong to the same account
+            Ok(info)
+                if provider.fields.subject(&info).as_deref() == Some(identity.subject.as_str()) =>
+            {
+                Validity::Valid(ProviderTokens {
+                    access_token: access_token.to_owned(),
+                    refresh_token: None,
+                    expires_at: None,
+                })
+            }
+            Ok(_) => Validity::Revoked,
+            Err(_) => Validity::Unknown,
+        },
+        _ => Validity::Unknown,
+    }
+}
+
+/// Asks the provider whether or not the identity's grant is still valid, refreshing its access
+/// token if needed.
+///
+/// # Arguments
+///
+/// * `client` - The HTTP client used to ask the provider
+/// * `provider` - The provider that issued the identity
+/// * `identity` - The identity that will be checked
+/// * `tokens` - The identity's latest tokens
+async fn validate(
+    client: &Client,
+    provider: &Provider,
+    identity: &UserIdentity,
+    tokens: ProviderTokens,
+) -> Validity {
+    // Expired access tokens have to be refreshed before they can be checked
+    let (tokens, refreshed) = if tokens.expired() {
+        match refresh(provider, &tokens).await {
+            Validity::Valid(new_tokens) => (new_tokens, true),
+            other => return other,
+        }
+    } else {
+        (tokens, false)
+    };
+
+    match check_access_token(client, provider, identity, &tokens.access_token).await {
+        Validity::Valid(_) => Validity::Valid(tokens),
+
+        // The access token may have been revoked on its own, so try a new one before giving up
+        Validity::Revoked if !refreshed && tokens.refresh_token.is_some() => {
+            match refresh(provider, &tokens).await {
+                Validity::Valid(new_tokens) => {
+                    match check_access_token(client, provider, identity, &new_tokens.access_token)
+                        .await
+                    {
+                        Validity::Valid(_) => Validity::Valid(new_tokens),
+                        other => other,
+                    }
+                }
+                other => other,
+            }
+        }
+        other => other,
+    }
+}
+
+/// Checks that the user hasn't revoked the identity's grant at its provider. If they have, every
+/// session started with the identity is revoked too. Returns whether or not the grant is still
+/// valid; grants that can't be checked (e.g. because the provider is down) are given the benefit
+/// of the doubt.
+///
+/// No database connection is held while the provider is asked, since that can take a while. One
+/// is only taken from the pool once the result needs to be saved.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `oauth_config` - The server's oauth configuration
+/// * `key` - The key that the identity's tokens are encrypted with
+/// * `client` - The HTTP client used to ask the provider
+/// * `identity` - The identity that will be checked
+pub(crate) async fn revalidate(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    oauth_config: &OauthConfig,
+    key: &SecretKey,
+    client: &Client,
+    identity: &UserIdentity,
+) -> Result<bool, Error> {
+    // Identities from providers that are no longer configured, or that were linked before their
+    // tokens were stored, can't be checked
+    let (provider, tokens) = match (
+        oauth_config.provider(&identity.provider),
+        ProviderTokens::decrypt(key, identity),
+    ) {
+        (Some(p), Some(t)) => (p, t),
+        _ => return Ok(true),
+    };
+
+    let validity = validate(client, provider, identity, tokens).await;
+
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    match validity {
+        Validity::Valid(tokens) => {
+            save_tokens(&conn, key, identity.id, &tokens)?;
+
+            Ok(true)
+        }
+        Validity::Revoked => {
+            revoke_identity(&conn, identity)?;
+
+            Ok(false)
+        }
+
+        // Try again later, rather than every tick
+        Validity::Unknown => {
+            update(user_identities.find(identity.id))
+                .set(schema::user_identities::checked_at.eq(Utc::now().naive_utc()))
+                .execute(&conn)?;
+
+            Ok(true)
+        }
+    }
+}
+
+/// Checks each of the grants that haven't been checked recently.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `oauth_config` - The server's oauth configuration
+/// * `key` - The key that the identities' tokens are encrypted with
+/// * `client` - The HTTP client used to ask the providers
+async fn revalidate_stale(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    oauth_config: &OauthConfig,
+    key: &SecretKey,
+    client: &Client,
+) -> Result<(), Error> {
+    // Get the grants that were checked longest ago. The connection is given back before any
+    // provider is asked, and each grant takes its own when it's saved.
+    let stale: Vec<UserIdentity> = user_identities
+        .filter(
+            schema::user_identities::access_token.is_not_null().and(
+                schema::user_identities::checked_at
+                    .lt(Utc::now().naive_utc() - Duration::seconds(REVALIDATION_INTERVAL_SECS)),
+            ),
+        )
+        .order(schema::user_identities::checked_at)
+        .limit(REVALIDATION_BATCH_SIZE)
+        .load(&pool.get()?)?;
+
+    for identity in &stale {
+        revalidate(pool, oauth_config, key, client, identity).await?;
+    }
+
+    Ok(())
+}
+
+/// Periodically checks that users haven't revoked their grants at their providers, so that
+/// revoking Notedly's access at a provider also logs the user out of Notedly. Runs forever.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `oauth_config` - The server's oauth configuration
+/// * `key` - The key that the identities' tokens are encrypted with
+pub async fn revalidate_periodically(
+    pool: Pool<ConnectionManager<PgConnection>>,
+    oauth_config: OauthConfig,
+    key: SecretKey,
+) {
+    let client = Client::default();
+    let mut ticks = actix_rt::time::interval(time::Duration::from_secs(REVALIDATION_TICK_SECS));
+
+    loop {
+        ticks.tick().await;
+
+        if let Err(e) = revalidate_stale(&pool, &oauth_config, &key, &client).await {
+            error!("Failed to check oauth provider grants: {}", e.0);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use super::{
+    super::models::{SearchResult, User},
+    auth::user_for_token,
+    users::{extract_bearer, Error},
+};
+use actix_web::{
+    error,
+    web::{Data, HttpRequest, Json, Query},
+    Scope as ActixScope,
+};
+use diesel::{
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    sql_query,
+    sql_types::{Int4, Int8, Text},
+    RunQueryDsl,
+};
+use serde::Deserialize;
+
+/// The number of results returned by a search when the caller doesn't ask for a specific amount.
+const DEFAULT_LIMIT: i64 = 20;
+
+/// The largest number of results that a single search may return.
+const MAX_LIMIT: i64 = 100;
+
+/// Marks the start of a matching term in the highlighted text returned by postgres. Characters
+/// from the private use area are used instead of HTML, so that the text can be escaped before it
+/// is sent to the user.
+const START_SEL: char = '\u{e000}';
+
+/// Marks the end of a matching term in the highlighted text returned by postgres.
+const STOP_SEL: char = '\u{e001}';
+
+/// Searches every note and board that the user is able to view. Notes match on their title and
+/// body, and boards match on their title. A note is only visible if the user can view its board,
+/// using the same rules as `boards::viewable_boards` (the user owns the board, or has been invited
+/// to it).
+///
+/// The search indexes created in the `create_search_indexes` migration are built on these exact
+/// `to_tsvector` expressions, so any change here must be mirrored there.
+///
+/// Matching terms are wrapped in `START_SEL` and `STOP_SEL` (both are given in $4, and the
+/// headline options in $5), which are first removed from the text itself so that they can't be
+/// forged.
+const SEARCH_QUERY: &str = "
+    WITH query AS (SELECT websearch_to_tsquery('english', $1) AS q),
+    viewable AS (
+        SELECT boards.id FROM boards
+        WHERE boards.user_id = $2
+            OR EXISTS (
+                SELECT 1 FROM permissions
+                WHERE permissions.user_id = $2 AND permissions.board_id = boards.id
+            )
+    )
+    SELECT 'note' AS kind, notes.id, notes.board_id,
+        ts_headline('english', translate(notes.title, $4, ''), query.q, $5 || ', HighlightAll=true') AS title,
+        ts_headline('english', translate(notes.body, $4, ''), query.q, $5 || ', MaxFragments=2') AS snippet,
+        ts_rank(to_tsvector('english', notes.title || ' ' || notes.body), query.q) AS rank
+    FROM notes, query
+    WHERE to_tsvector('english', notes.title || ' ' || notes.body) @@ query.q
+        AND notes.board_id IN (SELECT id FROM viewable)
+    UNION ALL
+    SELECT 'board' AS kind, boards.id, boards.id AS board_id,
+        ts_headline('english', translate(boards.title, $4, ''), query.q, $5 || ', HighlightAll=true') AS title,
+        '' AS snippet,
+        ts_rank(to_tsvector('english', boards.title), query.q) AS rank
+    FROM boards, query
+    WHERE to_tsvector('english', boards.title) @@ query.q
+        AND boards.id IN (SELECT id FROM viewable)
+    ORDER BY rank DESC, kind, id
+    LIMIT $3";
+
+/// A request to the /search route.
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    /// The search terms (supports quoted phrases, `or` and `-` exclusions)
+    pub q: String,
+
+    /// The maximum number of results to return
+    pub limit: Option<i64>,
+}
+
+/// Constructs an actix service group for the search endpoint.
+pub fn build_service_group() -> ActixScope {
+    ActixScope::new("/search").service(search)
+}
+
+/// Searches the notes and boards that the currently authenticated user is able to view, returning
+/// the best matches first.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `query` - The search terms, and an optional limit on the number of results
+#[get("")]
+pub async fn search(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    query: Query<SearchRequest>,
+) -> Result<Json<Vec<SearchResult>>, Error> {
+    // An empty query would match nothing, so let the user know instead
+    if query.q.trim().is_empty() {
+        return Err(Error(error::ErrorBadRequest(
+            "The search query must not be empty.",
+        )));
+    }
+
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the currently authenticated user
+    let u: User = user_for_token(&conn, token)?;
+
+    // Run the search, keeping the number of results within reason
+    let results = sql_query(SEARCH_QUERY)
+        .bind::<Text, _>(&query.q)
+        .bind::<Int4, _>(u.id)
+        .bind::<Int8, _>(query.limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT))
+        .bind::<Text, _>(format!("{}{}", START_SEL, STOP_SEL))
+        .bind::<Text, _>(format!(
+            "StartSel=\"{}\", StopSel=\"{}\"",
+            START_SEL, STOP_SEL
+        ))
+        .load::<SearchResult>(&conn)?;
+
+    // Escape the matches, so that clients can safely render the highlighting as HTML
+    Ok(Json(
+        results
+            .into_iter()
+            .map(|result| SearchResult {
+                title: highlight(&result.title),
+                snippet: highlight(&result.snippet),
+                ..result
+            })
+            .collect(),
+    ))
+}
+
+/// Escapes the given headline as HTML, and wraps each of its matching terms in a <mark> element.
+///
+/// # Arguments
+///
+/// * `headline` - Text highlighted by postgres with `START_SEL` and `STOP_SEL`
+fn highlight(headline: &str) -> String {
+    let mut html = String::with_capacity(headline.len());
+
+    for c in headline.chars() {
+        match c {
+            START_SEL => html.push_str("<mark>"),
+            STOP_SEL => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_escapes_html() {
+        assert_eq!(
+            highlight("<img src=x onerror=alert(1)> \u{e000}cat\u{e001} & dog"),
+            "&lt;img src=x onerror=alert(1)&gt; <mark>cat</mark> &amp; dog"
+        );
+    }
+}
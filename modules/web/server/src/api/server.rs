@@ -1,75 +1,118 @@
-use super::{boards, notes, oauth, users};
-use actix_cors::Cors;
-use actix_session::CookieSession;
-use actix_web::{middleware::Logger, App, HttpServer};
+use super::{
+    super::{
+        config::{redact_database_url, Config},
+        crypto::SecretKey,
+        mail::Mailer,
+    },
+    auth, boards,
+    cookies::CookieKeys,
+    invitations,
+    live::LiveNotes,
+    notes, oauth,
+    passwords::{self, PasswordConfig},
+    providers::{Provider, ProviderConfig},
+    revalidation, search, tokens, users,
+};
+use actix_cors::{Cors, CorsFactory};
+use actix_web::client::Client;
+use actix_web::{dev::Service, middleware::Logger, web::Data, App, HttpServer};
 use diesel::{
     pg::PgConnection,
     r2d2::{ConnectionManager, Pool},
 };
-use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
-use rand::Rng;
-use std::io;
+use oauth2::RedirectUrl;
+use std::{collections::HashMap, io, net::IpAddr};
 
 /// A configuration for the server's oauth capabilities.
 #[derive(Clone)]
 pub struct OauthConfig {
-    /// A basic oauth2 client for the GitHub authentication API
-    pub github_api_client: BasicClient,
+    /// Each of the providers that users can log in with, by name
+    providers: HashMap<String, Provider>,
 
-    /// A basic oauth2 client for the Google authentication API
-    pub google_api_client: BasicClient,
+    /// Where users are sent once they've logged in
+    frontend_url: String,
 }
 
 impl OauthConfig {
-    /// Initializes a new OauthConfig from the provided vector of environment variables.
-    /// Note: This method consumes the original vector of environment variables.
+    /// Initializes a new OauthConfig from the provided provider configurations, discovering the
+    /// endpoints of any OpenID Connect providers that weren't configured explicitly.
     ///
     /// # Arguments
     ///
-    /// * `oauth_credentials` - A vector of environment variables representing the following oauth
-    /// API credentials: github client ID, github client secret, google client ID, google client
-    /// secret
-    pub fn new(mut oauth_credentials: Vec<String>) -> (Self, Vec<String>) {
-        let mut clients: Vec<BasicClient> = Vec::new(); // A tuple to store the constructed clients in
-
-        // Construct an oauth client for each of the given credentials
-        for i in 0..2 {
-            let auth_url = AuthUrl::new(if i == 0 {
-                // Use the GitHub oauth authorization URL, instead of the google URL
-                "https://github.com/login/oauth/authorize".to_owned()
-            } else {
-                // use the Google oauth authorization URL, instead of the github URL
-                "https://accounts.google.com/o/oauth2/v2/auth".to_owned()
-            }); // Get the authorization URL for both providers
-
-            let token_url = TokenUrl::new(if i == 0 {
-                // Use the GitHub oauth token URL, instead of the google URL
-                "https://github.com/login/oauth/access_token".to_owned()
-            } else {
-                // use the Google oauth token URL, instead of the github URL
-                "https://accounts.google.com/o/oauth2/v3/token".to_owned()
-            }); // Get the authorization URL for both providers
-
-            let client_id = ClientId::new(oauth_credentials.remove(0)); // Make a client ID from the provided credential
-            let client_secret = ClientSecret::new(oauth_credentials.remove(0)); // Make a client secret from the provided credential
-
-            // Make the oauth client, and store it in the clients tuple
-            clients.push(BasicClient::new(
-                client_id,
-                Some(client_secret),
-                auth_url.unwrap(),
-                token_url.ok(),
-            ));
+    /// * `configs` - The configuration of each of the providers that users can log in with
+    pub async fn new(configs: Vec<ProviderConfig>) -> io::Result<Self> {
+        let client = Client::default(); // The client used to discover each provider
+        let mut providers = HashMap::new();
+
+        for config in configs {
+            let provider = config.resolve(&client).await?;
+
+            // Login URLs refer to providers by name, so names must be unique
+            if providers.contains_key(&provider.name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the oauth provider '{}' is configured twice", provider.name),
+                ));
+            }
+
+            info!("Registered oauth provider '{}'", provider.name);
+
+            providers.insert(provider.name.clone(), provider);
         }
 
-        // Return the initialized OauthConfig, as well as the remaining env vars
-        (
-            OauthConfig {
-                github_api_client: clients.remove(0),
-                google_api_client: clients.remove(0),
-            },
-            oauth_credentials,
-        )
+        Ok(Self {
+            providers,
+            frontend_url: String::new(),
+        })
+    }
+
+    /// Gets the provider registered under the given name, if it exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the provider
+    pub fn provider(&self, name: &str) -> Option<&Provider> {
+        self.providers.get(name)
+    }
+
+    /// Gets the URL of the front-end that users are sent to once they've logged in.
+    pub fn frontend_url(&self) -> &str {
+        &self.frontend_url
+    }
+
+    /// Sets the URLs that users are sent back to after logging in. Providers redirect users to
+    /// their own redirect URL if they have one, or to the server's /oauth/cb route otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `public_url` - The URL that the server can be reached at by users' browsers
+    /// * `frontend_url` - The URL of the front-end that users are sent to once they've logged in
+    fn set_urls(&mut self, public_url: &str, frontend_url: String) -> io::Result<()> {
+        let callback_url = format!("{}/oauth/cb", public_url.trim_end_matches('/'));
+
+        for provider in self.providers.values_mut() {
+            let redirect_url = RedirectUrl::new(
+                provider
+                    .redirect_url
+                    .clone()
+                    .unwrap_or_else(|| callback_url.clone()),
+            )
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "the oauth provider '{}' has an invalid redirect URL: {}",
+                        provider.name, e
+                    ),
+                )
+            })?;
+
+            provider.client = provider.client.clone().set_redirect_url(redirect_url);
+        }
+
+        self.frontend_url = frontend_url;
+
+        Ok(())
     }
 }
 
@@ -81,85 +124,167 @@ pub struct Server {
     /// The database URL to which the server will connect
     database_endpoint: String,
 
+    /// The most connections that the server will keep open to the database
+    database_pool_size: u32,
+
+    /// The key that the server will sign tokens with
+    secret_key: SecretKey,
+
+    /// The configuration of email/password logins, if they're allowed
+    password_config: Option<PasswordConfig>,
+
+    /// The keys that session cookies are encrypted with
+    cookie_keys: CookieKeys,
+
+    /// Whether or not session cookies should only be sent over TLS
+    secure_cookies: bool,
+
+    /// The origins that browsers may call the API from ("*" for any)
+    cors_origins: Vec<String>,
+
+    /// The IP address the API should be served on
+    bind_address: IpAddr,
+
     /// The port the API should be served on
     port: u16,
 }
 
 impl Server {
-    /// Initializes a new Server with the given oauth configuration.
+    /// Initializes a new Server with the given oauth configuration. Session cookies are encrypted
+    /// with a key derived from the secret key, unless cookie keys are configured, and are only
+    /// sent over TLS if the public URL uses it.
     ///
     /// # Arguments
     ///
     /// * `oauth_config` - The active Oauth API access configuration
-    /// * `database_endpoint` - The active database connection URI
-    /// * `port` - The port that the API will be served on
-    pub fn new(mut oauth_config: OauthConfig, database_endpoint: String, port: u16) -> Self {
-        let callback_url = "https://api.notedly.app/oauth/cb".to_owned(); // Get the oauth callback url
-
-        // Set the redirect URL for both clients
-        oauth_config.google_api_client = oauth_config
-            .google_api_client
-            .set_redirect_url(RedirectUrl::new(callback_url.clone()).unwrap());
-        oauth_config.github_api_client = oauth_config
-            .github_api_client
-            .set_redirect_url(RedirectUrl::new(callback_url).unwrap());
-
-        Self {
+    /// * `config` - The server's configuration
+    pub fn new(mut oauth_config: OauthConfig, config: &Config) -> io::Result<Self> {
+        // Set the redirect URL for every client
+        oauth_config.set_urls(&config.public_url, config.frontend_url.clone())?;
+
+        let secret_key = SecretKey::new(&config.secret_key);
+
+        Ok(Self {
             oauth_config,
-            database_endpoint,
-            port,
-        } // Return the initialized server
+            database_endpoint: config.database_url.clone(),
+            database_pool_size: config.database_pool_size,
+            cookie_keys: match &config.cookie_keys {
+                Some((primary, previous)) => CookieKeys::new(primary, previous)?,
+                None => CookieKeys::derive(&secret_key),
+            },
+            secret_key,
+            password_config: config.passwords.as_ref().map(|passwords| {
+                PasswordConfig::new(
+                    config.frontend_url.clone(),
+                    Mailer::new(passwords.sendmail.clone(), passwords.mail_from.clone()),
+                )
+            }),
+            secure_cookies: config.public_url.starts_with("https://"),
+            cors_origins: config.cors_origins.clone(),
+            bind_address: config.bind_address,
+            port: config.port,
+        }) // Return the initialized server
     }
 
     /// Starts the API web server.
     pub async fn start(&mut self) -> io::Result<()> {
         // Log the pending connection
         info!(
-            "Connecting to postgres database (postgres://{}:****@{})",
-            self.database_endpoint.split("://").collect::<Vec<&str>>()[1]
-                .split(':')
-                .collect::<Vec<&str>>()[0],
-            self.database_endpoint.split('@').collect::<Vec<&str>>()[1]
+            "Connecting to postgres database ({})",
+            redact_database_url(&self.database_endpoint)
         );
 
         let manager = ConnectionManager::<PgConnection>::new(&self.database_endpoint); // Make a new connection manager from the config's db endpoint
 
         // Make a connection pool from the r2d2 manager
-        match Pool::builder().build(manager) {
+        match Pool::builder()
+            .max_size(self.database_pool_size)
+            .build(manager)
+        {
             // Setup the HTTP server
             Ok(pool) =>
             // Start the HTTP server
             {
                 let cfg = self.oauth_config.clone(); // Clone the server's oauth configuration, so we can move it into the server logic closure
+                let key = self.secret_key.clone(); // Clone the server's secret key, so we can move it into the server logic closure
+                let passwords = self.password_config.clone(); // Clone the server's password login configuration, so we can move it into the server logic closure
+                let cookie_keys = self.cookie_keys.clone(); // Clone the server's cookie keys, so that every worker encrypts cookies the same way
+                let secure_cookies = self.secure_cookies;
+                let cors_origins = self.cors_origins.clone(); // Clone the allowed origins, so that every worker can build its own CORS middleware
+                let live = Data::new(LiveNotes::default()); // Share the state of live notes between every worker
+
+                // Log users out when they revoke our access at their oauth provider
+                actix_rt::spawn(revalidation::revalidate_periodically(
+                    pool.clone(),
+                    cfg.clone(),
+                    key.clone(),
+                ));
+
                 HttpServer::new(move || {
-                    let encryption_key: [u8; 32] = rand::thread_rng().gen::<[u8; 32]>(); // Generate an encryption key
+                    let rekey_keys = cookie_keys.clone(); // The keys that cookies from before the last key rotation are re-encrypted with
 
                     // Register all of the API's routes, and attach the db connection handler
                     App::new()
                         .wrap(Logger::default())
-                        .wrap(CookieSession::private(&encryption_key).secure(false)) // Use secure session storage to store state vars, pkce challenges
-                        .wrap(Cors::new().allowed_origin("*").finish()) // TODO: Better CORS policy?
+                        .wrap(cookie_keys.session_middleware(secure_cookies)) // Use secure session storage to store state vars, pkce challenges
+                        .wrap_fn(move |mut req, srv| {
+                            // Accept cookies encrypted with a key that has since been rotated out
+                            rekey_keys.rekey(&mut req);
+
+                            srv.call(req)
+                        })
+                        .wrap(cors_middleware(&cors_origins)) // Only let the configured origins call the API from browsers
                         .data(pool.clone()) // Allow usage of the db connector from API routes
                         .data(cfg.clone()) // Allow access to the oauth configuration from request handlers
+                        .data(key.clone()) // Allow request handlers to sign & verify tokens
+                        .app_data(live.clone()) // Allow access to the live notes from request handlers
                         .service(oauth::build_service_group()) // Register the oauth service
+                        .service(auth::build_service_group()) // Register the auth sessions service
+                        .service(tokens::build_service_group()) // Register the personal access tokens service
                         .service(users::build_service_group()) // Register the users service
                         .service(boards::build_service_group()) // Register the boards service
                         .service(notes::build_service_group()) // Register the notes service
+                        .service(search::build_service_group()) // Register the search service
+                        .service(invitations::build_service_group()) // Register the invitations service
+                        .configure(|svc| {
+                            // Only register the password service if password logins are allowed
+                            if let Some(password_cfg) = &passwords {
+                                svc.data(password_cfg.clone())
+                                    .service(passwords::build_service_group());
+                            }
+                        })
                 })
-                .bind(format!("0.0.0.0:{}", self.port))?
+                .bind((self.bind_address, self.port))?
                 .run()
                 .await
             }
 
-            // Log an error
-            Err(e) => {
-                error!(
-                    "Failed to construct a connection pool for the database: {}",
+            // Stop the server, since it can't do anything without a database
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "failed to construct a connection pool for the database: {}",
                     e
-                ); // Log the error
+                ),
+            )),
+        }
+    }
+}
 
-                Ok(()) // Stop the main thread
-            }
+/// Makes the middleware that lets browsers call the API from the given origins.
+///
+/// # Arguments
+///
+/// * `origins` - The origins that browsers may call the API from ("*" for any)
+fn cors_middleware(origins: &[String]) -> CorsFactory {
+    let mut cors = Cors::new();
+
+    // Any origin is allowed unless the origins are listed
+    if !origins.iter().any(|origin| origin == "*") {
+        for origin in origins {
+            cors = cors.allowed_origin(origin);
         }
     }
+
+    cors.finish()
 }
@@ -0,0 +1,373 @@
+use super::{
+    super::{
+        crypto::random_token,
+        models::{ApiToken, ApiTokenRequest, CreatedApiToken, NewApiToken, User},
+        schema::{self, api_tokens::dsl::api_tokens, users::dsl::users},
+    },
+    access::{continue_if_allowed, Capability},
+    auth::{user_for_session, LAST_USED_RESOLUTION_SECS},
+    users::{extract_bearer, hash_token, Bearer, Error},
+};
+use actix_web::{
+    error,
+    http::Method,
+    web::{Data, HttpRequest, HttpResponse, Json, Path},
+    Scope as ActixScope,
+};
+use chrono::{Duration, Utc};
+use diesel::{
+    dsl::update,
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+
+/// The prefix of every personal access token, which tells them apart from session tokens.
+const TOKEN_PREFIX: &str = "ntd_";
+
+/// Every scope that a personal access token can be given.
+pub const SCOPES: [&str; 5] = [
+    "boards:read",
+    "boards:write",
+    "notes:read",
+    "notes:write",
+    "users:read",
+];
+
+/// The boards that the personal access token used for a request is restricted to. Stored in the
+/// request's extensions, so that handlers can check boards named in request bodies.
+struct BoardRestriction(Vec<i32>);
+
+/// Constructs an actix service group for the personal access tokens endpoint.
+pub fn build_service_group() -> ActixScope {
+    ActixScope::new("/tokens")
+        .service(all_tokens)
+        .service(new_token)
+        .service(revoke_token)
+}
+
+/// Determines whether or not the given bearer token is a personal access token.
+///
+/// # Arguments
+///
+/// * `token` - The bearer token
+pub(crate) fn is_api_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Gets the scope that a personal access token needs for the given request. Requests that can't
+/// be made with a personal access token at all (e.g. managing sessions) don't have a scope.
+///
+/// # Arguments
+///
+/// * `req` - The request that the token was sent with
+fn required_scope(req: &HttpRequest) -> Option<&'static str> {
+    let segments: Vec<&str> = req.path().trim_matches('/').split('/').collect();
+
+    // Live notes are opened with a GET, but they're used to write to the note
+    let writes = !(req.method() == Method::GET || req.method() == Method::HEAD)
+        || segments.last() == Some(&"live");
+
+    let (read, write) = match segments[0] {
+        // Notes can also be listed through their boards and authors
+        "boards" | "users" if segments.contains(&"notes") => ("notes:read", Some("notes:write")),
+        "notes" | "search" => ("notes:read", Some("notes:write")),
+        "boards" | "invitations" => ("boards:read", Some("boards:write")),
+
+        // Tokens can look users up, but can't change the accounts that they log in with
+        "users" if !segments.contains(&"identities") => ("users:read", None),
+        _ => return None,
+    };
+
+    if writes {
+        write
+    } else {
+        Some(read)
+    }
+}
+
+/// Gets the board that the given request is about, if its path names one.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the board will be looked up with
+/// * `req` - The request that the token was sent with
+fn target_board(conn: &PgConnection, req: &HttpRequest) -> Result<Option<i32>, Error> {
+    let segments: Vec<&str> = req.path().trim_matches('/').split('/').collect();
+
+    // Rest patterns in slices need a newer compiler than the one that the server is built with, so
+    // match on the segments one at a time
+    let segment = |i: usize| segments.get(i).cloned();
+
+    Ok(match (segment(0), segment(1)) {
+        (Some("boards"), Some("shared")) => match segment(2) {
+            Some(slug) => schema::boards::table
+                .filter(schema::boards::share_slug.eq(slug))
+                .select(schema::boards::id)
+                .first(conn)
+                .optional()?,
+            None => None,
+        },
+        (Some("boards"), Some(board)) => board.parse().ok(),
+        (Some("users"), Some(_)) if segment(2) == Some("assignments") => {
+            segment(3).and_then(|board| board.parse().ok())
+        }
+        (Some("notes"), Some(note)) => match note.parse::<i32>() {
+            Ok(note_uid) => schema::notes::table
+                .find(note_uid)
+                .select(schema::notes::board_id)
+                .first(conn)
+                .optional()?,
+            Err(_) => None,
+        },
+        _ => None,
+    })
+}
+
+/// Gets the user that minted the given personal access token, as long as the token hasn't expired
+/// and is allowed to make the request that it was sent with.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the token will be read with
+/// * `bearer` - The personal access token, along with the request that it was sent with
+pub(crate) fn user_for_api_token(conn: &PgConnection, bearer: Bearer) -> Result<User, Error> {
+    let now = Utc::now().naive_utc();
+
+    let token: ApiToken = match api_tokens
+        .filter(
+            schema::api_tokens::token_hash
+                .eq(hash_token(bearer.token))
+                .and(
+                    schema::api_tokens::expires_at
+                        .is_null()
+                        .or(schema::api_tokens::expires_at.gt(now)),
+                ),
+        )
+        .first(conn)
+        .optional()?
+    {
+        Some(t) => t,
+        None => {
+            return Err(Error(error::ErrorUnauthorized(
+                "The provided access token has expired or been revoked.",
+            )))
+        }
+    };
+
+    // Make sure that the token was given the scope that the request needs
+    match required_scope(bearer.req) {
+        Some(scope) if token.scopes.iter().any(|s| s == scope) => {}
+        Some(scope) => {
+            return Err(Error(error::ErrorForbidden(format!(
+                "The provided access token needs the '{}' scope for this request.",
+                scope
+            ))))
+        }
+        None => {
+            return Err(Error(error::ErrorForbidden(
+                "Personal access tokens can't be used for this request. Log in instead.",
+            )))
+        }
+    }
+
+    // Restricted tokens can only be used with their boards
+    if let Some(allowed) = &token.board_ids {
+        match target_board(conn, bearer.req)? {
+            Some(board_uid) if allowed.contains(&board_uid) => {}
+
+            // New notes name their board in the request body, so they're checked once posted
+            None if bearer.req.method() == Method::POST
+                && bearer.req.path().trim_end_matches('/') == "/notes" => {}
+            _ => {
+                return Err(Error(error::ErrorForbidden(
+                    "The provided access token can't be used with this board.",
+                )))
+            }
+        }
+
+        bearer
+            .req
+            .extensions_mut()
+            .insert(BoardRestriction(allowed.clone()));
+    }
+
+    // Remember when the token was last used, so that users can spot unused tokens
+    if token.last_used_at.map_or(true, |t| {
+        t < now - Duration::seconds(LAST_USED_RESOLUTION_SECS)
+    }) {
+        update(api_tokens.find(token.id))
+            .set(schema::api_tokens::last_used_at.eq(Some(now)))
+            .execute(conn)?;
+    }
+
+    Ok(users.find(token.user_id).first(conn)?)
+}
+
+/// Ensures that the personal access token used for the given request, if any, may be used with
+/// the given board. Only needed for boards named in request bodies; boards named in the request's
+/// path are checked when the user is looked up.
+///
+/// # Arguments
+///
+/// * `req` - The user's request
+/// * `board_uid` - The ID of the board that the request is about
+pub(crate) fn continue_if_token_covers(req: &HttpRequest, board_uid: i32) -> Result<(), Error> {
+    match req.extensions().get::<BoardRestriction>() {
+        Some(BoardRestriction(allowed)) if !allowed.contains(&board_uid) => Err(Error(
+            error::ErrorForbidden("The provided access token can't be used with this board."),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Gets each of the user's personal access tokens. Their secrets aren't included.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("")]
+pub async fn all_tokens(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<Json<Vec<ApiToken>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the user making the request. Tokens can only be managed after logging in.
+    let matching_user: User = user_for_session(&conn, extract_bearer(&req)?.token)?;
+
+    // Return each of the user's tokens, newest first
+    Ok(Json(
+        api_tokens
+            .filter(schema::api_tokens::user_id.eq(matching_user.id))
+            .order(schema::api_tokens::created_at.desc())
+            .load(&conn)?,
+    ))
+}
+
+/// Mints a new personal access token for the user. The token's secret is only ever returned by
+/// this request, since only its hash is stored.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `token_req` - The name, scopes, expiry and boards of the new token
+#[post("")]
+pub async fn new_token(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+    mut token_req: Json<ApiTokenRequest>,
+) -> Result<Json<CreatedApiToken>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the user making the request. Tokens can only be minted after logging in.
+    let matching_user: User = user_for_session(&conn, extract_bearer(&req)?.token)?;
+
+    // Tokens need a name, so that users can tell them apart
+    if token_req.name.trim().is_empty() {
+        return Err(Error(error::ErrorBadRequest("Tokens must have a name.")));
+    }
+
+    // Tokens need at least one scope, and every scope needs to exist
+    if token_req.scopes.is_empty() {
+        return Err(Error(error::ErrorBadRequest(format!(
+            "Tokens must have at least one scope (one of {}).",
+            SCOPES.join(", ")
+        ))));
+    }
+
+    if let Some(unknown) = token_req
+        .scopes
+        .iter()
+        .find(|scope| !SCOPES.contains(&scope.as_str()))
+    {
+        return Err(Error(error::ErrorBadRequest(format!(
+            "Unknown scope '{}' (expected one of {}).",
+            unknown,
+            SCOPES.join(", ")
+        ))));
+    }
+
+    token_req.scopes.sort();
+    token_req.scopes.dedup();
+
+    // Tokens can't expire before they've been minted
+    let expires_at = match token_req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(Error(error::ErrorBadRequest(
+                "Tokens must last for at least a day.",
+            )))
+        }
+        Some(days) => Some(Utc::now().naive_utc() + Duration::days(days)),
+        None => None,
+    };
+
+    // Tokens can only be restricted to boards that the user can see
+    if let Some(board_ids) = &token_req.board_ids {
+        for board_uid in board_ids {
+            continue_if_allowed(&conn, *board_uid, Some(&matching_user), Capability::Read)?;
+        }
+    }
+
+    // Make the token, storing only a hash of its secret
+    let secret = format!("{}{}", TOKEN_PREFIX, random_token());
+
+    let token: ApiToken = diesel::insert_into(api_tokens)
+        .values(&NewApiToken {
+            user_id: matching_user.id,
+            name: token_req.name.trim(),
+            token_hash: &hash_token(&secret),
+            scopes: &token_req.scopes,
+            board_ids: token_req.board_ids.as_deref(),
+            expires_at,
+        })
+        .get_result(&conn)?;
+
+    Ok(Json(CreatedApiToken { token, secret }))
+}
+
+/// Revokes one of the user's personal access tokens, so that it can no longer be used.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `token_id` - The ID of the token that will be revoked
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/{token_id}")]
+pub async fn revoke_token(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    token_id: Path<i32>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the user making the request. Tokens can only be revoked after logging in.
+    let matching_user: User = user_for_session(&conn, extract_bearer(&req)?.token)?;
+
+    // Users can only revoke their own tokens
+    if diesel::delete(
+        api_tokens.filter(
+            schema::api_tokens::id
+                .eq(*token_id)
+                .and(schema::api_tokens::user_id.eq(matching_user.id)),
+        ),
+    )
+    .execute(&conn)?
+        == 0
+    {
+        return Err(Error(error::ErrorNotFound(format!(
+            "The requested token (id: {}) does not exist.",
+            *token_id
+        ))));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,386 @@
+use super::{
+    super::{
+        models::{
+            Board, BoardTransfer, NewBoardTransfer, NewPermission, OfferTransfer, User, ROLE_ADMIN,
+            ROLE_OWNER, TRANSFER_ACCEPTED, TRANSFER_CANCELLED, TRANSFER_DECLINED, TRANSFER_PENDING,
+        },
+        schema::{
+            self, board_transfers::dsl::board_transfers, boards::dsl::boards,
+            permissions::dsl::permissions, users::dsl::users,
+        },
+    },
+    access::{continue_if_allowed, Capability},
+    auth::user_for_token,
+    etag::tagged_response,
+    users::{extract_bearer, Error},
+};
+use actix_web::{
+    error,
+    web::{Data, HttpRequest, HttpResponse, Json, Path},
+};
+use diesel::{
+    dsl::{exists, select, update},
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    result::Error as DieselError,
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+
+/// Gets the pending transfer of the given board, returning a 404 if there isn't one.
+fn find_pending_transfer(conn: &PgConnection, board_uid: i32) -> Result<BoardTransfer, Error> {
+    match board_transfers
+        .filter(
+            schema::board_transfers::board_id
+                .eq(board_uid)
+                .and(schema::board_transfers::status.eq(TRANSFER_PENDING)),
+        )
+        .first(conn)
+        .optional()?
+    {
+        Some(transfer) => Ok(transfer),
+        None => Err(Error(error::ErrorNotFound(format!(
+            "The requested board (id: {}) has no pending transfer.",
+            board_uid
+        )))),
+    }
+}
+
+/// Ensures that the given transfer was offered to the given user.
+fn continue_if_recipient(transfer: &BoardTransfer, user: &User) -> Result<(), Error> {
+    if transfer.to_user_id != user.id {
+        Err(Error(error::ErrorUnauthorized(
+            "The provided access token does not match the user that this board was offered to.",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Marks a pending transfer with the given status, failing if somebody else has responded to it
+/// in the meantime. This should be called inside a transaction.
+fn respond_to_transfer(
+    conn: &PgConnection,
+    transfer: &BoardTransfer,
+    to: i16,
+) -> Result<(), DieselError> {
+    if update(
+        board_transfers.filter(
+            schema::board_transfers::id
+                .eq(transfer.id)
+                .and(schema::board_transfers::status.eq(TRANSFER_PENDING)),
+        ),
+    )
+    .set(schema::board_transfers::status.eq(to))
+    .execute(conn)?
+        == 0
+    {
+        return Err(DieselError::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Converts an error raised while responding to a transfer into a response. A missing row means
+/// that somebody else responded to the transfer, or the board changed hands, in the meantime.
+fn response_error(e: DieselError) -> Error {
+    match e {
+        DieselError::NotFound => Error(error::ErrorConflict("The transfer is no longer pending.")),
+        e => e.into(),
+    }
+}
+
+/// Offers a board to another user. Only the owner of the board may give it away, and the board
+/// only changes hands once the recipient accepts.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `offer` - A JSON request detailing which user the board will be offered to
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/{board_id}/transfer")]
+pub async fn offer_transfer(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    offer: Json<OfferTransfer>,
+    req: HttpRequest,
+) -> Result<Json<BoardTransfer>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to give the board away
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::TransferBoard,
+    )?;
+
+    // The owner already owns the board
+    if offer.user_id == matching_user.id {
+        return Err(Error(error::ErrorBadRequest(
+            "A board can't be transferred to its current owner.",
+        )));
+    }
+
+    // The recipient must exist
+    if !select(exists(users.find(offer.user_id))).get_result(&conn)? {
+        return Err(Error(error::ErrorNotFound(format!(
+            "The requested user (id: {}) does not exist.",
+            offer.user_id
+        ))));
+    }
+
+    // Put the transfer in the database, unless the board is already being transferred
+    match diesel::insert_into(board_transfers)
+        .values(&NewBoardTransfer {
+            board_id: *board_uid,
+            from_user_id: matching_user.id,
+            to_user_id: offer.user_id,
+        })
+        .on_conflict_do_nothing()
+        .get_result(&conn)
+        .optional()?
+    {
+        Some(transfer) => Ok(Json(transfer)),
+        None => Err(Error(error::ErrorConflict(format!(
+            "The requested board (id: {}) already has a pending transfer.",
+            *board_uid
+        )))),
+    }
+}
+
+/// Gets the pending transfer of a board. Only the owner of the board and the recipient of the
+/// transfer may view it.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("/{board_id}/transfer")]
+pub async fn pending_transfer(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    req: HttpRequest,
+) -> Result<Json<BoardTransfer>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Get the transfer, and make sure that the user is on one end of it
+    let transfer = find_pending_transfer(&conn, *board_uid)?;
+    if transfer.to_user_id != matching_user.id {
+        continue_if_allowed(
+            &conn,
+            *board_uid,
+            Some(&matching_user),
+            Capability::TransferBoard,
+        )?;
+    }
+
+    Ok(Json(transfer))
+}
+
+/// Withdraws the pending transfer of a board. Only the owner of the board may cancel it.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[delete("/{board_id}/transfer")]
+pub async fn cancel_transfer(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Ensure that the requesting user is allowed to give the board away
+    continue_if_allowed(
+        &conn,
+        *board_uid,
+        Some(&matching_user),
+        Capability::TransferBoard,
+    )?;
+
+    // Withdraw the transfer, unless the recipient got to it first
+    let transfer = find_pending_transfer(&conn, *board_uid)?;
+    respond_to_transfer(&conn, &transfer, TRANSFER_CANCELLED).map_err(response_error)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Accepts the pending transfer of a board, making the authenticated user its owner. The previous
+/// owner stays on as an admin of the board.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/{board_id}/transfer/accept")]
+pub async fn accept_transfer(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Get the transfer, and make sure that it was offered to the user
+    let transfer = find_pending_transfer(&conn, *board_uid)?;
+    continue_if_recipient(&transfer, &matching_user)?;
+
+    // Hand the board over, all at once
+    let written_board: Board = conn
+        .transaction::<_, DieselError, _>(|| {
+            respond_to_transfer(&conn, &transfer, TRANSFER_ACCEPTED)?;
+
+            // Change the board's owner, as long as the board hasn't changed hands in the meantime
+            let written_board: Board = update(
+                boards.filter(
+                    schema::boards::id
+                        .eq(*board_uid)
+                        .and(schema::boards::user_id.eq(transfer.from_user_id)),
+                ),
+            )
+            .set((
+                schema::boards::user_id.eq(matching_user.id),
+                schema::boards::version.eq(schema::boards::version + 1),
+                schema::boards::updated_by.eq(Some(matching_user.id)),
+            ))
+            .get_result(&conn)?;
+
+            // Make the recipient the owner of the board
+            diesel::insert_into(permissions)
+                .values(&NewPermission {
+                    user_id: matching_user.id,
+                    board_id: *board_uid,
+                    role: ROLE_OWNER,
+                    updated_by: Some(matching_user.id),
+                })
+                .on_conflict((schema::permissions::user_id, schema::permissions::board_id))
+                .do_update()
+                .set((
+                    schema::permissions::role.eq(ROLE_OWNER),
+                    schema::permissions::updated_by.eq(Some(matching_user.id)),
+                ))
+                .execute(&conn)?;
+
+            // Keep the previous owner on as an admin
+            update(
+                permissions.filter(
+                    schema::permissions::board_id
+                        .eq(*board_uid)
+                        .and(schema::permissions::user_id.eq(transfer.from_user_id)),
+                ),
+            )
+            .set((
+                schema::permissions::role.eq(ROLE_ADMIN),
+                schema::permissions::updated_by.eq(Some(matching_user.id)),
+            ))
+            .execute(&conn)?;
+
+            Ok(written_board)
+        })
+        .map_err(response_error)?;
+
+    // Return the board along with its new ETag
+    Ok(tagged_response(&written_board, written_board.version))
+}
+
+/// Declines the pending transfer of a board. Only the recipient of the transfer may decline it.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `board_uid` - The ID of the requested board
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/{board_id}/transfer/decline")]
+pub async fn decline_transfer(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    board_uid: Path<i32>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Get the transfer, and make sure that it was offered to the user
+    let transfer = find_pending_transfer(&conn, *board_uid)?;
+    continue_if_recipient(&transfer, &matching_user)?;
+
+    respond_to_transfer(&conn, &transfer, TRANSFER_DECLINED).map_err(response_error)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Gets a list of the pending transfers that have been offered to the authenticated user.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("/transfers")]
+pub async fn incoming_transfers(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<Json<Vec<BoardTransfer>>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get an authorization token from the headers sent with the request
+    let token = extract_bearer(&req)?;
+
+    // Get the user making the request
+    let matching_user: User = user_for_token(&conn, token)?;
+
+    // Return each of the transfers offered to the user
+    Ok(Json(
+        board_transfers
+            .filter(
+                schema::board_transfers::to_user_id
+                    .eq(matching_user.id)
+                    .and(schema::board_transfers::status.eq(TRANSFER_PENDING)),
+            )
+            .order(schema::board_transfers::id.asc())
+            .get_results(&conn)?,
+    ))
+}
@@ -0,0 +1,590 @@
+use super::{
+    super::{
+        crypto::SecretKey,
+        models::{
+            ChallengeResponse, IssuedSession, LoginOutcome, NewRecoveryCode,
+            NewTwoFactorCredential, RecoveryCodes, TwoFactorChallenge, TwoFactorCode,
+            TwoFactorCredential, TwoFactorEnrollment, TwoFactorStatus, User,
+        },
+        schema::{
+            self, recovery_codes::dsl::recovery_codes,
+            two_factor_credentials::dsl::two_factor_credentials, users::dsl::users,
+        },
+        totp::{base32_encode, Totp},
+    },
+    auth::{session_for_token, start_session, user_agent},
+    users::{extract_bearer, hash_token, Error},
+};
+use actix_session::Session;
+use actix_web::{
+    error,
+    web::{Data, HttpRequest, HttpResponse, Json},
+};
+use chrono::{Duration, Utc};
+use diesel::{
+    dsl::update,
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool},
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use rand::{rngs::OsRng, RngCore};
+
+/// The name that authenticator apps list Notedly accounts under.
+const ISSUER: &str = "Notedly";
+
+/// What TOTP secrets are encrypted for.
+const SECRET_PURPOSE: &str = "totp-secret";
+
+/// What challenge tokens are signed for.
+const CHALLENGE_PURPOSE: &str = "two-factor-challenge";
+
+/// The number of minutes that a user has to complete a challenge after entering their first
+/// factor.
+const CHALLENGE_LIFETIME_MINS: i64 = 5;
+
+/// The number of recovery codes that a user is given at a time.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// The number of incorrect codes that can be entered in a row before the user is locked out for a
+/// while, so that codes can't be guessed.
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+
+/// The number of minutes that a user is locked out for after too many incorrect codes.
+const LOCKOUT_MINS: i64 = 15;
+
+/// Gets the message that a challenge token signs.
+fn challenge_contents(usr_id: i32, identity_uid: Option<i32>, expiry: i64) -> String {
+    format!(
+        "{}:{}:{}",
+        usr_id,
+        identity_uid.map(|id| id.to_string()).unwrap_or_default(),
+        expiry
+    )
+}
+
+/// Generates a new challenge for the given user, as a signed token of the form
+/// `user_id.identity_id.expiry.signature` (the identity's ID is empty for password logins).
+fn issue_challenge(key: &SecretKey, usr_id: i32, identity_uid: Option<i32>) -> TwoFactorChallenge {
+    let expires_at = Utc::now().naive_utc() + Duration::minutes(CHALLENGE_LIFETIME_MINS);
+    let expiry = expires_at.timestamp();
+    let sig = key.sign(
+        CHALLENGE_PURPOSE,
+        &challenge_contents(usr_id, identity_uid, expiry),
+    );
+
+    TwoFactorChallenge {
+        challenge: format!(
+            "{}.{}.{}.{}",
+            usr_id,
+            identity_uid.map(|id| id.to_string()).unwrap_or_default(),
+            expiry,
+            sig
+        ),
+        expires_at,
+    }
+}
+
+/// Gets the user and identity that the given challenge was issued for, returning a 401 if the
+/// challenge is invalid or has expired.
+fn redeem_challenge(key: &SecretKey, challenge: &str) -> Result<(i32, Option<i32>), Error> {
+    let invalid = || {
+        Error(error::ErrorUnauthorized(
+            "The provided challenge is invalid or has expired. Log in again.",
+        ))
+    };
+
+    let mut parts = challenge.splitn(4, '.');
+    let (usr_id, identity, expiry, sig) = match (
+        parts.next().and_then(|part| part.parse::<i32>().ok()),
+        parts.next(),
+        parts.next().and_then(|part| part.parse::<i64>().ok()),
+        parts.next(),
+    ) {
+        (Some(usr_id), Some(identity), Some(expiry), Some(sig)) => (usr_id, identity, expiry, sig),
+        _ => return Err(invalid()),
+    };
+
+    // Password logins don't have an identity
+    let identity_uid = match identity {
+        "" => None,
+        id => Some(id.parse::<i32>().map_err(|_| invalid())?),
+    };
+
+    if expiry <= Utc::now().timestamp()
+        || !key.verify(
+            CHALLENGE_PURPOSE,
+            &challenge_contents(usr_id, identity_uid, expiry),
+            sig,
+        )
+    {
+        return Err(invalid());
+    }
+
+    Ok((usr_id, identity_uid))
+}
+
+/// Finishes logging a user in once their first factor (a password or provider account) has been
+/// checked. Users with two-factor authentication enabled are given a challenge instead of a
+/// session, which they complete at /auth/2fa/challenge.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the session will be stored with
+/// * `key` - The key that the challenge will be signed with
+/// * `usr_id` - The ID of the user that logged in
+/// * `user_agent` - The user agent of the device that the user logged in on
+/// * `identity_uid` - The ID of the provider identity that the user logged in with, if any
+pub(crate) fn finish_login(
+    conn: &PgConnection,
+    key: &SecretKey,
+    usr_id: i32,
+    user_agent: &str,
+    identity_uid: Option<i32>,
+) -> Result<LoginOutcome, Error> {
+    if enabled_credential(conn, usr_id)?.is_some() {
+        return Ok(LoginOutcome::Challenge(issue_challenge(
+            key,
+            usr_id,
+            identity_uid,
+        )));
+    }
+
+    let (token, started) = start_session(conn, usr_id, user_agent, identity_uid)?;
+
+    Ok(LoginOutcome::Session(IssuedSession {
+        token,
+        expires_at: started.expires_at,
+    }))
+}
+
+/// Gets the user's two-factor credential, if they've confirmed it.
+fn enabled_credential(
+    conn: &PgConnection,
+    usr_id: i32,
+) -> Result<Option<TwoFactorCredential>, Error> {
+    Ok(two_factor_credentials
+        .filter(
+            schema::two_factor_credentials::user_id
+                .eq(usr_id)
+                .and(schema::two_factor_credentials::enabled.eq(true)),
+        )
+        .first(conn)
+        .optional()?)
+}
+
+/// Gets the user's two-factor credential, returning a 404 if they haven't confirmed one.
+fn require_enabled_credential(
+    conn: &PgConnection,
+    usr_id: i32,
+) -> Result<TwoFactorCredential, Error> {
+    enabled_credential(conn, usr_id)?.ok_or_else(|| {
+        Error(error::ErrorNotFound(
+            "Two-factor authentication isn't enabled for this account.",
+        ))
+    })
+}
+
+/// Decrypts the TOTP secret of the given credential.
+fn totp_for(key: &SecretKey, credential: &TwoFactorCredential) -> Result<Totp, Error> {
+    key.decrypt(SECRET_PURPOSE, &credential.secret)
+        .and_then(|secret| Totp::from_base32(&secret))
+        .ok_or_else(|| {
+            Error(error::ErrorInternalServerError(
+                "The stored two-factor secret can't be read.",
+            ))
+        })
+}
+
+/// Puts a recovery code into the form that its hash is made from, so that users can type it with
+/// any case or spacing.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Replaces each of the user's recovery codes with a fresh set, returning the new codes.
+fn replace_recovery_codes(conn: &PgConnection, usr_id: i32) -> Result<Vec<String>, Error> {
+    // Each code holds 50 random bits, written as two groups of 5 base32 characters
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+
+            let encoded = base32_encode(&bytes).to_lowercase();
+            format!("{}-{}", &encoded[..5], &encoded[5..10])
+        })
+        .collect();
+
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+
+    conn.transaction::<_, Error, _>(|| {
+        diesel::delete(recovery_codes.filter(schema::recovery_codes::user_id.eq(usr_id)))
+            .execute(conn)?;
+
+        diesel::insert_into(recovery_codes)
+            .values(
+                &hashes
+                    .iter()
+                    .map(|hash| NewRecoveryCode {
+                        user_id: usr_id,
+                        code_hash: hash,
+                    })
+                    .collect::<Vec<NewRecoveryCode>>(),
+            )
+            .execute(conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(codes)
+}
+
+/// Ensures that the given code is either the current code from the user's authenticator app, or
+/// one of their unused recovery codes (which is used up). Codes can't be replayed, and users are
+/// locked out for a while after too many incorrect codes.
+///
+/// The credential is locked while the code is checked and the attempt is recorded, so that
+/// concurrent attempts can't all slip in under the limit.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the credential will be updated with
+/// * `key` - The key that the credential's secret was encrypted with
+/// * `credential` - The user's two-factor credential
+/// * `code` - The code that the user entered
+fn continue_if_valid_code(
+    conn: &PgConnection,
+    key: &SecretKey,
+    credential: &TwoFactorCredential,
+    code: &str,
+) -> Result<(), Error> {
+    let now = Utc::now();
+
+    let accepted = conn.transaction::<_, Error, _>(|| {
+        // Get the latest copy of the credential, and hold on to it until the attempt is recorded
+        let credential: TwoFactorCredential = two_factor_credentials
+            .find(credential.user_id)
+            .for_update()
+            .first(conn)?;
+
+        // Stop codes from being guessed
+        if credential.failed_attempts >= MAX_FAILED_ATTEMPTS
+            && credential.last_failed_at.map_or(false, |t| {
+                t > now.naive_utc() - Duration::minutes(LOCKOUT_MINS)
+            })
+        {
+            return Err(Error(error::ErrorTooManyRequests(
+                "Too many incorrect codes have been entered. Try again later.",
+            )));
+        }
+
+        let accepted = match totp_for(key, &credential)?.verify(
+            code,
+            now.timestamp() as u64,
+            Some(credential.last_used_step as u64),
+        ) {
+            // Only accept the code if no other request has used it (or a later one)
+            Some(step) => {
+                update(
+                    two_factor_credentials
+                        .find(credential.user_id)
+                        .filter(schema::two_factor_credentials::last_used_step.lt(step as i64)),
+                )
+                .set(schema::two_factor_credentials::last_used_step.eq(step as i64))
+                .execute(conn)?
+                    == 1
+            }
+
+            // Recovery codes can only be used once, and only once the credential is confirmed
+            None if credential.enabled => {
+                diesel::delete(
+                    recovery_codes.filter(
+                        schema::recovery_codes::user_id.eq(credential.user_id).and(
+                            schema::recovery_codes::code_hash
+                                .eq(hash_token(&normalize_recovery_code(code))),
+                        ),
+                    ),
+                )
+                .execute(conn)?
+                    == 1
+            }
+            None => false,
+        };
+
+        if accepted {
+            update(two_factor_credentials.find(credential.user_id))
+                .set(schema::two_factor_credentials::failed_attempts.eq(0))
+                .execute(conn)?;
+        } else {
+            update(two_factor_credentials.find(credential.user_id))
+                .set((
+                    schema::two_factor_credentials::failed_attempts
+                        .eq(schema::two_factor_credentials::failed_attempts + 1),
+                    schema::two_factor_credentials::last_failed_at.eq(Some(now.naive_utc())),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok(accepted)
+    })?;
+
+    if accepted {
+        Ok(())
+    } else {
+        Err(Error(error::ErrorUnauthorized(
+            "The provided code is incorrect.",
+        )))
+    }
+}
+
+/// Gets whether or not the user has two-factor authentication enabled, and how many recovery
+/// codes they have left.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[get("/2fa")]
+pub async fn two_factor_status(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    req: HttpRequest,
+) -> Result<Json<TwoFactorStatus>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    Ok(Json(TwoFactorStatus {
+        enabled: enabled_credential(&conn, current.user_id)?.is_some(),
+        recovery_codes_left: recovery_codes
+            .filter(schema::recovery_codes::user_id.eq(current.user_id))
+            .count()
+            .get_result(&conn)?,
+    }))
+}
+
+/// Starts enrolling the user in two-factor authentication, returning a new TOTP secret for their
+/// authenticator app. The user isn't challenged on login until they confirm the secret with a
+/// code from their app.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the secret will be encrypted with
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+#[post("/2fa/enroll")]
+pub async fn enroll(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    req: HttpRequest,
+) -> Result<Json<TwoFactorEnrollment>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+    let u: User = users.find(current.user_id).first(&conn)?;
+
+    let totp = Totp::generate();
+
+    // Replace any enrollment that the user didn't finish
+    conn.transaction::<_, Error, _>(|| {
+        if enabled_credential(&conn, u.id)?.is_some() {
+            return Err(Error(error::ErrorConflict(
+                "Two-factor authentication is already enabled for this account.",
+            )));
+        }
+
+        diesel::delete(two_factor_credentials.find(u.id)).execute(&conn)?;
+
+        diesel::insert_into(two_factor_credentials)
+            .values(&NewTwoFactorCredential {
+                user_id: u.id,
+                secret: &key.encrypt(SECRET_PURPOSE, &totp.to_base32()),
+            })
+            .execute(&conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(Json(TwoFactorEnrollment {
+        secret: totp.to_base32(),
+        provisioning_uri: totp.provisioning_uri(ISSUER, &u.email),
+    }))
+}
+
+/// Finishes enrolling the user in two-factor authentication with a code from their authenticator
+/// app, returning their recovery codes. The user is challenged on every login from then on.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the secret was encrypted with
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `code` - A code from the user's authenticator app
+#[post("/2fa/confirm")]
+pub async fn confirm(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    req: HttpRequest,
+    code: Json<TwoFactorCode>,
+) -> Result<Json<RecoveryCodes>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    // Get the secret that the user is enrolling with
+    let credential: TwoFactorCredential = match two_factor_credentials
+        .find(current.user_id)
+        .first::<TwoFactorCredential>(&conn)
+        .optional()?
+    {
+        Some(c) if c.enabled => {
+            return Err(Error(error::ErrorConflict(
+                "Two-factor authentication is already enabled for this account.",
+            )))
+        }
+        Some(c) => c,
+        None => {
+            return Err(Error(error::ErrorNotFound(
+                "Start enrolling in two-factor authentication first.",
+            )))
+        }
+    };
+
+    // Make sure that the user's app generates the same codes as us
+    continue_if_valid_code(&conn, &key, &credential, &code.code)?;
+
+    update(two_factor_credentials.find(current.user_id))
+        .set(schema::two_factor_credentials::enabled.eq(true))
+        .execute(&conn)?;
+
+    Ok(Json(RecoveryCodes {
+        recovery_codes: replace_recovery_codes(&conn, current.user_id)?,
+    }))
+}
+
+/// Replaces the user's recovery codes with a fresh set, which is returned. The old codes stop
+/// working.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the secret was encrypted with
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `code` - A code from the user's authenticator app, or one of their recovery codes
+#[post("/2fa/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    req: HttpRequest,
+    code: Json<TwoFactorCode>,
+) -> Result<Json<RecoveryCodes>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    // Make sure that the user still has their second factor
+    let credential = require_enabled_credential(&conn, current.user_id)?;
+    continue_if_valid_code(&conn, &key, &credential, &code.code)?;
+
+    Ok(Json(RecoveryCodes {
+        recovery_codes: replace_recovery_codes(&conn, current.user_id)?,
+    }))
+}
+
+/// Turns off two-factor authentication for the user, deleting their secret and recovery codes.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the secret was encrypted with
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
+/// token (required) of the user
+/// * `code` - A code from the user's authenticator app, or one of their recovery codes
+#[delete("/2fa")]
+pub async fn disable(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    req: HttpRequest,
+    code: Json<TwoFactorCode>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the session making the request
+    let current = session_for_token(&conn, extract_bearer(&req)?.token)?;
+
+    // A stolen session alone can't be used to turn off the second factor
+    let credential = require_enabled_credential(&conn, current.user_id)?;
+    continue_if_valid_code(&conn, &key, &credential, &code.code)?;
+
+    conn.transaction::<_, Error, _>(|| {
+        diesel::delete(recovery_codes.filter(schema::recovery_codes::user_id.eq(current.user_id)))
+            .execute(&conn)?;
+        diesel::delete(two_factor_credentials.find(current.user_id)).execute(&conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Completes the challenge that a user with two-factor authentication was given when they logged
+/// in, starting their session.
+///
+/// # Arguments
+///
+/// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `key` - The key that the challenge was signed with
+/// * `session` - The user's cookie session, which the new session token is stored in
+/// * `req` - An HTTP request provided by the caller of this method. Used to obtain the user agent
+/// of the device that the user is logging in on
+/// * `response` - The challenge, and a code from the user's authenticator app or one of their
+/// recovery codes
+#[post("/2fa/challenge")]
+pub async fn complete_challenge(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    key: Data<SecretKey>,
+    session: Session,
+    req: HttpRequest,
+    response: Json<ChallengeResponse>,
+) -> Result<Json<IssuedSession>, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the user that passed their first factor
+    let (usr_id, identity_uid) = redeem_challenge(&key, &response.challenge)?;
+
+    // Users that turned two-factor authentication off after being challenged don't need a code
+    if let Some(credential) = enabled_credential(&conn, usr_id)? {
+        continue_if_valid_code(&conn, &key, &credential, &response.code)?;
+    }
+
+    // Start a new session for the device that the user logged in on
+    let (token, started) = start_session(&conn, usr_id, user_agent(&req), identity_uid)?;
+
+    // Save the token in a session cookie
+    session
+        .set::<String>("token", token.clone())
+        .map_err(Error)?;
+
+    Ok(Json(IssuedSession {
+        token,
+        expires_at: started.expires_at,
+    }))
+}
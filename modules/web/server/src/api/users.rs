@@ -1,11 +1,17 @@
-use super::super::{
-    models::{Board, Note, Permission, User},
-    schema::{self, users::dsl::*},
+use super::{
+    super::{
+        models::{Board, Note, Permission, User},
+        schema::{self, users::dsl::*},
+    },
+    access::continue_if_authenticated,
+    auth::user_for_token,
+    identities::{identities, unlink_identity},
+    listing::{page_response, BoardFilter, Keyed, NoteFilter, Page, Sort, UserFilter},
 };
 use actix_web::{
     error,
-    web::{Data, HttpRequest, Json, Path},
-    Error as ActixError, Scope as ActixScope,
+    web::{Data, HttpRequest, Json, Path, Query},
+    Error as ActixError, HttpResponse, Scope as ActixScope,
 };
 use diesel::{
     pg::PgConnection,
@@ -38,6 +44,8 @@ pub fn build_service_group() -> ActixScope {
     ActixScope::new("/users")
         .service(all_user_ids)
         .service(user)
+        .service(identities)
+        .service(unlink_identity)
         .service(user_with_id)
         .service(boards_from_user_with_id)
         .service(notes_from_user_with_id)
@@ -45,22 +53,31 @@ pub fn build_service_group() -> ActixScope {
         .service(permission_for_user_with_board)
 }
 
-/// Gets the user's oauth bearer token from the given HTTP request. If the token isn't found, an
-/// error is returned.
+/// A bearer token, along with the request that it was sent with. Personal access tokens can only
+/// be used for some requests, so the request is kept around to check the token against.
+#[derive(Clone, Copy)]
+pub(crate) struct Bearer<'a> {
+    /// The bearer token itself
+    pub token: &'a str,
+
+    /// The request that the token was sent with
+    pub req: &'a HttpRequest,
+}
+
+/// Gets the user's bearer token from the given HTTP request. If the token isn't found, an error
+/// is returned.
 ///
 /// # Arguments
 ///
 /// * `req` - The user's request
-pub(crate) fn extract_bearer(req: &HttpRequest) -> Result<&'_ str, Error> {
+pub(crate) fn extract_bearer(req: &HttpRequest) -> Result<Bearer<'_>, Error> {
     // First, check that the key even exists in the request's headers
     if let Some(bearer_token) = req.headers().get("Authorization") {
-        // Remove the "Bearer " prefix from the header value
-        if let Some(split_token) = bearer_token.to_str()?.split(' ').last() {
-            Ok(split_token) // Return the token as a string
-        } else {
-            // The token value doesn't exist, so just return an empty string
-            Ok("")
-        }
+        // Remove the "Bearer " prefix from the header value. If the token value doesn't exist,
+        // just use an empty string.
+        let token = bearer_token.to_str()?.split(' ').last().unwrap_or("");
+
+        Ok(Bearer { token, req }) // Return the token along with its request
     } else {
         // Return error describing this discrepancy
         Err(Error(error::ErrorUnauthorized(
@@ -69,6 +86,26 @@ pub(crate) fn extract_bearer(req: &HttpRequest) -> Result<&'_ str, Error> {
     }
 }
 
+/// Gets the user that made the given request, if the request carries a bearer token. Requests
+/// without a token are treated as anonymous, but a token that doesn't match any user is still
+/// rejected.
+///
+/// # Arguments
+///
+/// * `conn` - The connection that the user will be read with
+/// * `req` - The user's request
+pub(crate) fn extract_optional_user(
+    conn: &PgConnection,
+    req: &HttpRequest,
+) -> Result<Option<User>, Error> {
+    // Anonymous requests don't have an authorization header at all
+    if req.headers().get("Authorization").is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(user_for_token(conn, extract_bearer(req)?)?))
+}
+
 /// Hashes the inputted string via sha3 256.
 ///
 /// # Arguments
@@ -83,8 +120,7 @@ pub(crate) fn hash_token(token: &str) -> String {
     hex::encode(token_hasher.result())
 }
 
-/// Gets the user with the given oauth token (much, much slower than with_id, since this involves
-/// filters).
+/// Gets the user that the provided session token was issued to.
 ///
 /// # Arguments
 ///
@@ -101,15 +137,8 @@ pub async fn user(
     // Get an authorization token from the headers sent with the request
     let token = extract_bearer(&req)?;
 
-    // Get and return the matching user. If the user doesn't exist, 404
-    Ok(Json(
-        match users.filter(oauth_token.eq(hash_token(token))).first(&conn) {
-            Ok(u) => Ok(u),
-            Err(_) => Err(Error(error::ErrorNotFound(format!(
-                "The requested user does not exist.",
-            )))),
-        }?,
-    ))
+    // Get and return the matching user
+    Ok(Json(user_for_token(&conn, token)?))
 }
 
 /// Gets a list of boards belonging to a user with the given ID.
@@ -122,12 +151,18 @@ pub async fn user(
 /// integer
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
+/// * `page` - Which page of results to return (e.g. ?limit=50&cursor=...)
+/// * `filter` - Which results to return (e.g. ?title_contains=roadmap)
 #[get("/{user_id}/boards")]
 pub async fn boards_from_user_with_id(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     user_id: Path<i32>,
     req: HttpRequest,
-) -> Result<Json<Vec<i32>>, Error> {
+    sort: Query<Sort>,
+    page: Query<Page>,
+    filter: Query<BoardFilter>,
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel.
     let conn = pool.get()?;
 
@@ -144,20 +179,14 @@ pub async fn boards_from_user_with_id(
     }?;
 
     // Check that the provided access token matches the one on file
-    if u.oauth_token == hash_token(token) {
-        // Get the ID of each board, and return a vector of these IDs
-        Ok(Json(
-            Board::belonging_to(&u)
-                .select(schema::boards::id)
-                .load::<i32>(&conn)?,
-        ))
-    } else {
-        // The codes don't match, communicate this discrepancy through
-        // a 300 (unauth) error
-        Err(Error(error::ErrorUnauthorized(
-            "The provided access code does not match the recorded token.",
-        )))
-    }
+    continue_if_authenticated(&conn, &u, token)?;
+
+    // Get the ID of each board matching the filter, and return a page of these IDs
+    let owned = filter.apply(Board::belonging_to(&u).into_boxed());
+    let rows = crate::paginated!(owned, boards, schema::boards::id, *sort, &page)?
+        .load::<Keyed<i32>>(&conn)?;
+
+    Ok(page_response(&req, rows, *sort, &page))
 }
 
 /// Gets a list of notes belonging to a user with the given ID.
@@ -170,12 +199,18 @@ pub async fn boards_from_user_with_id(
 /// integer
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
+/// * `page` - Which page of results to return (e.g. ?limit=50&cursor=...)
+/// * `filter` - Which results to return (e.g. ?board_id=7&updated_since=2020-05-27T00:00:00Z)
 #[get("/{user_id}/notes")]
 pub async fn notes_from_user_with_id(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     user_id: Path<i32>,
     req: HttpRequest,
-) -> Result<Json<Vec<i32>>, Error> {
+    sort: Query<Sort>,
+    page: Query<Page>,
+    filter: Query<NoteFilter>,
+) -> Result<HttpResponse, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel.
     let conn = pool.get()?;
 
@@ -192,20 +227,14 @@ pub async fn notes_from_user_with_id(
     }?;
 
     // Check that the provided access token matches the one on file
-    if u.oauth_token == hash_token(token) {
-        // Get the ID of each note, and return a vector of these IDs
-        Ok(Json(
-            Note::belonging_to(&u)
-                .select(schema::notes::id)
-                .load::<i32>(&conn)?,
-        ))
-    } else {
-        // The codes don't match, communicate this discrepancy through
-        // a 300 (unauth) error
-        Err(Error(error::ErrorUnauthorized(
-            "The provided access code does not match the recorded token.",
-        )))
-    }
+    continue_if_authenticated(&conn, &u, token)?;
+
+    // Get the ID of each note matching the filter, and return a page of these IDs
+    let written = filter.apply(Note::belonging_to(&u).into_boxed());
+    let rows = crate::paginated!(written, notes, schema::notes::id, *sort, &page)?
+        .load::<Keyed<i32>>(&conn)?;
+
+    Ok(page_response(&req, rows, *sort, &page))
 }
 
 /// Gets a list of permissions assigned to a user with the given ID.
@@ -218,11 +247,13 @@ pub async fn notes_from_user_with_id(
 /// integer
 /// * `req` - An HTTP request provided by the caller of this method. Used to obtain the bearer
 /// token (required) of the user
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
 #[get("/{user_id}/assignments")]
 pub async fn permissions_for_user_with_id(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
     user_id: Path<i32>,
     req: HttpRequest,
+    sort: Query<Sort>,
 ) -> Result<Json<Vec<Permission>>, Error> {
     // Get a connection from the provided connection pool, so we can start using diesel
     let conn = pool.get()?;
@@ -240,15 +271,16 @@ pub async fn permissions_for_user_with_id(
     }?;
 
     // Check that the provided access token matches the one on file
-    if u.oauth_token == hash_token(token) {
-        Ok(Json(Permission::belonging_to(&u).get_results(&conn)?))
-    } else {
-        // The codes don't match, communicate this discrepancy through
-        // a 300 (unauth) error
-        Err(Error(error::ErrorUnauthorized(
-            "The provided access code does not match the recoded token.",
-        )))
-    }
+    continue_if_authenticated(&conn, &u, token)?;
+
+    Ok(Json(
+        crate::sorted!(
+            Permission::belonging_to(&u).into_boxed(),
+            permissions,
+            sort.into_inner()
+        )
+        .get_results(&conn)?,
+    ))
 }
 
 /// Gets a specific permission.
@@ -283,26 +315,20 @@ pub async fn permission_for_user_with_board(
     }?;
 
     // Check that the provided access token matches the one on file
-    if u.oauth_token == hash_token(token) {
-        Ok(Json(
-            match schema::permissions::dsl::permissions
-                .filter(
-                    schema::permissions::board_id
-                        .eq(context.1)
-                        .and(schema::permissions::user_id.eq(u.id)),
-                )
-                .first(&conn){
-                    Ok(perm) => Ok(perm),
-                    Err(_) => Err(Error(error::ErrorNotFound(format!("The requested assignment belonging to the requested board (id: {}) and user (id: {}) does not exist.", context.0, context.1))))
-                }?,
-        ))
-    } else {
-        // The codes don't match, communicate this discrepancy through
-        // a 300 (unauth) error
-        Err(Error(error::ErrorUnauthorized(
-            "The provided access code does not match the recoded token.",
-        )))
-    }
+    continue_if_authenticated(&conn, &u, token)?;
+
+    Ok(Json(
+        match schema::permissions::dsl::permissions
+            .filter(
+                schema::permissions::board_id
+                    .eq(context.1)
+                    .and(schema::permissions::user_id.eq(u.id)),
+            )
+            .first(&conn){
+                Ok(perm) => Ok(perm),
+                Err(_) => Err(Error(error::ErrorNotFound(format!("The requested assignment belonging to the requested board (id: {}) and user (id: {}) does not exist.", context.0, context.1))))
+            }?,
+    ))
 }
 
 /// Gets the user with given oauth token and ID from the database.
@@ -337,16 +363,10 @@ pub async fn user_with_id(
     }?;
 
     // Check that the provided access token matches the one on file
-    if u.oauth_token == hash_token(token) {
-        // Return the user's details
-        Ok(Json(u))
-    } else {
-        // The codes don't match, communicate this discrepancy through
-        // a 300 (unauth) error
-        Err(Error(error::ErrorUnauthorized(
-            "The provided access code does not match the recorded token.",
-        )))
-    }
+    continue_if_authenticated(&conn, &u, token)?;
+
+    // Return the user's details
+    Ok(Json(u))
 }
 
 /// Gets the usernames of each user in the database.
@@ -354,24 +374,25 @@ pub async fn user_with_id(
 /// # Arguments
 ///
 /// * `pool` - The connection pool that will be used to connect to the postgres database
+/// * `req` - An HTTP request provided by the caller of this method. Used to build the link to the
+/// next page
+/// * `sort` - How the results are sorted (e.g. ?sort=updated_at&order=desc)
+/// * `page` - Which page of results to return (e.g. ?limit=50&cursor=...)
+/// * `filter` - Which results to return (e.g. ?updated_since=2020-05-27T00:00:00Z)
 #[get("")]
 pub async fn all_user_ids(
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> Result<Json<Vec<i32>>, Error> {
-    match pool.get() {
-        // We were able to connect to the database, get the requested data (all usernames)
-        Ok(conn) => {
-            // Get the ID of each user, and return an appropriate repsonse
-            match users.select(id).load::<i32>(&conn) {
-                // Respond with each of the user IDs
-                Ok(user_ids) => Ok(Json(user_ids)),
-
-                // Since an error was found, return a 300
-                Err(e) => Err(Error(error::ErrorInternalServerError(e))),
-            }
-        }
-
-        // Since an error was found, return a 300
-        Err(e) => Err(Error(error::ErrorInternalServerError(e))),
-    }
+    req: HttpRequest,
+    sort: Query<Sort>,
+    page: Query<Page>,
+    filter: Query<UserFilter>,
+) -> Result<HttpResponse, Error> {
+    // Get a connection from the provided connection pool, so we can start using diesel
+    let conn = pool.get()?;
+
+    // Get the ID of each user matching the filter, and respond with a page of these IDs
+    let matching = filter.apply(users.into_boxed());
+    let rows = crate::paginated!(matching, users, id, *sort, &page)?.load::<Keyed<i32>>(&conn)?;
+
+    Ok(page_response(&req, rows, *sort, &page))
 }
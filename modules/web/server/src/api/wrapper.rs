@@ -1,20 +1,22 @@
+use super::{oidc::Identity, providers::Provider};
 use actix_web::{client::Client, error, Error};
 use serde::{Deserialize, Serialize};
-use std::{default::Default, io};
+use serde_json::Value;
+use std::default::Default;
 
-/// A wrapper for each of the respective oauth provider APIs (Google, GitHub).
-pub struct User {
+/// A wrapper for the APIs of the oauth provider that a user logged in with.
+pub struct User<'a> {
     /// The access token associated with the user
     access_token: String,
 
-    /// Cached results for the /email GET
-    email: String,
-
     /// The provider of the authorization service
-    provider: String,
+    provider: &'a Provider,
 
     /// The HTTP client used to make requests
     client: Client,
+
+    /// The identity of the user, once verified by the provider
+    identity: Option<Identity>,
 }
 
 /// A response from the GitHub API for the user's emails.
@@ -33,10 +35,10 @@ impl GitHubEmailResponse {
         Self { emails } // Return the new instance
     }
 
-    /// Gets the most suitable email of the available GitHub emails.
-    pub fn best_email(&self) -> &GitHubEmail {
-        // We'll use the first email until we find a better one
-        let mut best: &GitHubEmail = &self.emails[0];
+    /// Gets the most suitable verified email of the available GitHub emails.
+    pub fn best_email(&self) -> Option<&GitHubEmail> {
+        // We'll only use a verified email, and prefer the primary one
+        let mut best: Option<&GitHubEmail> = None;
 
         // Iterate through the emails
         for i in 0..self.emails.len() {
@@ -44,7 +46,7 @@ impl GitHubEmailResponse {
 
             // Check if the email is at least verified
             if gh_email.verified {
-                best = gh_email; // Set the new best email
+                best = Some(gh_email); // Set the new best email
 
                 if gh_email.primary {
                     break; // Stop, use the best possible email
@@ -73,114 +75,115 @@ struct GitHubEmail {
     visibility: String,
 }
 
-/// A GitHub user ID.
-#[derive(Serialize, Deserialize)]
-struct GitHubIDResponse {
-    /// The ID of the user
-    id: i32,
-}
-
-impl User {
+impl<'a> User<'a> {
     /// Initializes a new user from the given access token and provider.
-    pub fn new(access_token: String, provider: String) -> Self {
+    ///
+    /// # Arguments
+    ///
+    /// * `access_token` - The access token issued to the user by the provider
+    /// * `provider` - The provider that the user logged in with
+    pub fn new(access_token: String, provider: &'a Provider) -> Self {
         Self {
-            email: "".to_owned(),
             access_token,
             provider,
             client: Client::default(),
+            identity: None,
         } // Return the new instance
     }
 
-    /// Gets the URL of the account's oauth provider.
+    /// Verifies the ID token issued alongside the user's access token, and fetches the user's
+    /// identity from the OpenID Connect provider. Must be called before the oauth ID or email of
+    /// a user of an OpenID Connect provider can be read.
     ///
     /// # Arguments
     ///
-    /// * `postfix` - A string appended to the end of the provider url
-    ///
-    /// # Example
+    /// * `id_token` - The ID token returned alongside the user's access token
+    /// * `nonce` - The nonce that was sent to the provider when the login began
+    pub async fn verify_identity(&mut self, id_token: &str, nonce: &str) -> Result<(), Error> {
+        let oidc = self
+            .provider
+            .oidc
+            .as_ref()
+            .ok_or_else(|| error::ErrorBadRequest("The provider does not issue ID tokens."))?;
+
+        // Make sure the ID token is genuine, and was meant for this login
+        let claims = oidc.verify_id_token(&self.client, id_token, nonce).await?;
+
+        // Get the user's verified details
+        self.identity = Some(
+            oidc.identity(&self.client, &self.access_token, &claims)
+                .await?,
+        );
+
+        Ok(())
+    }
+
+    /// Sends an authenticated GET request to one of the provider's endpoints, and parses the JSON
+    /// response.
     ///
-    /// ```
-    /// use server::api::wrapper::User;
-    //
-    /// // A new GitHub user
-    /// let u = User::new("SOME_ACCESS_TOKEN", "github");
+    /// # Arguments
     ///
-    /// println!("{}", u.provider_url("emails")); // => https://api.github.com/user/emails
-    /// ```
-    pub fn provider_url(&self, postfix: &str) -> String {
-        // Return the respective authentication URL
-        if self.provider == "google" {
-            // Idk some google stuff
-            format!(
-                "https://openidconnect.googleapis.com/v1/userinfo{}",
-                if postfix != "" {
-                    format!("/{}", postfix)
-                } else {
-                    "".to_owned()
-                }
-            )
-        } else {
-            // A URL for all of the information known about the user
-            format!(
-                "https://api.github.com/user{}",
-                if postfix != "" {
-                    format!("/{}", postfix)
-                } else {
-                    "".to_owned()
+    /// * `url` - The endpoint to send the request to
+    async fn fetch<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        Ok(self
+            .client
+            .get(url) // Start the request
+            .set_header("Authorization", format!("Bearer {}", self.access_token)) // Tell the provider which user we would like to get the details of
+            .set_header("User-Agent", "Notedly") // Make sure GitHub sees this as a valid request
+            .send() // Send the request
+            .await? // Yay async
+            .json::<T>()
+            .await?)
+    }
+
+    /// Gets the identity of the user from the known provider. Users of OpenID Connect providers
+    /// must have already been verified with `verify_identity`.
+    async fn identity(&mut self) -> Result<&Identity, Error> {
+        if self.identity.is_none() {
+            // ID tokens are the only way to trust an OpenID Connect provider's user
+            if self.provider.oidc.is_some() {
+                return Err(error::ErrorUnauthorized(
+                    "The user's identity has not been verified by the provider.",
+                ));
+            }
+
+            // Ask the provider who the access token belongs to
+            let info: Value = self.fetch(&self.provider.userinfo_endpoint).await?;
+            let subject = self.provider.fields.subject(&info).ok_or_else(|| {
+                error::ErrorUnauthorized("The provider did not say who the user is.")
+            })?;
+
+            // Get the user's verified email, from wherever the provider keeps it
+            let email = match &self.provider.emails_endpoint {
+                Some(endpoint) => {
+                    GitHubEmailResponse::new(self.fetch::<Vec<GitHubEmail>>(endpoint).await?)
+                        .best_email()
+                        .map(|e| e.email.clone())
                 }
-            )
+                None => self.provider.fields.verified_email(&info),
+            }
+            .ok_or_else(|| {
+                error::ErrorUnauthorized("The provider has not verified the user's email address.")
+            })?;
+
+            self.identity = Some(Identity { subject, email });
+        }
+
+        match &self.identity {
+            Some(identity) => Ok(identity),
+            None => Err(error::ErrorInternalServerError(
+                "The user's identity could not be determined.",
+            )),
         }
     }
 
     /// Gets the oauth ID of the user from the known provider.
-    pub async fn oauth_id(&self) -> Result<i32, Error> {
-        // Send a request asking for the oauth ID of the user with the matching oauth token, and
-        // await the response from the service
-        let mut response = self
-            .client
-            .get(self.provider_url("")) // Start the request
-            .set_header("Authorization", format!("Bearer {}", self.access_token)) // Tell GitHub which user we would like to get the ID of
-            .set_header("User-Agent", "Notedly") // Make sure GitHub sees this as a valid request
-            .send() // Send the request
-            .await?; // Await the response
-
-        if self.provider == "github" {
-            // Convert the general response to a GitHub response
-            let github_resp: GitHubIDResponse = response.json::<GitHubIDResponse>().await?;
-
-            // Return the identifier of the user as an owned string
-            Ok(github_resp.id)
-        } else {
-            Err(error::ErrorBadRequest(io::Error::new(
-                io::ErrorKind::Other,
-                "the provider does not exit",
-            ))) // User did something bad
-        }
+    pub async fn oauth_id(&mut self) -> Result<String, Error> {
+        Ok(self.identity().await?.subject.clone())
     }
 
     /// Gets the email of the user from the known provider.
     pub async fn email(&mut self) -> Result<&str, Error> {
-        // Send a request asking for the email of the user with the matching oauth token, and await
-        // the response from the service
-        let mut response = self
-            .client
-            .get(self.provider_url("emails")) // Start the request
-            .set_header("Authorization", format!("Bearer {}", self.access_token)) // Tell GitHub which user we would like to get the email of by sending our token
-            .set_header("User-Agent", "Notedly") // Make sure GitHub sees this as a valid request
-            .send() // Send the request
-            .await?; // Yay async
-
-        Ok(if self.provider == "github" {
-            // Get the email from the GitHub response
-            self.email = GitHubEmailResponse::new(response.json::<Vec<GitHubEmail>>().await?)
-                .best_email()
-                .email
-                .clone();
-
-            &self.email // Return the user's email
-        } else {
-            ""
-            // TODO: Add Google openID connect support
-        })
+        Ok(&self.identity().await?.email)
     }
 }
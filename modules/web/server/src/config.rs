@@ -0,0 +1,737 @@
+use super::api::{cookies::CookieKeys, providers::ProviderConfig};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    env, fmt, fs, io,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+};
+
+/// The port that the API is served on by default.
+const DEFAULT_PORT: u16 = 8080;
+
+/// The number of database connections that are kept open by default.
+const DEFAULT_POOL_SIZE: u32 = 10;
+
+/// The URL of the front-end by default.
+const DEFAULT_FRONTEND_URL: &str = "http://localhost:3000";
+
+/// The address that emails are sent from by default.
+const DEFAULT_MAIL_FROM: &str = "Notedly <notedly@localhost>";
+
+/// How the server's logs are written.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(invalid(format!(
+                "unknown log format '{}' (expected 'text' or 'json')",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// The ID and secret that the server is registered with at a well-known provider.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ClientCredentials {
+    /// The ID that the server is registered with at the provider
+    pub client_id: String,
+
+    /// The secret that the server authenticates with at the provider
+    pub client_secret: String,
+}
+
+/// The database settings of a configuration layer.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseLayer {
+    /// The URL of the postgres database
+    pub url: Option<String>,
+
+    /// The most connections that are kept open to the database
+    pub pool_size: Option<u32>,
+
+    /// Whether or not pending migrations are applied before the server starts
+    pub migrate: Option<bool>,
+}
+
+/// The session settings of a configuration layer.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionLayer {
+    /// The key that the server signs tokens and encrypts stored secrets with
+    pub secret_key: Option<String>,
+
+    /// The key that session cookies are encrypted with
+    pub cookie_key: Option<String>,
+
+    /// The keys that session cookies were encrypted with before the last rotation
+    pub previous_cookie_keys: Option<Vec<String>>,
+}
+
+/// The CORS settings of a configuration layer.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsLayer {
+    /// The origins that browsers may call the API from ("*" for any)
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+/// The oauth settings of a configuration layer.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OauthLayer {
+    /// The path of a JSON file listing more providers
+    pub providers_file: Option<String>,
+
+    /// The providers that users can log in with
+    pub providers: Option<Vec<ProviderConfig>>,
+
+    /// The credentials of the server's GitHub app, if GitHub logins are enabled
+    pub github: Option<ClientCredentials>,
+
+    /// The credentials of the server's Google app, if Google logins are enabled
+    pub google: Option<ClientCredentials>,
+}
+
+/// The email/password login settings of a configuration layer.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordsLayer {
+    /// Whether or not users can sign up with an email address and password
+    pub enabled: Option<bool>,
+
+    /// The sendmail-compatible command that emails are piped to
+    pub sendmail: Option<String>,
+
+    /// The address that emails are sent from
+    pub mail_from: Option<String>,
+}
+
+/// One source of the server's configuration (the config file, the env or the command line). Any
+/// setting can be left out of a layer, so that it can be filled in by another.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// The IP address that the API is served on
+    pub bind_address: Option<IpAddr>,
+
+    /// The port that the API is served on
+    pub port: Option<u16>,
+
+    /// The URL that the API can be reached at by users' browsers
+    pub public_url: Option<String>,
+
+    /// The URL of the front-end that users are sent to once they've logged in
+    pub frontend_url: Option<String>,
+
+    /// How the server's logs are written
+    pub log_format: Option<LogFormat>,
+
+    /// The database settings
+    pub database: DatabaseLayer,
+
+    /// The session settings
+    pub session: SessionLayer,
+
+    /// The CORS settings
+    pub cors: CorsLayer,
+
+    /// The oauth settings
+    pub oauth: OauthLayer,
+
+    /// The email/password login settings
+    pub passwords: PasswordsLayer,
+}
+
+impl ConfigLayer {
+    /// Reads a configuration layer from the TOML file at the given path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("couldn't read the config file {}: {}", path, e),
+            )
+        })?;
+
+        toml::from_str(&contents)
+            .map_err(|e| invalid(format!("the config file {} is invalid: {}", path, e)))
+    }
+
+    /// Reads a configuration layer from the process's env.
+    pub fn from_env() -> io::Result<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Reads a configuration layer from env vars.
+    ///
+    /// # Arguments
+    ///
+    /// * `var` - Gets the value of the env var with the given name, if it is set
+    pub fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> io::Result<Self> {
+        // Lists are comma-separated
+        let list = |name: &str| {
+            var(name).map(|values| {
+                values
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<String>>()
+            })
+        };
+
+        // Well-known providers are enabled once both of their credentials are set
+        let credentials = |id: &str, secret: &str| match (var(id), var(secret)) {
+            (Some(client_id), Some(client_secret)) => Some(ClientCredentials {
+                client_id,
+                client_secret,
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            bind_address: parse_var(&var, "NOTEDLY_BIND_ADDRESS")?,
+            port: parse_var(&var, "NOTEDLY_PORT")?,
+            public_url: var("NOTEDLY_PUBLIC_URL"),
+            frontend_url: var("NOTEDLY_FRONTEND_URL"),
+            log_format: parse_var(&var, "NOTEDLY_LOG_FORMAT")?,
+            database: DatabaseLayer {
+                url: var("DATABASE_URL"),
+                pool_size: parse_var(&var, "NOTEDLY_DATABASE_POOL_SIZE")?,
+                migrate: parse_bool_var(&var, "NOTEDLY_MIGRATE")?,
+            },
+            session: SessionLayer {
+                secret_key: var("NOTEDLY_SECRET_KEY"),
+                cookie_key: var("NOTEDLY_COOKIE_KEY"),
+                previous_cookie_keys: list("NOTEDLY_PREVIOUS_COOKIE_KEYS"),
+            },
+            cors: CorsLayer {
+                allowed_origins: list("NOTEDLY_CORS_ORIGINS"),
+            },
+            oauth: OauthLayer {
+                providers_file: var("NOTEDLY_OAUTH_PROVIDERS"),
+                providers: None,
+                github: credentials("GITHUB_OAUTH_CLIENT_ID", "GITHUB_OAUTH_CLIENT_SECRET"),
+                google: credentials("GOOGLE_OAUTH_CLIENT_ID", "GOOGLE_OAUTH_CLIENT_SECRET"),
+            },
+            passwords: PasswordsLayer {
+                enabled: parse_bool_var(&var, "NOTEDLY_PASSWORD_LOGIN")?,
+                sendmail: var("NOTEDLY_SENDMAIL"),
+                mail_from: var("NOTEDLY_MAIL_FROM"),
+            },
+        })
+    }
+
+    /// Loads every layer of the configuration (the config file, if any, the env and the command
+    /// line), merged in increasing order of precedence.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file. Read from NOTEDLY_CONFIG if not given.
+    /// * `flags` - The settings given on the command line
+    pub fn load(path: Option<&str>, flags: Self) -> io::Result<Self> {
+        let file = match path
+            .map(str::to_owned)
+            .or_else(|| env::var("NOTEDLY_CONFIG").ok())
+        {
+            Some(p) => Self::from_file(&p)?,
+            None => Self::default(),
+        };
+
+        Ok(file.merge(Self::from_env()?).merge(flags))
+    }
+
+    /// Gets the checked database URL of a merged layer, for commands that only need the database
+    /// (e.g. notedlyd migrate).
+    pub fn database_url(self) -> io::Result<String> {
+        checked_database_url(self.database.url)
+    }
+
+    /// Lays another layer over this one, so that any setting in the other layer takes precedence.
+    ///
+    /// # Arguments
+    ///
+    /// * `over` - The layer whose settings take precedence
+    pub fn merge(self, over: Self) -> Self {
+        Self {
+            bind_address: over.bind_address.or(self.bind_address),
+            port: over.port.or(self.port),
+            public_url: over.public_url.or(self.public_url),
+            frontend_url: over.frontend_url.or(self.frontend_url),
+            log_format: over.log_format.or(self.log_format),
+            database: DatabaseLayer {
+                url: over.database.url.or(self.database.url),
+                pool_size: over.database.pool_size.or(self.database.pool_size),
+                migrate: over.database.migrate.or(self.database.migrate),
+            },
+            session: SessionLayer {
+                secret_key: over.session.secret_key.or(self.session.secret_key),
+                cookie_key: over.session.cookie_key.or(self.session.cookie_key),
+                previous_cookie_keys: over
+                    .session
+                    .previous_cookie_keys
+                    .or(self.session.previous_cookie_keys),
+            },
+            cors: CorsLayer {
+                allowed_origins: over.cors.allowed_origins.or(self.cors.allowed_origins),
+            },
+            oauth: OauthLayer {
+                providers_file: over.oauth.providers_file.or(self.oauth.providers_file),
+                providers: over.oauth.providers.or(self.oauth.providers),
+                github: over.oauth.github.or(self.oauth.github),
+                google: over.oauth.google.or(self.oauth.google),
+            },
+            passwords: PasswordsLayer {
+                enabled: over.passwords.enabled.or(self.passwords.enabled),
+                sendmail: over.passwords.sendmail.or(self.passwords.sendmail),
+                mail_from: over.passwords.mail_from.or(self.passwords.mail_from),
+            },
+        }
+    }
+}
+
+/// The email/password login settings of a server that allows them.
+#[derive(Clone, Debug)]
+pub struct PasswordSettings {
+    /// The sendmail-compatible command that emails are piped to, if any (emails are logged
+    /// otherwise)
+    pub sendmail: Option<String>,
+
+    /// The address that emails are sent from
+    pub mail_from: String,
+}
+
+/// The server's complete, checked configuration.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The IP address that the API is served on
+    pub bind_address: IpAddr,
+
+    /// The port that the API is served on
+    pub port: u16,
+
+    /// The URL that the API can be reached at by users' browsers (e.g. https://api.notedly.app)
+    pub public_url: String,
+
+    /// The URL of the front-end that users are sent to once they've logged in
+    pub frontend_url: String,
+
+    /// How the server's logs are written
+    pub log_format: LogFormat,
+
+    /// The URL of the postgres database
+    pub database_url: String,
+
+    /// The most connections that are kept open to the database
+    pub database_pool_size: u32,
+
+    /// Whether or not pending migrations are applied before the server starts
+    pub migrate: bool,
+
+    /// The key that the server signs tokens and encrypts stored secrets with
+    pub secret_key: String,
+
+    /// The keys that session cookies are encrypted with, if they're configured separately from
+    /// the secret key
+    pub cookie_keys: Option<(String, Vec<String>)>,
+
+    /// The origins that browsers may call the API from ("*" for any)
+    pub cors_origins: Vec<String>,
+
+    /// The providers that users can log in with
+    pub providers: Vec<ProviderConfig>,
+
+    /// The email/password login settings, if users can log in with a password
+    pub passwords: Option<PasswordSettings>,
+}
+
+impl Config {
+    /// Loads the server's configuration from the config file (if any), the env and the command
+    /// line, in increasing order of precedence, and checks it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file. Read from NOTEDLY_CONFIG if not given.
+    /// * `flags` - The settings given on the command line
+    pub fn load(path: Option<&str>, flags: ConfigLayer) -> io::Result<Self> {
+        Self::from_layer(ConfigLayer::load(path, flags)?)
+    }
+
+    /// Fills in the defaults of a merged configuration layer, and checks that it is usable.
+    ///
+    /// # Arguments
+    ///
+    /// * `layer` - Every layer of the configuration, merged
+    pub fn from_layer(layer: ConfigLayer) -> io::Result<Self> {
+        let port = layer.port.unwrap_or(DEFAULT_PORT);
+
+        let database_url = checked_database_url(layer.database.url)?;
+
+        let database_pool_size = layer.database.pool_size.unwrap_or(DEFAULT_POOL_SIZE);
+        if database_pool_size == 0 {
+            return Err(invalid("database.pool_size must be at least 1"));
+        }
+
+        let secret_key = layer
+            .session
+            .secret_key
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| missing("session.secret_key", "NOTEDLY_SECRET_KEY", None))?;
+
+        // Make sure that the cookie keys are long enough to be used
+        let cookie_keys = match layer.session.cookie_key {
+            Some(primary) => {
+                let previous = layer.session.previous_cookie_keys.unwrap_or_default();
+                CookieKeys::new(&primary, &previous)
+                    .map_err(|e| invalid(format!("session.cookie_key is invalid: {}", e)))?;
+
+                Some((primary, previous))
+            }
+            None if layer
+                .session
+                .previous_cookie_keys
+                .map_or(false, |k| !k.is_empty()) =>
+            {
+                return Err(invalid(
+                    "session.previous_cookie_keys can only be set along with session.cookie_key",
+                ))
+            }
+            None => None,
+        };
+
+        let public_url = layer
+            .public_url
+            .unwrap_or_else(|| format!("http://localhost:{}", port));
+        continue_if_http_url("public_url", &public_url)?;
+
+        let frontend_url = layer
+            .frontend_url
+            .unwrap_or_else(|| DEFAULT_FRONTEND_URL.to_owned());
+        continue_if_http_url("frontend_url", &frontend_url)?;
+
+        // Any origin is allowed by default
+        let cors_origins = layer
+            .cors
+            .allowed_origins
+            .unwrap_or_else(|| vec!["*".to_owned()]);
+        for origin in cors_origins.iter().filter(|origin| *origin != "*") {
+            continue_if_http_url("cors.allowed_origins", origin)?;
+        }
+
+        Ok(Self {
+            bind_address: layer
+                .bind_address
+                .unwrap_or_else(|| IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            port,
+            public_url,
+            frontend_url,
+            log_format: layer.log_format.unwrap_or(LogFormat::Text),
+            database_url,
+            database_pool_size,
+            migrate: layer.database.migrate.unwrap_or(false),
+            secret_key,
+            cookie_keys,
+            cors_origins,
+            providers: collect_providers(layer.oauth)?,
+            passwords: match layer.passwords.enabled {
+                Some(true) => {
+                    let mail_from = layer
+                        .passwords
+                        .mail_from
+                        .unwrap_or_else(|| DEFAULT_MAIL_FROM.to_owned());
+                    if mail_from.contains(|c| c == '\r' || c == '\n') {
+                        return Err(invalid("passwords.mail_from can't contain line breaks"));
+                    }
+
+                    Some(PasswordSettings {
+                        sendmail: layer.passwords.sendmail,
+                        mail_from,
+                    })
+                }
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Collects the providers that users can log in with, from the config file, the providers file
+/// and the well-known providers' credentials.
+fn collect_providers(oauth: OauthLayer) -> io::Result<Vec<ProviderConfig>> {
+    let mut providers = oauth.providers.unwrap_or_default();
+
+    // Load any providers listed in the providers file
+    if let Some(path) = &oauth.providers_file {
+        providers.extend(ProviderConfig::from_file(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("couldn't read the providers file {}: {}", path, e),
+            )
+        })?);
+    }
+
+    if let Some(github) = oauth.github {
+        providers.push(ProviderConfig::github(
+            github.client_id,
+            github.client_secret,
+        ));
+    }
+
+    if let Some(google) = oauth.google {
+        providers.push(ProviderConfig::google(
+            google.client_id,
+            google.client_secret,
+        ));
+    }
+
+    // Login URLs refer to providers by name, so names must be unique
+    let mut names = HashSet::new();
+    for provider in &providers {
+        if !names.insert(provider.name.as_str()) {
+            return Err(invalid(format!(
+                "the oauth provider '{}' is configured twice",
+                provider.name
+            )));
+        }
+
+        if let Some(url) = &provider.redirect_url {
+            continue_if_http_url(&format!("the redirect_url of '{}'", provider.name), url)?;
+        }
+    }
+
+    Ok(providers)
+}
+
+/// Hides the password in the given database URL, so that it can be logged.
+///
+/// # Arguments
+///
+/// * `url` - The database URL
+///
+/// # Example
+///
+/// ```
+/// use server::config::redact_database_url;
+///
+/// assert_eq!(
+///     redact_database_url("postgres://notedly:hunter2@db:5432/notedly"),
+///     "postgres://notedly:****@db:5432/notedly"
+/// );
+/// assert_eq!(
+///     redact_database_url("postgres://localhost/notedly"),
+///     "postgres://localhost/notedly"
+/// );
+/// ```
+pub fn redact_database_url(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme_end), Some(at)) if at > scheme_end => {
+            let user = url[scheme_end + 3..at].split(':').next().unwrap_or("");
+
+            format!("{}{}:****{}", &url[..scheme_end + 3], user, &url[at..])
+        }
+        _ => url.to_owned(),
+    }
+}
+
+/// Parses the value of an env var, if it is set.
+fn parse_var<T, F>(var: &F, name: &str) -> io::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+    F: Fn(&str) -> Option<String>,
+{
+    var(name)
+        .map(|v| {
+            v.parse()
+                .map_err(|e| invalid(format!("{} is invalid ('{}'): {}", name, v, e)))
+        })
+        .transpose()
+}
+
+/// Parses the value of a boolean env var (true, false, 1 or 0), if it is set.
+fn parse_bool_var<F>(var: &F, name: &str) -> io::Result<Option<bool>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match var(name).as_deref() {
+        Some("true") | Some("1") => Ok(Some(true)),
+        Some("false") | Some("0") => Ok(Some(false)),
+        Some(other) => Err(invalid(format!(
+            "{} must be true or false, not '{}'",
+            name, other
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Ensures that the database URL has been set, and is a postgres:// URL.
+fn checked_database_url(url: Option<String>) -> io::Result<String> {
+    let url = url.ok_or_else(|| missing("database.url", "DATABASE_URL", Some("--database-url")))?;
+    if !url.starts_with("postgres://") && !url.starts_with("postgresql://") {
+        return Err(invalid("database.url must be a postgres:// URL"));
+    }
+
+    Ok(url)
+}
+
+/// Ensures that the given setting is an http:// or https:// URL.
+fn continue_if_http_url(setting: &str, url: &str) -> io::Result<()> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "{} must be an http:// or https:// URL, not '{}'",
+            setting, url
+        )))
+    }
+}
+
+/// Makes the error returned when a required setting hasn't been set anywhere.
+fn missing(setting: &str, var: &str, flag: Option<&str>) -> io::Error {
+    invalid(match flag {
+        Some(flag) => format!(
+            "{} isn't set (set it in the config file, with {} or with {})",
+            setting, var, flag
+        ),
+        None => format!(
+            "{} isn't set (set it in the config file or with {})",
+            setting, var
+        ),
+    })
+}
+
+/// Makes the error returned for an unusable configuration.
+fn invalid<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A config file that sets everything that the env sets in these tests.
+    const CONFIG_FILE: &str = r#"
+        port = 9000
+        public_url = "https://api.notedly.app"
+        log_format = "json"
+
+        [database]
+        url = "postgres://notedly:file@db/notedly"
+        pool_size = 4
+
+        [session]
+        secret_key = "from the file"
+
+        [cors]
+        allowed_origins = ["https://notedly.app"]
+    "#;
+
+    /// Reads a configuration layer from the given env vars.
+    fn env_layer(vars: &[(&str, &str)]) -> ConfigLayer {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        ConfigLayer::from_vars(|name| vars.get(name).cloned()).unwrap()
+    }
+
+    #[test]
+    fn test_layers_take_precedence_in_order() {
+        let file: ConfigLayer = toml::from_str(CONFIG_FILE).unwrap();
+        let env = env_layer(&[
+            ("DATABASE_URL", "postgres://notedly:env@db/notedly"),
+            ("NOTEDLY_PORT", "9001"),
+        ]);
+        let flags = ConfigLayer {
+            port: Some(9002),
+            ..ConfigLayer::default()
+        };
+
+        let config = Config::from_layer(file.merge(env).merge(flags)).unwrap();
+
+        assert_eq!(config.port, 9002);
+        assert_eq!(config.database_url, "postgres://notedly:env@db/notedly");
+        assert_eq!(config.secret_key, "from the file");
+        assert_eq!(config.database_pool_size, 4);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.cors_origins, vec!["https://notedly.app"]);
+        assert_eq!(config.frontend_url, DEFAULT_FRONTEND_URL);
+        assert!(config.passwords.is_none());
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let example: ConfigLayer = toml::from_str(include_str!("../notedly.example.toml")).unwrap();
+        let config = Config::from_layer(example).unwrap();
+
+        assert_eq!(config.providers.len(), 1);
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_missing_settings_are_named() {
+        let err = Config::from_layer(env_layer(&[("NOTEDLY_SECRET_KEY", "secret")])).unwrap_err();
+
+        assert!(err.to_string().contains("DATABASE_URL"));
+    }
+
+    #[test]
+    fn test_invalid_settings_are_rejected() {
+        let base = [
+            ("DATABASE_URL", "postgres://db/notedly"),
+            ("NOTEDLY_SECRET_KEY", "secret"),
+        ];
+
+        // Each of these breaks an otherwise valid configuration
+        for (name, value) in &[
+            ("NOTEDLY_PORT", "eighty"),
+            ("NOTEDLY_LOG_FORMAT", "xml"),
+            ("NOTEDLY_PASSWORD_LOGIN", "maybe"),
+            ("NOTEDLY_MIGRATE", "yes"),
+            ("NOTEDLY_DATABASE_POOL_SIZE", "0"),
+            ("NOTEDLY_COOKIE_KEY", "too short"),
+            ("NOTEDLY_PUBLIC_URL", "api.notedly.app"),
+        ] {
+            let mut vars = base.to_vec();
+            vars.push((*name, *value));
+
+            let vars: HashMap<&str, &str> = vars.into_iter().collect();
+            let result = ConfigLayer::from_vars(|n| vars.get(n).map(|v| v.to_string()))
+                .and_then(Config::from_layer);
+
+            assert!(result.is_err(), "{}={} was accepted", name, value);
+        }
+
+        assert!(toml::from_str::<ConfigLayer>("prot = 8080").is_err());
+    }
+}
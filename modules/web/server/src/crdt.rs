@@ -0,0 +1,546 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The largest number of operations from a single replica that can be held while they wait for
+/// the characters they refer to.
+pub const MAX_PENDING: usize = 256;
+
+/// A globally unique identifier for a single character in a `Text`. Identifiers are ordered first
+/// by their lamport counter, then by the site that created them, which gives every replica the
+/// same total order over concurrent insertions.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct CharId {
+    /// The value of the creating site's lamport clock when the character was inserted
+    pub counter: u64,
+
+    /// The ID of the site (replica) that inserted the character
+    pub site: u32,
+}
+
+/// An operation that can be applied to a `Text`. Operations are commutative and idempotent, so
+/// replicas that have applied the same set of operations (in any order) hold the same text.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Op {
+    /// Inserts a character directly after another character (or at the start of the text)
+    Insert {
+        /// The ID of the new character
+        id: CharId,
+
+        /// The ID of the character that the new character was typed after, if any
+        after: Option<CharId>,
+
+        /// The character being inserted
+        value: char,
+    },
+
+    /// Removes a character from the text
+    Delete {
+        /// The ID of the character being removed
+        id: CharId,
+    },
+}
+
+/// The error returned when a replica has too many operations waiting for characters that haven't
+/// arrived.
+#[derive(PartialEq, Debug)]
+pub struct TooManyPending;
+
+/// A character in a `Text`, including characters that have been deleted (tombstones), which
+/// must be kept around so that concurrent insertions after them can still be placed.
+#[derive(Clone, Debug)]
+struct Element {
+    /// The ID of the character
+    id: CharId,
+
+    /// The character itself
+    value: char,
+
+    /// Whether or not the character has been deleted
+    deleted: bool,
+}
+
+/// A replicated growable array (RGA): a sequence CRDT for collaboratively edited plain text.
+///
+/// # Example
+///
+/// ```
+/// use server::crdt::Text;
+///
+/// // Two replicas start from the same text
+/// let mut a = Text::with_text(1, "helo");
+/// let mut b = Text::new(2);
+/// for op in a.ops() {
+///     b.apply(op);
+/// }
+///
+/// // Both replicas edit the text concurrently
+/// let from_a = a.insert(3, "l");
+/// let from_b = b.insert(4, "!");
+///
+/// // Once each replica has seen the other's changes, they agree
+/// from_b.into_iter().for_each(|op| { a.apply(op); });
+/// from_a.into_iter().for_each(|op| { b.apply(op); });
+///
+/// assert_eq!(a.to_string(), "hello!");
+/// assert_eq!(b.to_string(), "hello!");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Text {
+    /// The ID of the site that local operations are attributed to
+    site: u32,
+
+    /// The site's lamport clock
+    clock: u64,
+
+    /// Every character that has ever been inserted, in document order
+    elements: Vec<Element>,
+
+    /// The highest counter of each site's characters that has been received
+    clocks: HashMap<u32, u64>,
+
+    /// Operations that arrived before the characters they refer to, along with the site that sent
+    /// them (if they came from an untrusted replica)
+    pending: Vec<(Option<u32>, Op)>,
+}
+
+impl Text {
+    /// Initializes a new, empty text.
+    ///
+    /// # Arguments
+    ///
+    /// * `site` - The ID of the site that local operations will be attributed to
+    pub fn new(site: u32) -> Self {
+        Self {
+            site,
+            clock: 0,
+            elements: Vec::new(),
+            clocks: HashMap::new(),
+            pending: Vec::new(),
+        } // Return the new instance
+    }
+
+    /// Initializes a new text containing the given string.
+    ///
+    /// # Arguments
+    ///
+    /// * `site` - The ID of the site that local operations will be attributed to
+    /// * `s` - The initial contents of the text
+    pub fn with_text(site: u32, s: &str) -> Self {
+        let mut text = Self::new(site);
+        text.insert(0, s);
+
+        text // Return the new instance
+    }
+
+    /// Gets a list of operations that will reproduce this text when applied to an empty one.
+    pub fn ops(&self) -> Vec<Op> {
+        // Insert each of the characters after the one preceding it, which yields the same order
+        // (and the same placement of any future concurrent insertions) as the original history
+        let mut ops: Vec<Op> = Vec::with_capacity(self.elements.len());
+        let mut after: Option<CharId> = None;
+
+        for element in &self.elements {
+            ops.push(Op::Insert {
+                id: element.id,
+                after,
+                value: element.value,
+            });
+
+            after = Some(element.id);
+        }
+
+        // Remove any of the characters that have been deleted
+        ops.extend(
+            self.elements
+                .iter()
+                .filter(|element| element.deleted)
+                .map(|element| Op::Delete { id: element.id }),
+        );
+
+        ops
+    }
+
+    /// Inserts the given string at a visible character index, returning the operations that other
+    /// replicas need to apply to see the change.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The number of visible characters preceding the inserted string
+    /// * `s` - The string to insert
+    pub fn insert(&mut self, index: usize, s: &str) -> Vec<Op> {
+        // Find the character that the string will be typed after
+        let after: Option<CharId> = if index == 0 {
+            None
+        } else {
+            self.visible_id(index - 1)
+        };
+
+        self.insert_after(after, s)
+    }
+
+    /// Inserts the given string directly after the character with the given ID (which may have
+    /// been deleted), returning the operations that other replicas need to apply to see the
+    /// change.
+    ///
+    /// # Arguments
+    ///
+    /// * `after` - The ID of the character that the string is typed after (none for the start of
+    /// the text)
+    /// * `s` - The string to insert
+    pub fn insert_after(&mut self, mut after: Option<CharId>, s: &str) -> Vec<Op> {
+        let mut ops: Vec<Op> = Vec::new();
+
+        // Insert each of the characters after the previous one
+        for value in s.chars() {
+            self.clock += 1;
+
+            let op = Op::Insert {
+                id: CharId {
+                    counter: self.clock,
+                    site: self.site,
+                },
+                after,
+                value,
+            };
+
+            after = Some(CharId {
+                counter: self.clock,
+                site: self.site,
+            });
+
+            self.apply(op.clone());
+            ops.push(op);
+        }
+
+        ops
+    }
+
+    /// Deletes a number of characters starting at a visible character index, returning the
+    /// operations that other replicas need to apply to see the change.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The number of visible characters preceding the first deleted character
+    /// * `len` - The number of characters to delete
+    pub fn delete(&mut self, index: usize, len: usize) -> Vec<Op> {
+        // Collect the IDs before deleting anything, since deleting shifts visible indexes
+        let ids: Vec<CharId> = (index..index + len)
+            .filter_map(|i| self.visible_id(i))
+            .collect();
+
+        self.remove(&ids)
+    }
+
+    /// Deletes the characters with the given IDs, returning the operations that other replicas
+    /// need to apply to see the change.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - The IDs of the characters to delete
+    pub fn remove(&mut self, ids: &[CharId]) -> Vec<Op> {
+        ids.iter()
+            .map(|&id| {
+                let op = Op::Delete { id };
+                self.apply(op.clone());
+
+                op
+            })
+            .collect()
+    }
+
+    /// Applies an operation from a trusted replica. Operations that refer to characters that
+    /// haven't been seen yet are held until those characters arrive. Returns whether or not the
+    /// operation was valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operation to apply
+    pub fn apply(&mut self, op: Op) -> bool {
+        // Operations referring to the character they create make no sense
+        if let Op::Insert {
+            id,
+            after: Some(after),
+            ..
+        } = op
+        {
+            if id == after {
+                return false;
+            }
+        }
+
+        // Trusted replicas may have any number of operations waiting
+        self.integrate_or_hold(None, op).is_ok()
+    }
+
+    /// Applies an operation sent by another site, which can't be trusted to only refer to
+    /// characters that exist. Operations are rejected if they create characters under another
+    /// site's ID, or refer to characters that no site has sent yet (which no honest replica could
+    /// have seen). Returns whether or not the operation was valid, or an error if the site has
+    /// too many operations waiting for characters that haven't arrived.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The ID of the site that sent the operation
+    /// * `op` - The operation to apply
+    pub fn apply_from(&mut self, sender: u32, op: Op) -> Result<bool, TooManyPending> {
+        let valid = match op {
+            // A replica's lamport clock is always past every character it has seen, so it can
+            // only type after characters with lower counters
+            Op::Insert { id, after, .. } => {
+                id.site == sender
+                    && after.map_or(true, |after| {
+                        after.counter < id.counter && self.has_received(after)
+                    })
+            }
+            Op::Delete { id } => self.has_received(id),
+        };
+
+        if !valid {
+            return Ok(false);
+        }
+
+        self.integrate_or_hold(Some(sender), op).map(|_| true)
+    }
+
+    /// Applies an operation, or holds it until the characters it refers to arrive. Fails if the
+    /// sending site already has `MAX_PENDING` operations held.
+    fn integrate_or_hold(&mut self, sender: Option<u32>, op: Op) -> Result<(), TooManyPending> {
+        if !self.integrate(&op) {
+            if sender.is_some()
+                && self
+                    .pending
+                    .iter()
+                    .filter(|(from, _)| *from == sender)
+                    .count()
+                    >= MAX_PENDING
+            {
+                return Err(TooManyPending);
+            }
+
+            self.receive(&op);
+            self.pending.push((sender, op));
+
+            return Ok(());
+        }
+
+        self.receive(&op);
+
+        // The new operation might have been what some of the held operations were waiting for
+        loop {
+            let before = self.pending.len();
+            let pending = std::mem::take(&mut self.pending);
+
+            for (from, op) in pending {
+                if !self.integrate(&op) {
+                    self.pending.push((from, op));
+                }
+            }
+
+            // Stop once a pass makes no more progress
+            if self.pending.len() == before {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Records that the character created by an operation (if any) has been received.
+    fn receive(&mut self, op: &Op) {
+        if let Op::Insert { id, .. } = *op {
+            let clock = self.clocks.entry(id.site).or_insert(0);
+            *clock = (*clock).max(id.counter);
+        }
+    }
+
+    /// Checks whether the site that created the given character has sent it, or any later
+    /// character.
+    fn has_received(&self, id: CharId) -> bool {
+        self.clocks
+            .get(&id.site)
+            .map_or(false, |clock| id.counter <= *clock)
+    }
+
+    /// Tries to apply an operation, returning false if it refers to a character that doesn't
+    /// exist yet.
+    fn integrate(&mut self, op: &Op) -> bool {
+        match *op {
+            Op::Insert { id, after, value } => {
+                // Applying the same insertion twice is a no-op
+                if self.position(id).is_some() {
+                    return true;
+                }
+
+                // Find where the character that this one was typed after is
+                let mut pos = match after {
+                    Some(after) => match self.position(after) {
+                        Some(p) => p + 1,
+                        None => return false,
+                    },
+                    None => 0,
+                };
+
+                // Skip over any characters inserted concurrently at the same place that win the
+                // tie (along with anything inserted after them), so every replica picks the same
+                // spot
+                while pos < self.elements.len() && self.elements[pos].id > id {
+                    pos += 1;
+                }
+
+                self.elements.insert(
+                    pos,
+                    Element {
+                        id,
+                        value,
+                        deleted: false,
+                    },
+                );
+
+                // Keep the lamport clock ahead of every character seen so far
+                self.clock = self.clock.max(id.counter);
+
+                true
+            }
+
+            Op::Delete { id } => match self.position(id) {
+                Some(p) => {
+                    self.elements[p].deleted = true;
+
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Gets each of the visible characters, along with their IDs.
+    pub fn visible(&self) -> Vec<(CharId, char)> {
+        self.elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .map(|element| (element.id, element.value))
+            .collect()
+    }
+
+    /// Gets the index of the character with the given ID in the list of all characters.
+    fn position(&self, id: CharId) -> Option<usize> {
+        self.elements.iter().position(|element| element.id == id)
+    }
+
+    /// Gets the ID of the visible character at the given index.
+    fn visible_id(&self, index: usize) -> Option<CharId> {
+        self.elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .nth(index)
+            .map(|element| element.id)
+    }
+}
+
+impl std::fmt::Display for Text {
+    /// Writes each of the text's visible characters.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .try_for_each(|element| write!(f, "{}", element.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_from_rejects_forged_references() {
+        let mut text = Text::with_text(0, "hi");
+        let first = CharId {
+            counter: 1,
+            site: 0,
+        };
+
+        // Typing after a character that exists is fine
+        let valid = Op::Insert {
+            id: CharId {
+                counter: 3,
+                site: 1,
+            },
+            after: Some(first),
+            value: '!',
+        };
+        assert_eq!(text.apply_from(1, valid), Ok(true));
+
+        // Characters can't be created under another site's ID
+        let impersonated = Op::Insert {
+            id: CharId {
+                counter: 4,
+                site: 2,
+            },
+            after: None,
+            value: '?',
+        };
+        assert_eq!(text.apply_from(1, impersonated), Ok(false));
+
+        // Nor typed after characters from sites that haven't sent anything, or from the future
+        for after in &[
+            CharId {
+                counter: 1,
+                site: 7,
+            },
+            CharId {
+                counter: 9,
+                site: 0,
+            },
+        ] {
+            let forged = Op::Insert {
+                id: CharId {
+                    counter: 10,
+                    site: 1,
+                },
+                after: Some(*after),
+                value: '?',
+            };
+            assert_eq!(text.apply_from(1, forged), Ok(false));
+        }
+
+        assert_eq!(text.to_string(), "h!i");
+    }
+
+    #[test]
+    fn test_apply_from_limits_pending_operations() {
+        let mut text = Text::new(0);
+
+        // Counters that a site skipped can be referred to, but never arrive
+        let start = Op::Insert {
+            id: CharId {
+                counter: 2 * MAX_PENDING as u64 + 2,
+                site: 1,
+            },
+            after: None,
+            value: 'a',
+        };
+        assert_eq!(text.apply_from(1, start), Ok(true));
+
+        for i in 0..MAX_PENDING as u64 {
+            let held = Op::Insert {
+                id: CharId {
+                    counter: 2 * MAX_PENDING as u64 + 3 + i,
+                    site: 1,
+                },
+                after: Some(CharId {
+                    counter: i + 1,
+                    site: 1,
+                }),
+                value: 'b',
+            };
+            assert_eq!(text.apply_from(1, held), Ok(true));
+        }
+
+        let one_too_many = Op::Delete {
+            id: CharId {
+                counter: 1,
+                site: 1,
+            },
+        };
+        assert_eq!(text.apply_from(1, one_too_many), Err(TooManyPending));
+    }
+}
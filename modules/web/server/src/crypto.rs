@@ -0,0 +1,223 @@
+use argon2::{Config, Variant, Version};
+use rand::{rngs::OsRng, RngCore};
+use ring::{aead, digest, hmac};
+
+/// The server's secret key, used to sign the tokens that the server hands out (e.g. board
+/// invitations), and to encrypt the secrets that the server stores (e.g. provider refresh tokens).
+/// Every replica of the server must be configured with the same secret.
+#[derive(Clone)]
+pub struct SecretKey {
+    /// The key used to sign and verify tokens
+    signing_key: hmac::Key,
+
+    /// The key used to encrypt and decrypt stored secrets, derived from the secret
+    encryption_key: [u8; 32],
+}
+
+impl SecretKey {
+    /// Initializes a new SecretKey from the given secret.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - A long, random string that is shared by every replica of the server
+    pub fn new(secret: &str) -> Self {
+        // Derive a separate key for encryption, so that signatures never reveal anything about it
+        let mut encryption_key = [0u8; 32];
+        encryption_key.copy_from_slice(
+            digest::digest(
+                &digest::SHA256,
+                Self::payload("encryption", secret).as_bytes(),
+            )
+            .as_ref(),
+        );
+
+        Self {
+            signing_key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            encryption_key,
+        } // Return the new instance
+    }
+
+    /// Signs the given message, returning the URL-safe, base64-encoded signature.
+    ///
+    /// # Arguments
+    ///
+    /// * `purpose` - What the signature will be used for (signatures never verify for another)
+    /// * `msg` - The message that will be signed
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use server::crypto::SecretKey;
+    ///
+    /// let key = SecretKey::new("some long, random secret");
+    /// let sig = key.sign("invitation", "hello");
+    ///
+    /// assert!(key.verify("invitation", "hello", &sig));
+    /// assert!(!key.verify("session", "hello", &sig));
+    /// ```
+    pub fn sign(&self, purpose: &str, msg: &str) -> String {
+        base64::encode_config(
+            hmac::sign(&self.signing_key, Self::payload(purpose, msg).as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// Checks that the given signature was made by this key, for the given purpose and message.
+    ///
+    /// # Arguments
+    ///
+    /// * `purpose` - What the signature was made for
+    /// * `msg` - The message that was signed
+    /// * `signature` - The URL-safe, base64-encoded signature
+    pub fn verify(&self, purpose: &str, msg: &str, signature: &str) -> bool {
+        match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+            // Compare the signatures in constant time
+            Ok(tag) => hmac::verify(
+                &self.signing_key,
+                Self::payload(purpose, msg).as_bytes(),
+                &tag,
+            )
+            .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Encrypts the given secret so that it can be stored, returning the URL-safe, base64-encoded
+    /// nonce and ciphertext.
+    ///
+    /// # Arguments
+    ///
+    /// * `purpose` - What the secret is (secrets never decrypt for another purpose)
+    /// * `plaintext` - The secret that will be encrypted
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use server::crypto::SecretKey;
+    ///
+    /// let key = SecretKey::new("some long, random secret");
+    /// let sealed = key.encrypt("refresh-token", "hello");
+    ///
+    /// assert_eq!(key.decrypt("refresh-token", &sealed), Some("hello".to_owned()));
+    /// assert_eq!(key.decrypt("access-token", &sealed), None);
+    /// ```
+    pub fn encrypt(&self, purpose: &str, plaintext: &str) -> String {
+        // Every message needs its own nonce, which is stored alongside the ciphertext
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut sealed = plaintext.as_bytes().to_vec();
+        self.cipher()
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(purpose.as_bytes()),
+                &mut sealed,
+            )
+            .expect("the secret is too long to be encrypted");
+
+        base64::encode_config([&nonce[..], &sealed[..]].concat(), base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Decrypts a secret encrypted by this key for the given purpose. Returns `None` if the
+    /// secret has been tampered with, or was encrypted by another key or for another purpose.
+    ///
+    /// # Arguments
+    ///
+    /// * `purpose` - What the secret is
+    /// * `sealed` - The URL-safe, base64-encoded nonce and ciphertext
+    pub fn decrypt(&self, purpose: &str, sealed: &str) -> Option<String> {
+        let mut sealed = base64::decode_config(sealed, base64::URL_SAFE_NO_PAD).ok()?;
+        if sealed.len() < aead::NONCE_LEN {
+            return None;
+        }
+
+        // Split the nonce back off of the ciphertext
+        let mut ciphertext = sealed.split_off(aead::NONCE_LEN);
+        let nonce = aead::Nonce::try_assume_unique_for_key(&sealed).ok()?;
+
+        let plaintext = self
+            .cipher()
+            .open_in_place(nonce, aead::Aad::from(purpose.as_bytes()), &mut ciphertext)
+            .ok()?;
+
+        String::from_utf8(plaintext.to_vec()).ok()
+    }
+
+    /// Derives a 32-byte key for the given purpose from the secret, for other parts of the server
+    /// that need a key shared by every replica (e.g. the session cookie key).
+    ///
+    /// # Arguments
+    ///
+    /// * `purpose` - What the key will be used for (each purpose gets a different key)
+    pub fn derive_key(&self, purpose: &str) -> Vec<u8> {
+        hmac::sign(&self.signing_key, Self::payload(purpose, "").as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+
+    /// Gets the cipher that stored secrets are encrypted with.
+    fn cipher(&self) -> aead::LessSafeKey {
+        aead::LessSafeKey::new(
+            aead::UnboundKey::new(&aead::AES_256_GCM, &self.encryption_key)
+                .expect("the encryption key has the wrong length"),
+        )
+    }
+
+    /// Prefixes a message with its purpose, so that a signature can't be reused for a different
+    /// purpose.
+    fn payload(purpose: &str, msg: &str) -> String {
+        format!("{}\n{}", purpose, msg)
+    }
+}
+
+/// Generates a random, URL-safe string suitable for use as a secret token.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Hashes a password with argon2id, returning the encoded hash (which includes its salt and
+/// parameters).
+///
+/// # Arguments
+///
+/// * `password` - The password that will be hashed
+///
+/// # Example
+///
+/// ```
+/// use server::crypto::{hash_password, verify_password};
+///
+/// let hash = hash_password("correct horse battery staple").unwrap();
+///
+/// assert!(verify_password(&hash, "correct horse battery staple"));
+/// assert!(!verify_password(&hash, "hunter2"));
+/// ```
+pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    argon2::hash_encoded(
+        password.as_bytes(),
+        &salt,
+        &Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: 19456,
+            time_cost: 2,
+            ..Config::default()
+        },
+    )
+}
+
+/// Checks a password against a hash made by `hash_password`.
+///
+/// # Arguments
+///
+/// * `hash` - The encoded hash of the correct password
+/// * `password` - The password that will be checked
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
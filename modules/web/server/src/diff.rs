@@ -0,0 +1,168 @@
+use serde::Serialize;
+
+/// The largest LCS table that a diff will build (about 16MB). Note bodies aren't limited in size,
+/// so sequences whose changed sections would need a larger table are diffed as a replacement of
+/// every changed item instead.
+pub const MAX_TABLE_CELLS: usize = 2_000_000;
+
+/// A single item (usually a line) in a diff between two sequences.
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(tag = "op", content = "line", rename_all = "lowercase")]
+pub enum Change<T = String> {
+    /// An item that exists in both the old and the new sequence
+    Equal(T),
+
+    /// An item that only exists in the new sequence
+    Insert(T),
+
+    /// An item that only exists in the old sequence
+    Delete(T),
+}
+
+/// Computes a line-based diff between the two provided pieces of text, using the longest common
+/// subsequence of their lines. If the changed sections of the texts are too large to compare line
+/// by line (see `MAX_TABLE_CELLS`), every changed line is shown as deleted and then inserted.
+///
+/// # Arguments
+///
+/// * `old` - The original text
+/// * `new` - The text that the original was changed to
+///
+/// # Example
+///
+/// ```
+/// use server::diff::{diff_lines, Change};
+///
+/// let changes = diff_lines("a\nb\nc", "a\nc\nd");
+///
+/// assert_eq!(
+///     changes,
+///     vec![
+///         Change::Equal("a".to_owned()),
+///         Change::Delete("b".to_owned()),
+///         Change::Equal("c".to_owned()),
+///         Change::Insert("d".to_owned()),
+///     ]
+/// );
+/// ```
+pub fn diff_lines(old: &str, new: &str) -> Vec<Change> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    diff(&old_lines, &new_lines)
+        .into_iter()
+        .map(|change| match change {
+            Change::Equal(line) => Change::Equal(line.to_owned()),
+            Change::Insert(line) => Change::Insert(line.to_owned()),
+            Change::Delete(line) => Change::Delete(line.to_owned()),
+        })
+        .collect()
+}
+
+/// Computes a diff between two sequences (e.g. the characters of two pieces of text), using their
+/// longest common subsequence. As with `diff_lines`, sequences whose changed sections are too
+/// large to compare item by item are diffed as a replacement of every changed item.
+///
+/// # Arguments
+///
+/// * `old` - The original sequence
+/// * `new` - The sequence that the original was changed to
+///
+/// # Example
+///
+/// ```
+/// use server::diff::{diff, Change};
+///
+/// let changes = diff(&['c', 'a', 't'], &['c', 'o', 't']);
+///
+/// assert_eq!(
+///     changes,
+///     vec![
+///         Change::Equal('c'),
+///         Change::Delete('a'),
+///         Change::Insert('o'),
+///         Change::Equal('t'),
+///     ]
+/// );
+/// ```
+pub fn diff<T: PartialEq + Clone>(old: &[T], new: &[T]) -> Vec<Change<T>> {
+    // Skip over any items shared at the start and end of both sequences, since they can't be part
+    // of a change, and would only make the table below larger
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut changes: Vec<Change<T>> = old[..prefix]
+        .iter()
+        .map(|item| Change::Equal(item.clone()))
+        .collect();
+
+    // Only compare the changed sections item by item if the table would fit in the budget
+    let cells = (old_mid.len() + 1).saturating_mul(new_mid.len() + 1);
+    let (i, j) = if cells <= MAX_TABLE_CELLS {
+        diff_mid(old_mid, new_mid, &mut changes)
+    } else {
+        (0, 0)
+    };
+
+    // Anything left over only exists on one side
+    changes.extend(old_mid[i..].iter().map(|item| Change::Delete(item.clone())));
+    changes.extend(new_mid[j..].iter().map(|item| Change::Insert(item.clone())));
+    changes.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|item| Change::Equal(item.clone())),
+    );
+
+    changes
+}
+
+/// Diffs the changed sections of two sequences using an LCS table, pushing the changes onto the
+/// given list. Returns how far into each section the diff got, since any items after that only
+/// exist on one side.
+fn diff_mid<T: PartialEq + Clone>(
+    old_mid: &[T],
+    new_mid: &[T],
+    changes: &mut Vec<Change<T>>,
+) -> (usize, usize) {
+    // lcs[i][j] holds the length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    // Walk the table, preferring deletions over insertions so that removed items come first
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() && j < new_mid.len() {
+        if old_mid[i] == new_mid[j] {
+            changes.push(Change::Equal(old_mid[i].clone()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Delete(old_mid[i].clone()));
+            i += 1;
+        } else {
+            changes.push(Change::Insert(new_mid[j].clone()));
+            j += 1;
+        }
+    }
+
+    (i, j)
+}
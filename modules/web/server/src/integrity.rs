@@ -0,0 +1,253 @@
+use diesel::{
+    connection::Connection, pg::PgConnection, result::Error as DieselError, sql_query,
+    sql_types::Int8, RunQueryDsl,
+};
+use std::io;
+
+/// Every kind of inconsistency that is checked for, in the order that they are repaired. Rows
+/// that belong to other rows are checked after the rows that they belong to, so that repairing
+/// one check never leaves anything behind for an earlier one.
+pub const CHECKS: &[Check] = &[
+    Check {
+        description: "boards owned by users that don't exist",
+        table: "boards",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "boards without an owner permission",
+        table: "boards",
+        // 4 => owner
+        condition: "NOT EXISTS (SELECT 1 FROM permissions WHERE permissions.board_id = boards.id \
+                    AND permissions.user_id = boards.user_id AND permissions.role = 4)",
+        fix: Fix::GrantOwnership,
+    },
+    Check {
+        description: "notes on boards that don't exist",
+        table: "notes",
+        condition: "board_id NOT IN (SELECT id FROM boards)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "notes written by users that don't exist",
+        table: "notes",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "revisions of notes that don't exist",
+        table: "note_revisions",
+        condition: "note_id NOT IN (SELECT id FROM notes)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "revisions written by users that don't exist",
+        table: "note_revisions",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "permissions on boards or for users that don't exist",
+        table: "permissions",
+        condition: "board_id NOT IN (SELECT id FROM boards) \
+                    OR user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "invitations to boards or from users that don't exist",
+        table: "board_invitations",
+        condition: "board_id NOT IN (SELECT id FROM boards) \
+                    OR inviter_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "transfers of boards or between users that don't exist",
+        table: "board_transfers",
+        condition: "board_id NOT IN (SELECT id FROM boards) \
+                    OR from_user_id NOT IN (SELECT id FROM users) \
+                    OR to_user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "linked accounts of users that don't exist",
+        table: "user_identities",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "sessions of users that don't exist",
+        table: "auth_sessions",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "sessions started with linked accounts that don't exist",
+        table: "auth_sessions",
+        condition: "identity_id NOT IN (SELECT id FROM user_identities)",
+        fix: Fix::Update("identity_id = NULL"),
+    },
+    Check {
+        description: "API tokens of users that don't exist",
+        table: "api_tokens",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "API tokens restricted to boards that don't exist",
+        table: "api_tokens",
+        condition: "NOT (board_ids <@ ARRAY(SELECT id FROM boards))",
+        fix: Fix::Update(
+            "board_ids = ARRAY(SELECT b FROM unnest(board_ids) AS b \
+             WHERE b IN (SELECT id FROM boards))",
+        ),
+    },
+    Check {
+        description: "passwords of users that don't exist",
+        table: "password_credentials",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "two-factor credentials of users that don't exist",
+        table: "two_factor_credentials",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+    Check {
+        description: "recovery codes of users that don't exist",
+        table: "recovery_codes",
+        condition: "user_id NOT IN (SELECT id FROM users)",
+        fix: Fix::Delete,
+    },
+];
+
+/// A kind of inconsistency that can be left behind by writes that were interrupted, or that were
+/// made before the database enforced foreign keys.
+pub struct Check {
+    /// What the check looks for (e.g. notes on boards that don't exist)
+    pub description: &'static str,
+
+    /// The table that inconsistent rows are found in
+    table: &'static str,
+
+    /// The condition that inconsistent rows match
+    condition: &'static str,
+
+    /// How inconsistent rows are repaired
+    fix: Fix,
+}
+
+/// How the rows found by a check are repaired.
+enum Fix {
+    /// The rows are deleted
+    Delete,
+
+    /// The rows are updated with the given SET clause
+    Update(&'static str),
+
+    /// The boards' owners are given the owner permission on them
+    GrantOwnership,
+}
+
+impl Check {
+    /// Gets the statement that counts the rows found by the check.
+    fn count_sql(&self) -> String {
+        format!(
+            "SELECT COUNT(*) AS count FROM {} WHERE {}",
+            self.table, self.condition
+        )
+    }
+
+    /// Gets the statement that repairs the rows found by the check.
+    fn repair_sql(&self) -> String {
+        match self.fix {
+            Fix::Delete => format!("DELETE FROM {} WHERE {}", self.table, self.condition),
+            Fix::Update(set) => {
+                format!("UPDATE {} SET {} WHERE {}", self.table, set, self.condition)
+            }
+            // 4 => owner
+            Fix::GrantOwnership => format!(
+                "INSERT INTO permissions (user_id, board_id, role) \
+                 SELECT user_id, id, 4 FROM {} WHERE {} \
+                 ON CONFLICT (user_id, board_id) DO UPDATE SET role = 4",
+                self.table, self.condition
+            ),
+        }
+    }
+}
+
+/// The number of rows that a check found, or repaired.
+pub struct Finding {
+    /// The check that found the rows
+    pub check: &'static Check,
+
+    /// The number of rows
+    pub rows: usize,
+}
+
+/// The result of a COUNT(*) query.
+#[derive(QueryableByName)]
+struct Count {
+    #[sql_type = "Int8"]
+    count: i64,
+}
+
+/// Runs every check, returning the checks that found inconsistent rows.
+///
+/// # Arguments
+///
+/// * `conn` - A connection to the database
+pub fn check(conn: &PgConnection) -> io::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+
+    for check in CHECKS {
+        let count: Count = sql_query(check.count_sql())
+            .get_result(conn)
+            .map_err(|e| failed(check, e))?;
+
+        if count.count > 0 {
+            findings.push(Finding {
+                check,
+                rows: count.count as usize,
+            });
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Repairs every inconsistent row in a single transaction, returning the checks that found rows
+/// to repair.
+///
+/// # Arguments
+///
+/// * `conn` - A connection to the database
+pub fn repair(conn: &PgConnection) -> io::Result<Vec<Finding>> {
+    conn.transaction::<_, DieselError, _>(|| {
+        let mut repaired = Vec::new();
+
+        for check in CHECKS {
+            let rows = sql_query(check.repair_sql()).execute(conn)?;
+
+            if rows > 0 {
+                repaired.push(Finding { check, rows });
+            }
+        }
+
+        Ok(repaired)
+    })
+    .map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("couldn't repair the database: {}", e),
+        )
+    })
+}
+
+/// Makes the error returned when a check fails to run.
+fn failed(check: &Check, e: DieselError) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("couldn't check for {}: {}", check.description, e),
+    )
+}
@@ -1,6 +1,14 @@
 pub mod api;
+pub mod config;
+pub mod crdt;
+pub mod crypto;
+pub mod diff;
+pub mod integrity;
+pub mod mail;
+pub mod migrations;
 pub mod models;
 pub mod schema;
+pub mod totp;
 
 #[macro_use]
 extern crate log;
@@ -8,20 +16,29 @@ extern crate env_logger;
 
 #[macro_use]
 extern crate actix_web;
+extern crate actix_codec;
 extern crate actix_cors;
+extern crate actix_http;
 extern crate actix_rt;
 extern crate actix_session;
 
 #[macro_use]
 extern crate diesel;
 
+extern crate argon2;
+extern crate base64;
+extern crate bytes;
+extern crate chrono;
+extern crate futures;
 extern crate hex;
 extern crate oauth2;
 extern crate r2d2;
 extern crate r2d2_postgres;
 extern crate rand;
+extern crate ring;
 extern crate serde;
 extern crate serde_json;
 extern crate sha3;
 extern crate snafu;
 extern crate tokio;
+extern crate toml;
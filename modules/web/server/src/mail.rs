@@ -0,0 +1,81 @@
+use std::{
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+/// Sends emails to users (e.g. email verification links). Emails are piped to a
+/// sendmail-compatible command, so that any local mail transfer agent can deliver them. Without a
+/// command, emails are written to the server's log instead, so that the administrators of
+/// air-gapped servers can hand them over themselves.
+#[derive(Clone)]
+pub struct Mailer {
+    /// The sendmail-compatible command that emails are piped to (e.g. "/usr/sbin/sendmail -t")
+    command: Option<String>,
+
+    /// The address that emails are sent from
+    from: String,
+}
+
+impl Mailer {
+    /// Initializes a new Mailer.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The sendmail-compatible command that emails are piped to, if any
+    /// * `from` - The address that emails are sent from
+    pub fn new(command: Option<String>, from: String) -> Self {
+        Self { command, from } // Return the new instance
+    }
+
+    /// Sends a plain text email.
+    ///
+    /// # Arguments
+    ///
+    /// * `to` - The address of the recipient
+    /// * `subject` - The subject of the email
+    /// * `body` - The plain text body of the email
+    pub fn send(&self, to: &str, subject: &str, body: &str) -> io::Result<()> {
+        // Headers can't be smuggled in through the recipient or subject
+        if to.contains(|c| c == '\r' || c == '\n') || subject.contains(|c| c == '\r' || c == '\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "email headers can't contain line breaks",
+            ));
+        }
+
+        let command = match &self.command {
+            Some(cmd) => cmd,
+            None => {
+                info!("Email to {} ({}):\n{}", to, subject, body);
+
+                return Ok(());
+            }
+        };
+
+        // Hand the message to the mail transfer agent
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()?;
+
+        if let Some(stdin) = child.stdin.as_mut() {
+            write!(
+                stdin,
+                "From: {}\r\nTo: {}\r\nSubject: {}\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+                self.from, to, subject, body
+            )?;
+        }
+
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("the mail command exited with {}", status),
+            ))
+        }
+    }
+}
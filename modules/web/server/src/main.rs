@@ -5,12 +5,24 @@ extern crate log;
 
 extern crate dotenv;
 
+use chrono::Utc;
 use clap::Clap;
 use dotenv::dotenv;
 use human_panic::setup_panic;
 use log::LevelFilter::{Debug, Info};
-use server::api::server::{OauthConfig, Server};
-use std::{env, io};
+use server::{
+    api::server::{OauthConfig, Server},
+    config::{
+        redact_database_url, Config, ConfigLayer, CorsLayer, DatabaseLayer, LogFormat,
+        PasswordsLayer,
+    },
+    integrity, migrations,
+};
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+    process,
+};
 
 /// The notedly command-line interface.
 #[derive(Clap)]
@@ -34,22 +46,226 @@ enum SubCommand {
     /// Starts the notedly API web server
     #[clap(name = "serve", version = "1.0", author = "Dowland A.")]
     Serve(Serve),
+
+    /// Works with the notedly API web server's configuration
+    #[clap(name = "config", version = "1.0", author = "Dowland A.")]
+    Config(ConfigCommand),
+
+    /// Applies and reverts the database migrations built into notedlyd
+    #[clap(name = "migrate", version = "1.0", author = "Dowland A.")]
+    Migrate(MigrateCommand),
+
+    /// Works with the data in the database
+    #[clap(name = "db", version = "1.0", author = "Dowland A.")]
+    Db(DbCommand),
 }
 
-/// Starts the notedly API web server. Please note that `serve` assumes the following variables
-/// have been set, and can be found in your OS env: GITHUB_OAUTH_CLIENT_ID,
-/// GITHUB_OAUTH_CLIENT_SECRET, GOOGLE_OAUTH_CLIENT_ID, GOOGLE_OAUTH_CLIENT_SECRET.
+/// Starts the notedly API web server. Settings are read from the TOML config file at --config (or
+/// NOTEDLY_CONFIG), then from the env, then from these flags, with each source overriding the
+/// last. The database URL and secret key are required, and can be set with DATABASE_URL and
+/// NOTEDLY_SECRET_KEY. See notedly.example.toml for every setting and the env var that sets it.
 #[derive(Clap)]
 #[clap(name = "serve", version = "1.0", author = "Dowland A.")]
 struct Serve {
-    /// The port the API will be served on
-    #[clap(short = "p", default_value = "8080")]
-    port: u16,
+    /// The path of the TOML config file
+    #[clap(short = "c", long = "config")]
+    config: Option<String>,
+
+    /// The IP address the API will be served on (0.0.0.0 by default)
+    #[clap(short = "b", long = "bind")]
+    bind_address: Option<IpAddr>,
+
+    /// The port the API will be served on (8080 by default)
+    #[clap(short = "p", long = "port")]
+    port: Option<u16>,
+
+    /// The URL of the postgres database
+    #[clap(long = "database-url")]
+    database_url: Option<String>,
+
+    /// The most connections that will be kept open to the database (10 by default)
+    #[clap(long = "database-pool-size")]
+    database_pool_size: Option<u32>,
+
+    /// The URL that browsers reach the API at (http://localhost:{port} by default)
+    #[clap(long = "public-url")]
+    public_url: Option<String>,
+
+    /// The URL of the front-end that users are sent to once they've logged in
+    /// (http://localhost:3000 by default)
+    #[clap(long = "frontend-url")]
+    frontend_url: Option<String>,
+
+    /// An origin that browsers may call the API from (any origin by default)
+    #[clap(long = "cors-origin")]
+    cors_origins: Vec<String>,
+
+    /// How logs are written: text or json (text by default)
+    #[clap(long = "log-format")]
+    log_format: Option<LogFormat>,
+
+    /// Let users sign up with an email address and password
+    #[clap(long = "password-login")]
+    password_login: bool,
+
+    /// Apply any pending database migrations before serving
+    #[clap(long = "migrate")]
+    migrate: bool,
+}
+
+impl Serve {
+    /// Gets the path of the config file, and the configuration layer made up of the given flags.
+    fn into_layer(self) -> (Option<String>, ConfigLayer) {
+        (
+            self.config,
+            ConfigLayer {
+                bind_address: self.bind_address,
+                port: self.port,
+                public_url: self.public_url,
+                frontend_url: self.frontend_url,
+                log_format: self.log_format,
+                database: DatabaseLayer {
+                    url: self.database_url,
+                    pool_size: self.database_pool_size,
+                    migrate: if self.migrate { Some(true) } else { None },
+                },
+                cors: CorsLayer {
+                    allowed_origins: if self.cors_origins.is_empty() {
+                        None
+                    } else {
+                        Some(self.cors_origins)
+                    },
+                },
+                passwords: PasswordsLayer {
+                    enabled: if self.password_login {
+                        Some(true)
+                    } else {
+                        None
+                    },
+                    ..PasswordsLayer::default()
+                },
+                ..ConfigLayer::default()
+            },
+        )
+    }
+}
+
+/// Works with the notedly API web server's configuration.
+#[derive(Clap)]
+#[clap(name = "config", version = "1.0", author = "Dowland A.")]
+struct ConfigCommand {
+    #[clap(subcommand)]
+    subcmd: ConfigSubCommand,
+}
+
+/// A subcommand of the config command.
+#[derive(Clap)]
+enum ConfigSubCommand {
+    /// Checks that the configuration is valid, without starting the server
+    #[clap(name = "check", version = "1.0", author = "Dowland A.")]
+    Check(Check),
+}
+
+/// Checks that the configuration in the config file and the env is valid, without starting the
+/// server.
+#[derive(Clap)]
+#[clap(name = "check", version = "1.0", author = "Dowland A.")]
+struct Check {
+    /// The path of the TOML config file
+    #[clap(short = "c", long = "config")]
+    config: Option<String>,
+}
+
+/// Applies and reverts the database migrations built into notedlyd.
+#[derive(Clap)]
+#[clap(name = "migrate", version = "1.0", author = "Dowland A.")]
+struct MigrateCommand {
+    #[clap(subcommand)]
+    subcmd: MigrateSubCommand,
+}
+
+/// A subcommand of the migrate command.
+#[derive(Clap)]
+enum MigrateSubCommand {
+    /// Applies every pending migration
+    #[clap(name = "up", version = "1.0", author = "Dowland A.")]
+    Up(DatabaseOptions),
+
+    /// Reverts the most recently applied migration
+    #[clap(name = "down", version = "1.0", author = "Dowland A.")]
+    Down(DatabaseOptions),
+
+    /// Lists every migration, and whether or not it has been applied
+    #[clap(name = "status", version = "1.0", author = "Dowland A.")]
+    Status(DatabaseOptions),
+
+    /// Reverts the most recently applied migration and applies it again
+    #[clap(name = "redo", version = "1.0", author = "Dowland A.")]
+    Redo(DatabaseOptions),
+}
+
+/// The options shared by each command that only works with the database. Only the database URL
+/// is read from the config file and the env.
+#[derive(Clap)]
+struct DatabaseOptions {
+    /// The path of the TOML config file
+    #[clap(short = "c", long = "config")]
+    config: Option<String>,
+
+    /// The URL of the postgres database
+    #[clap(long = "database-url")]
+    database_url: Option<String>,
+}
+
+impl DatabaseOptions {
+    /// Gets the URL of the database that will be worked with.
+    fn database_url(&self) -> io::Result<String> {
+        ConfigLayer::load(
+            self.config.as_deref(),
+            ConfigLayer {
+                database: DatabaseLayer {
+                    url: self.database_url.clone(),
+                    ..DatabaseLayer::default()
+                },
+                ..ConfigLayer::default()
+            },
+        )?
+        .database_url()
+    }
+}
+
+/// Works with the data in the database.
+#[derive(Clap)]
+#[clap(name = "db", version = "1.0", author = "Dowland A.")]
+struct DbCommand {
+    #[clap(subcommand)]
+    subcmd: DbSubCommand,
+}
+
+/// A subcommand of the db command.
+#[derive(Clap)]
+enum DbSubCommand {
+    /// Looks for rows that refer to rows that don't exist, and boards without an owner
+    #[clap(name = "check", version = "1.0", author = "Dowland A.")]
+    Check(DbCheck),
+}
+
+/// Looks for rows that refer to rows that don't exist, and boards without an owner. Exits with an
+/// error if any are found, unless they are repaired.
+#[derive(Clap)]
+#[clap(name = "check", version = "1.0", author = "Dowland A.")]
+struct DbCheck {
+    #[clap(flatten)]
+    database: DatabaseOptions,
+
+    /// Repair any problems that are found (rows that refer to missing rows are deleted)
+    #[clap(long = "repair")]
+    repair: bool,
 }
 
 /// The entry point for the notedly CLI.
 #[actix_rt::main]
-async fn main() -> io::Result<()> {
+async fn main() {
     // Even though everything might be on fire, don't scare the user
     setup_panic!();
 
@@ -58,63 +274,255 @@ async fn main() -> io::Result<()> {
 
     let opts: Opts = Opts::parse(); // Parse any arguments issued by the user
 
-    // Configure the logger
-    if !opts.silent {
-        if opts.debug {
-            env_logger::builder().filter_level(Debug).init(); // Include debug statements in logger output
-        } else {
-            env_logger::builder().filter_level(Info).init(); // Include info statements
+    // Check if the user is trying to start the web server or just use the CLI
+    let result = match opts.subcmd {
+        // Start serving
+        SubCommand::Serve(cfg) => {
+            let (path, flags) = cfg.into_layer();
+
+            match Config::load(path.as_deref(), flags) {
+                Ok(config) => {
+                    init_logger(opts.debug, opts.silent, config.log_format);
+
+                    serve(config).await
+                }
+                Err(e) => Err(e),
+            }
         }
+
+        // Check the configuration
+        SubCommand::Config(cmd) => match cmd.subcmd {
+            ConfigSubCommand::Check(check) => {
+                Config::load(check.config.as_deref(), ConfigLayer::default()).map(print_config)
+            }
+        },
+
+        // Apply or revert migrations
+        SubCommand::Migrate(cmd) => migrate(cmd.subcmd),
+
+        // Check the data in the database
+        SubCommand::Db(cmd) => match cmd.subcmd {
+            DbSubCommand::Check(check) => check_db(check),
+        },
+    };
+
+    // Bad configurations (and anything else that stops the server) must fail loudly
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+
+        process::exit(1);
     }
+}
 
-    // Check if the user is trying to start the web server or just use the CLI
-    match opts.subcmd {
-        // Start serving
-        SubCommand::Serve(cfg) => serve(cfg).await,
+/// Configures the logger.
+///
+/// # Arguments
+///
+/// * `debug` - Whether or not debug statements should be logged
+/// * `silent` - Whether or not logging should be turned off
+/// * `format` - How log lines are written
+fn init_logger(debug: bool, silent: bool, format: LogFormat) {
+    if silent {
+        return;
     }
+
+    let mut builder = env_logger::builder();
+    builder.filter_level(if debug { Debug } else { Info }); // Include debug statements in logger output, if asked to
+
+    // Write each line as a JSON object, so that log collectors don't have to parse it
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                serde_json::json!({
+                    "timestamp": Utc::now().to_rfc3339(),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                })
+            )
+        });
+    }
+
+    builder.init();
 }
 
 /// Starts serving the notedly API.
 ///
 /// # Arguments
 ///
-/// * `serve` - A config for the serve command
-async fn serve(serve: Serve) -> io::Result<()> {
-    // The names of the environment variables where we expect that the oauth config & couchbase
-    // credentials have been stored
-    let required_vars = [
-        "GITHUB_OAUTH_CLIENT_ID",
-        "GITHUB_OAUTH_CLIENT_SECRET",
-        "GOOGLE_OAUTH_CLIENT_ID",
-        "GOOGLE_OAUTH_CLIENT_SECRET",
-        "DATABASE_URL",
-    ];
-
-    // The values of each environment variable, which we'll collect in a moment
-    let mut var_values: Vec<String> = Vec::new();
-
-    // Iterate through each of the desired ENV variables
-    for required_var in required_vars.iter() {
-        match env::var(required_var) {
-            // If the var exists, add it to the var values vec
-            Ok(var) => var_values.push(var),
-
-            // If the var doesn't exist, panic! We need each of the vars to be set in order to
-            // work.
-            Err(_) => error!("Expected env var {} to be set.", required_var),
-        };
+/// * `config` - The server's configuration
+async fn serve(config: Config) -> io::Result<()> {
+    // Nobody would be able to log in without a provider
+    if config.providers.is_empty() && config.passwords.is_none() {
+        warn!("No oauth providers have been configured.");
+    }
+
+    if config.passwords.is_some() {
+        info!("Password logins are enabled");
+    }
+
+    // Bring the database up to date before anything uses it
+    if config.migrate {
+        let conn = migrations::connect(&config.database_url)?;
+
+        for migration in migrations::run_pending(&conn)? {
+            info!("Applied migration {}", migration.name);
+        }
     }
 
-    // If the user hasn't provided the required variables, return
-    if var_values.len() == required_vars.len() {
-        // Make a new oauth config from the collected env variables
-        let (oauth_config, mut rem_values) = OauthConfig::new(var_values);
+    // Make a new oauth config from the configured providers
+    let oauth_config = OauthConfig::new(config.providers.clone()).await?;
 
-        // Make a new server from the generated oauth config
-        let mut s = Server::new(oauth_config, rem_values.remove(0), serve.port);
+    // Make a new server from the generated oauth config
+    Server::new(oauth_config, &config)?.start().await
+}
 
-        s.start().await
-    } else {
-        Ok(()) // Nothing to do, stop the main fn!
+/// Runs a migrate subcommand, printing what was done.
+///
+/// # Arguments
+///
+/// * `cmd` - The migrate subcommand that was issued
+fn migrate(cmd: MigrateSubCommand) -> io::Result<()> {
+    let opts = match &cmd {
+        MigrateSubCommand::Up(opts)
+        | MigrateSubCommand::Down(opts)
+        | MigrateSubCommand::Status(opts)
+        | MigrateSubCommand::Redo(opts) => opts,
+    };
+    let conn = migrations::connect(&opts.database_url()?)?;
+
+    match cmd {
+        MigrateSubCommand::Up(_) => {
+            let applied = migrations::run_pending(&conn)?;
+            if applied.is_empty() {
+                println!("The database is up to date.");
+            }
+
+            for migration in applied {
+                println!("Applied {}", migration.name);
+            }
+        }
+        MigrateSubCommand::Down(_) => match migrations::revert_latest(&conn)? {
+            Some(migration) => println!("Reverted {}", migration.name),
+            None => println!("No migrations have been applied."),
+        },
+        MigrateSubCommand::Status(_) => {
+            for status in migrations::status(&conn)? {
+                println!(
+                    "  {:<27}  {}",
+                    match status.run_on {
+                        Some(run_on) => format!("applied {}", run_on.format("%Y-%m-%d %H:%M:%S")),
+                        None => "pending".to_owned(),
+                    },
+                    match status.migration {
+                        Some(migration) => migration.name.to_owned(),
+                        None => format!("{} (unknown to this notedlyd)", status.version),
+                    }
+                );
+            }
+        }
+        MigrateSubCommand::Redo(_) => match migrations::redo_latest(&conn)? {
+            Some(migration) => println!("Redid {}", migration.name),
+            None => println!("No migrations have been applied."),
+        },
     }
+
+    Ok(())
+}
+
+/// Checks the data in the database, repairing it if asked to, and prints what was found.
+///
+/// # Arguments
+///
+/// * `check` - The options given to the check command
+fn check_db(check: DbCheck) -> io::Result<()> {
+    let conn = migrations::connect(&check.database.database_url()?)?;
+
+    if check.repair {
+        let repaired = integrity::repair(&conn)?;
+        if repaired.is_empty() {
+            println!("No problems were found.");
+        }
+
+        for finding in repaired {
+            println!("Repaired {} {}", finding.rows, finding.check.description);
+        }
+
+        return Ok(());
+    }
+
+    let findings = integrity::check(&conn)?;
+    if findings.is_empty() {
+        println!("No problems were found.");
+
+        return Ok(());
+    }
+
+    for finding in &findings {
+        println!("  {:>6}  {}", finding.rows, finding.check.description);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "problems were found (run notedlyd db check --repair to repair them)",
+    ))
+}
+
+/// Prints a summary of a valid configuration, so that administrators can see what the server will
+/// run with.
+///
+/// # Arguments
+///
+/// * `config` - The configuration that was checked
+fn print_config(config: Config) {
+    let providers: Vec<&str> = config.providers.iter().map(|p| p.name.as_str()).collect();
+
+    println!("The configuration is valid.");
+    println!();
+    println!(
+        "  Address:          {}:{}",
+        config.bind_address, config.port
+    );
+    println!("  Public URL:       {}", config.public_url);
+    println!("  Front-end URL:    {}", config.frontend_url);
+    println!(
+        "  Database:         {} ({} connections)",
+        redact_database_url(&config.database_url),
+        config.database_pool_size
+    );
+    println!(
+        "  Oauth providers:  {}",
+        if providers.is_empty() {
+            "none".to_owned()
+        } else {
+            providers.join(", ")
+        }
+    );
+    println!(
+        "  Password logins:  {}",
+        if config.passwords.is_some() {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    println!(
+        "  Migrations:       {}",
+        if config.migrate {
+            "applied on start"
+        } else {
+            "not applied on start"
+        }
+    );
+    println!("  CORS origins:     {}", config.cors_origins.join(", "));
+    println!(
+        "  Session cookies:  {}",
+        match &config.cookie_keys {
+            Some((_, previous)) => format!("configured key ({} previous)", previous.len()),
+            None => "key derived from the secret key".to_owned(),
+        }
+    );
+    println!("  Log format:       {}", config.log_format);
 }
@@ -0,0 +1,355 @@
+use self::__diesel_schema_migrations::dsl::{
+    __diesel_schema_migrations as applied, run_on, version,
+};
+use super::config::redact_database_url;
+use chrono::NaiveDateTime;
+use diesel::{
+    connection::{Connection, SimpleConnection},
+    expression::dsl::max,
+    insert_into,
+    pg::PgConnection,
+    result::Error as DieselError,
+    sql_query,
+    sql_types::Int8,
+    ExpressionMethods, QueryDsl, RunQueryDsl,
+};
+use std::io;
+
+/// The key of the Postgres advisory lock held while migrations are applied or reverted, so that
+/// replicas starting at the same time don't race each other ("notedly" in ASCII).
+const LOCK_KEY: i64 = 0x006e_6f74_6564_6c79;
+
+/// Embeds the up.sql and down.sql of each of the given migration directories.
+macro_rules! migrations {
+    ($($name:literal),* $(,)?) => {
+        &[$(Migration {
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, "/up.sql")),
+            down: include_str!(concat!("../migrations/", $name, "/down.sql")),
+        }),*]
+    };
+}
+
+/// Every migration in the migrations directory, oldest first. New migrations must be added here.
+pub const MIGRATIONS: &[Migration] = migrations![
+    "00000000000000_diesel_initial_setup",
+    "2019-12-02-213647_create_notes",
+    "2020-04-20-180000_create_note_revisions",
+    "2020-04-22-153000_create_search_indexes",
+    "2020-04-24-201500_add_versions",
+    "2020-04-27-170000_unique_permissions",
+    "2020-04-29-143000_create_board_invitations",
+    "2020-05-01-120000_add_share_slugs",
+    "2020-05-04-160000_add_roles",
+    "2020-05-06-110000_create_board_transfers",
+    "2020-05-08-130000_add_oauth_providers",
+    "2020-05-11-100000_create_user_identities",
+    "2020-05-13-090000_create_auth_sessions",
+    "2020-05-15-100000_create_api_tokens",
+    "2020-05-18-100000_add_provider_tokens",
+    "2020-05-20-100000_create_password_credentials",
+    "2020-05-22-100000_create_two_factor_credentials",
+    "2020-05-25-100000_add_foreign_keys",
+    "2020-05-27-100000_add_timestamps",
+    "2020-05-29-100000_create_note_comments",
+];
+
+// The table that diesel_cli records applied migrations in. The same table is used here, so that
+// databases migrated with diesel_cli can be managed by notedlyd and vice versa.
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+/// A migration that has been built into the binary.
+pub struct Migration {
+    /// The name of the migration's directory (e.g. 2019-12-02-213647_create_notes)
+    pub name: &'static str,
+
+    /// The SQL that applies the migration
+    up: &'static str,
+
+    /// The SQL that reverts the migration
+    down: &'static str,
+}
+
+impl Migration {
+    /// Gets the version that the migration is recorded under, which is its directory's timestamp
+    /// without any dashes (e.g. 20191202213647), as with diesel_cli.
+    pub fn version(&self) -> String {
+        self.name
+            .split('_')
+            .next()
+            .unwrap_or(self.name)
+            .replace('-', "")
+    }
+}
+
+/// The state of a migration in the database.
+pub struct MigrationStatus {
+    /// The version of the migration
+    pub version: String,
+
+    /// The migration, if this binary knows about it (databases migrated by a newer notedlyd may
+    /// have applied migrations that this one doesn't have)
+    pub migration: Option<&'static Migration>,
+
+    /// When the migration was applied, if it has been
+    pub run_on: Option<NaiveDateTime>,
+}
+
+/// Connects to the database that will be migrated.
+///
+/// # Arguments
+///
+/// * `database_url` - The URL of the postgres database
+pub fn connect(database_url: &str) -> io::Result<PgConnection> {
+    PgConnection::establish(database_url).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "couldn't connect to the database at {}: {}",
+                redact_database_url(database_url),
+                e
+            ),
+        )
+    })
+}
+
+/// Gets the state of every migration, whether it is built into the binary or has been applied to
+/// the database, in order of version.
+///
+/// # Arguments
+///
+/// * `conn` - A connection to the database
+pub fn status(conn: &PgConnection) -> io::Result<Vec<MigrationStatus>> {
+    setup(conn)?;
+
+    let mut applied_versions: Vec<(String, NaiveDateTime)> = applied
+        .select((version, run_on))
+        .load(conn)
+        .map_err(|e| failed("read the applied migrations", e))?;
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|migration| {
+            let v = migration.version();
+            let ran = applied_versions
+                .iter()
+                .position(|(applied_version, _)| *applied_version == v)
+                .map(|i| applied_versions.remove(i).1);
+
+            MigrationStatus {
+                version: v,
+                migration: Some(migration),
+                run_on: ran,
+            }
+        })
+        .collect();
+
+    // Include any migrations that only the database knows about
+    statuses.extend(
+        applied_versions
+            .into_iter()
+            .map(|(v, ran)| MigrationStatus {
+                version: v,
+                migration: None,
+                run_on: Some(ran),
+            }),
+    );
+    statuses.sort_by(|a, b| a.version.cmp(&b.version));
+
+    Ok(statuses)
+}
+
+/// Applies every migration that hasn't been applied yet, oldest first, returning the migrations
+/// that were applied. Each migration is applied in its own transaction.
+///
+/// # Arguments
+///
+/// * `conn` - A connection to the database
+pub fn run_pending(conn: &PgConnection) -> io::Result<Vec<&'static Migration>> {
+    with_lock(conn, || {
+        let applied_versions: Vec<String> = applied
+            .select(version)
+            .load(conn)
+            .map_err(|e| failed("read the applied migrations", e))?;
+
+        let pending: Vec<&'static Migration> = MIGRATIONS
+            .iter()
+            .filter(|migration| !applied_versions.contains(&migration.version()))
+            .collect();
+
+        for migration in &pending {
+            conn.transaction(|| apply(conn, migration))
+                .map_err(|e| failed(&format!("apply {}", migration.name), e))?;
+        }
+
+        Ok(pending)
+    })
+}
+
+/// Reverts the most recently applied migration, returning it. Returns `None` if no migrations
+/// have been applied.
+///
+/// # Arguments
+///
+/// * `conn` - A connection to the database
+pub fn revert_latest(conn: &PgConnection) -> io::Result<Option<&'static Migration>> {
+    with_lock(conn, || {
+        let migration = match latest(conn)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        conn.transaction(|| revert(conn, migration))
+            .map_err(|e| failed(&format!("revert {}", migration.name), e))?;
+
+        Ok(Some(migration))
+    })
+}
+
+/// Reverts the most recently applied migration and applies it again, in a single transaction,
+/// returning it. Returns `None` if no migrations have been applied.
+///
+/// # Arguments
+///
+/// * `conn` - A connection to the database
+pub fn redo_latest(conn: &PgConnection) -> io::Result<Option<&'static Migration>> {
+    with_lock(conn, || {
+        let migration = match latest(conn)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        conn.transaction(|| {
+            revert(conn, migration)?;
+            apply(conn, migration)
+        })
+        .map_err(|e| failed(&format!("redo {}", migration.name), e))?;
+
+        Ok(Some(migration))
+    })
+}
+
+/// Gets the most recently applied migration. Fails if this binary doesn't know about it.
+fn latest(conn: &PgConnection) -> io::Result<Option<&'static Migration>> {
+    let latest_version: Option<String> = applied
+        .select(max(version))
+        .first(conn)
+        .map_err(|e| failed("read the applied migrations", e))?;
+
+    match latest_version {
+        Some(v) => MIGRATIONS
+            .iter()
+            .find(|migration| migration.version() == v)
+            .map(Some)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the latest migration ({}) isn't built into this notedlyd, so it can't be \
+                         reverted",
+                        v
+                    ),
+                )
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Runs a migration's up.sql and records it as applied.
+fn apply(conn: &PgConnection, migration: &Migration) -> Result<(), DieselError> {
+    conn.batch_execute(migration.up)?;
+
+    insert_into(applied)
+        .values(version.eq(migration.version()))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Runs a migration's down.sql and forgets that it was applied.
+fn revert(conn: &PgConnection, migration: &Migration) -> Result<(), DieselError> {
+    conn.batch_execute(migration.down)?;
+
+    diesel::delete(applied.filter(version.eq(migration.version())))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Runs the given closure while holding the migration lock. Other replicas wait for the lock, and
+/// then find that there's nothing left to do.
+fn with_lock<T, F>(conn: &PgConnection, f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T>,
+{
+    sql_query("SELECT pg_advisory_lock($1)")
+        .bind::<Int8, _>(LOCK_KEY)
+        .execute(conn)
+        .map_err(|e| failed("acquire the migration lock", e))?;
+
+    // Always release the lock, even if the migrations failed
+    let result = setup(conn).and_then(|_| f());
+
+    sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<Int8, _>(LOCK_KEY)
+        .execute(conn)
+        .map_err(|e| failed("release the migration lock", e))?;
+
+    result
+}
+
+/// Creates the table that applied migrations are recorded in, if it doesn't exist yet.
+fn setup(conn: &PgConnection) -> io::Result<()> {
+    conn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+            version VARCHAR(50) PRIMARY KEY NOT NULL,
+            run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );",
+    )
+    .map_err(|e| failed("create the migrations table", e))
+}
+
+/// Makes the error returned when a step of a migration fails.
+fn failed(step: &str, e: DieselError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("couldn't {}: {}", step, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_every_migration_is_embedded() {
+        let mut names: Vec<String> =
+            fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                // Only directories are migrations (e.g. not .gitkeep)
+                .filter(|entry| entry.file_type().unwrap().is_dir())
+                .map(|entry| entry.file_name().into_string().unwrap())
+                .collect();
+        names.sort();
+
+        let embedded: Vec<&str> = MIGRATIONS.iter().map(|migration| migration.name).collect();
+
+        assert_eq!(names, embedded);
+    }
+
+    #[test]
+    fn test_versions_match_diesel_cli() {
+        assert_eq!(MIGRATIONS[0].version(), "00000000000000");
+        assert_eq!(MIGRATIONS[1].version(), "20191202213647");
+
+        // Migrations are applied in the order of their versions
+        let versions: Vec<String> = MIGRATIONS.iter().map(Migration::version).collect();
+        let mut sorted = versions.clone();
+        sorted.sort();
+        sorted.dedup();
+
+        assert_eq!(versions, sorted);
+    }
+}
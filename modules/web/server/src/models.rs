@@ -1,4 +1,12 @@
-use super::schema::{boards, notes, permissions, users};
+use super::{
+    diff::Change,
+    schema::{
+        api_tokens, auth_sessions, board_invitations, board_transfers, boards, note_comments,
+        note_revisions, notes, password_credentials, permissions, recovery_codes,
+        two_factor_credentials, user_identities, users,
+    },
+};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Insertable, Identifiable, Queryable, PartialEq, Debug)]
@@ -8,38 +16,19 @@ pub struct User {
     /// The user's unique identifier
     pub id: i32,
 
-    /// The user's oauth ID provided by google or GitHub
-    pub oauth_id: i32,
-
-    /// A hash of the user's current oauth access token
-    pub oauth_token: String,
-
     /// The email of the user
     pub email: String,
-}
 
-/// An owned representation of the user struct. Usually used in server responses.
-#[derive(Serialize)]
-pub struct OwnedUser {
-    /// The unique ID issued by the user's oauth provider (i.e. Google or GitHub)
-    pub oauth_id: i32,
-
-    /// The raw oauth token of the user
-    pub oauth_token: String,
+    /// When the user was created
+    pub created_at: NaiveDateTime,
 
-    /// The user's email
-    pub email: String,
+    /// When the user was last changed
+    pub updated_at: NaiveDateTime,
 }
 
 #[derive(Insertable)]
 #[table_name = "users"]
 pub struct NewUser<'a> {
-    /// The user's oauth identifier
-    pub oauth_id: i32,
-
-    /// The user's current oauth access token hash
-    pub oauth_token: &'a str,
-
     /// The email of the new user
     pub email: &'a str,
 }
@@ -47,13 +36,411 @@ pub struct NewUser<'a> {
 #[derive(AsChangeset)]
 #[table_name = "users"]
 pub struct UpdateUser<'a> {
-    /// The update oauth access token hash of the user
-    pub oauth_token: &'a str,
-
     /// The updated email of the user
     pub email: &'a str,
 }
 
+/// A logged-in device. Each session has its own bearer token, which stops working when the
+/// session expires or is revoked.
+#[derive(Serialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(User)]
+#[table_name = "auth_sessions"]
+pub struct AuthSession {
+    /// The ID of the session
+    pub id: i32,
+
+    /// The ID of the user that the session belongs to
+    pub user_id: i32,
+
+    /// A hash of the session's bearer token
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+
+    /// The user agent of the device that the session was started on
+    pub user_agent: String,
+
+    /// When the session was started
+    pub created_at: NaiveDateTime,
+
+    /// When the session was last used to make a request
+    pub last_used_at: NaiveDateTime,
+
+    /// When the session stops being accepted
+    pub expires_at: NaiveDateTime,
+
+    /// The ID of the identity that the user logged in with, if it's still linked
+    pub identity_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "auth_sessions"]
+pub struct NewAuthSession<'a> {
+    /// The ID of the user that the session belongs to
+    pub user_id: i32,
+
+    /// A hash of the session's bearer token
+    pub token_hash: &'a str,
+
+    /// The user agent of the device that the session was started on
+    pub user_agent: &'a str,
+
+    /// When the session stops being accepted
+    pub expires_at: NaiveDateTime,
+
+    /// The ID of the identity that the user logged in with
+    pub identity_id: Option<i32>,
+}
+
+/// A newly started session, along with its bearer token. Usually used in server responses.
+#[derive(Serialize)]
+pub struct IssuedSession {
+    /// The session's bearer token
+    pub token: String,
+
+    /// When the session stops being accepted
+    pub expires_at: NaiveDateTime,
+}
+
+/// A session, as listed to the user that it belongs to. Usually used in server responses.
+#[derive(Serialize)]
+pub struct OwnedAuthSession {
+    /// The details of the session
+    #[serde(flatten)]
+    pub session: AuthSession,
+
+    /// Whether or not this is the session that made the request
+    pub current: bool,
+}
+
+/// A personal access token that a user minted for a script or integration.
+#[derive(Serialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(User)]
+#[table_name = "api_tokens"]
+pub struct ApiToken {
+    /// The ID of the token
+    pub id: i32,
+
+    /// The ID of the user that minted the token
+    pub user_id: i32,
+
+    /// The name that the user gave the token (e.g. "CI bot")
+    pub name: String,
+
+    /// A hash of the token's secret
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+
+    /// What the token can be used for (e.g. "notes:read")
+    pub scopes: Vec<String>,
+
+    /// The boards that the token is restricted to, if any
+    pub board_ids: Option<Vec<i32>>,
+
+    /// When the token was minted
+    pub created_at: NaiveDateTime,
+
+    /// When the token was last used to make a request
+    pub last_used_at: Option<NaiveDateTime>,
+
+    /// When the token stops being accepted, if ever
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_tokens"]
+pub struct NewApiToken<'a> {
+    /// The ID of the user that minted the token
+    pub user_id: i32,
+
+    /// The name that the user gave the token
+    pub name: &'a str,
+
+    /// A hash of the token's secret
+    pub token_hash: &'a str,
+
+    /// What the token can be used for
+    pub scopes: &'a [String],
+
+    /// The boards that the token is restricted to, if any
+    pub board_ids: Option<&'a [i32]>,
+
+    /// When the token stops being accepted, if ever
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// A request to mint a new personal access token.
+#[derive(Deserialize)]
+pub struct ApiTokenRequest {
+    /// The name of the token (e.g. "CI bot")
+    pub name: String,
+
+    /// What the token can be used for (e.g. ["notes:read", "boards:write"])
+    pub scopes: Vec<String>,
+
+    /// How many days the token lasts for. Tokens without an expiry last until they're revoked.
+    pub expires_in_days: Option<i64>,
+
+    /// The boards that the token is restricted to. Unrestricted tokens can be used with any board
+    /// that the user can access.
+    pub board_ids: Option<Vec<i32>>,
+}
+
+/// A newly minted personal access token, along with its secret. The secret is only ever shown
+/// in this response.
+#[derive(Serialize)]
+pub struct CreatedApiToken {
+    /// The details of the token
+    #[serde(flatten)]
+    pub token: ApiToken,
+
+    /// The token's secret, which is used as a bearer token
+    pub secret: String,
+}
+
+/// An account at an oauth provider that a user can log in with.
+#[derive(Serialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(User)]
+#[table_name = "user_identities"]
+pub struct UserIdentity {
+    /// The ID of the identity
+    pub id: i32,
+
+    /// The ID of the user that the identity belongs to
+    pub user_id: i32,
+
+    /// The name of the provider that issued the identity (e.g. "google" or "github")
+    pub provider: String,
+
+    /// The provider's unique identifier for the user
+    pub subject: String,
+
+    /// The access token issued by the provider, encrypted with the server's secret key
+    #[serde(skip_serializing)]
+    pub access_token: Option<String>,
+
+    /// The refresh token issued by the provider, encrypted with the server's secret key
+    #[serde(skip_serializing)]
+    pub refresh_token: Option<String>,
+
+    /// When the access token expires, if the provider said
+    #[serde(skip_serializing)]
+    pub token_expires_at: Option<NaiveDateTime>,
+
+    /// When the identity's grant was last checked with the provider
+    pub checked_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_identities"]
+pub struct NewUserIdentity<'a> {
+    /// The ID of the user that the identity belongs to
+    pub user_id: i32,
+
+    /// The name of the provider that issued the identity
+    pub provider: &'a str,
+
+    /// The provider's unique identifier for the user
+    pub subject: &'a str,
+}
+
+/// An email address and password that a user can log in with, for servers that allow it.
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(User)]
+#[table_name = "password_credentials"]
+#[primary_key(user_id)]
+pub struct PasswordCredential {
+    /// The ID of the user that can log in with the password
+    pub user_id: i32,
+
+    /// The email address that the user logs in with
+    pub email: String,
+
+    /// The argon2id hash of the password
+    pub password_hash: String,
+
+    /// Whether or not the user has proven that they own the email address
+    pub verified: bool,
+
+    /// When the credential was created
+    pub created_at: NaiveDateTime,
+
+    /// When the password was last changed
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_credentials"]
+pub struct NewPasswordCredential<'a> {
+    /// The ID of the user that can log in with the password
+    pub user_id: i32,
+
+    /// The email address that the user logs in with
+    pub email: &'a str,
+
+    /// The argon2id hash of the password
+    pub password_hash: &'a str,
+}
+
+/// An email address and password, used to sign up or log in.
+#[derive(Deserialize)]
+pub struct PasswordLogin {
+    /// The user's email address
+    pub email: String,
+
+    /// The user's password
+    pub password: String,
+}
+
+/// A request for something to be emailed to the owner of an email address (e.g. a password reset
+/// link).
+#[derive(Deserialize)]
+pub struct EmailRequest {
+    /// The email address
+    pub email: String,
+}
+
+/// A request to verify an email address, with the token that was emailed to it.
+#[derive(Deserialize)]
+pub struct EmailVerification {
+    /// The token from the verification email
+    pub token: String,
+}
+
+/// A request to reset a forgotten password, with the token that was emailed to the user.
+#[derive(Deserialize)]
+pub struct PasswordReset {
+    /// The token from the password reset email
+    pub token: String,
+
+    /// The user's new password
+    pub password: String,
+}
+
+/// A TOTP secret that a user logs in with as a second factor.
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(User)]
+#[table_name = "two_factor_credentials"]
+#[primary_key(user_id)]
+pub struct TwoFactorCredential {
+    /// The ID of the user that the credential protects
+    pub user_id: i32,
+
+    /// The user's TOTP secret, encrypted with the server's secret key
+    pub secret: String,
+
+    /// Whether or not the user has confirmed their authenticator app, and is challenged on login
+    pub enabled: bool,
+
+    /// The time step of the last code that was accepted, so that codes can't be replayed
+    pub last_used_step: i64,
+
+    /// The number of incorrect codes entered since the last correct one
+    pub failed_attempts: i32,
+
+    /// When an incorrect code was last entered
+    pub last_failed_at: Option<NaiveDateTime>,
+
+    /// When the user started enrolling
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "two_factor_credentials"]
+pub struct NewTwoFactorCredential<'a> {
+    /// The ID of the user that the credential protects
+    pub user_id: i32,
+
+    /// The user's TOTP secret, encrypted with the server's secret key
+    pub secret: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+pub struct NewRecoveryCode<'a> {
+    /// The ID of the user that the code belongs to
+    pub user_id: i32,
+
+    /// The hash of the code
+    pub code_hash: &'a str,
+}
+
+/// Whether or not a user has two-factor authentication enabled. Usually used in server responses.
+#[derive(Serialize)]
+pub struct TwoFactorStatus {
+    /// Whether or not the user is challenged for a code when logging in
+    pub enabled: bool,
+
+    /// The number of recovery codes that the user hasn't used yet
+    pub recovery_codes_left: i64,
+}
+
+/// A new TOTP secret, which the user adds to their authenticator app. Usually used in server
+/// responses.
+#[derive(Serialize)]
+pub struct TwoFactorEnrollment {
+    /// The base32-encoded secret, for users that can't scan the provisioning URI
+    pub secret: String,
+
+    /// The otpauth:// URI of the secret, which is shown to the user as a QR code
+    pub provisioning_uri: String,
+}
+
+/// A code from the user's authenticator app, or one of their recovery codes.
+#[derive(Deserialize)]
+pub struct TwoFactorCode {
+    /// The code
+    pub code: String,
+}
+
+/// A fresh set of recovery codes. Only their hashes are stored, so they're only ever shown once.
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    /// The codes, each of which can be used once in place of a TOTP code
+    pub recovery_codes: Vec<String>,
+}
+
+/// A challenge that a user with two-factor authentication enabled must complete before their
+/// session is started. Usually used in server responses.
+#[derive(Serialize)]
+pub struct TwoFactorChallenge {
+    /// The signed challenge token, which is sent back along with the user's code
+    pub challenge: String,
+
+    /// When the challenge can no longer be completed
+    pub expires_at: NaiveDateTime,
+}
+
+/// A response to a two-factor challenge.
+#[derive(Deserialize)]
+pub struct ChallengeResponse {
+    /// The challenge token from the login
+    pub challenge: String,
+
+    /// A code from the user's authenticator app, or one of their recovery codes
+    pub code: String,
+}
+
+/// The result of checking a user's first factor: either a new session, or a challenge for their
+/// second factor. Usually used in server responses.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    /// The user is logged in
+    Session(IssuedSession),
+
+    /// The user must complete a two-factor challenge first
+    Challenge(TwoFactorChallenge),
+}
+
+/// The visibility of a board that can only be read by users that have been invited to it.
+pub const VISIBILITY_PRIVATE: i16 = 0;
+
+/// The visibility of a board that can be read by any authenticated user with its ID or share slug.
+pub const VISIBILITY_UNLISTED: i16 = 1;
+
+/// The visibility of a board that can be read by anyone, even without an account.
+pub const VISIBILITY_PUBLIC: i16 = 2;
+
 #[derive(
     Serialize, Deserialize, Identifiable, Queryable, Associations, AsChangeset, PartialEq, Debug,
 )]
@@ -69,8 +456,23 @@ pub struct Board {
     /// The title of the board
     pub title: String,
 
-    /// The privacy setting of the board (0 => private, 1 => public [accessable by link])
+    /// The privacy setting of the board (0 => private, 1 => unlisted, 2 => public)
     pub visibility: i16,
+
+    /// The number of times the board has been written to (used as the board's ETag)
+    pub version: i32,
+
+    /// A hard-to-guess name that the board can be shared by
+    pub share_slug: String,
+
+    /// When the board was created
+    pub created_at: NaiveDateTime,
+
+    /// When the board was last changed
+    pub updated_at: NaiveDateTime,
+
+    /// The ID of the user that last changed the board, if they still exist
+    pub updated_by: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Insertable, AsChangeset)]
@@ -82,26 +484,39 @@ pub struct NewBoard {
     /// The title of the board
     pub title: String,
 
-    /// The privacy setting of the board (0 => private, 1 => public [accessable by link])
+    /// The privacy setting of the board (0 => private, 1 => unlisted, 2 => public)
     pub visibility: i16,
+
+    /// A hard-to-guess name that the board can be shared by (generated by the server)
+    #[serde(skip_deserializing)]
+    pub share_slug: String,
+
+    /// The ID of the user that created the board (set by the server)
+    #[serde(skip_deserializing)]
+    pub updated_by: Option<i32>,
 }
 
 #[derive(Deserialize)]
 pub struct UpdateBoard {
-    /// The ID of the user that the board is owned by
+    /// The ID of the user that the board is owned by (can only be changed through a transfer)
     pub user_id: Option<i32>,
 
     /// The title of the board
     pub title: Option<String>,
 
-    /// The privacy setting of the board (0 => private, 1 => public [accessable by link])
+    /// The privacy setting of the board (0 => private, 1 => unlisted, 2 => public)
     pub visibility: Option<i16>,
 }
 
 impl UpdateBoard {
     /// Consumes the request to update the given board, and returns a new board given potentially
     /// empty fields in the current request to update & an old, completed request.
-    pub fn new_board(&mut self, old: Board) -> Board {
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The board as it currently is
+    /// * `editor` - The ID of the user making the change
+    pub fn new_board(&mut self, old: Board, editor: i32) -> Board {
         // Use fields from the new instance if they exist, but fall back to the old instance if
         // they don't
         Board {
@@ -121,6 +536,12 @@ impl UpdateBoard {
             } else {
                 old.visibility
             },
+            version: old.version + 1,
+            share_slug: old.share_slug,
+            created_at: old.created_at,
+            // Bumped by the database when the board is saved
+            updated_at: old.updated_at,
+            updated_by: Some(editor),
         }
     }
 }
@@ -146,6 +567,18 @@ pub struct Note {
 
     /// The text contained in the note
     pub body: String,
+
+    /// The number of times the note has been written to (used as the note's ETag)
+    pub version: i32,
+
+    /// When the note was created
+    pub created_at: NaiveDateTime,
+
+    /// When the note was last changed
+    pub updated_at: NaiveDateTime,
+
+    /// The ID of the user that last changed the note, if they still exist
+    pub updated_by: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Insertable)]
@@ -162,6 +595,10 @@ pub struct NewNote {
 
     /// The text contained in the note
     pub body: String,
+
+    /// The ID of the user that created the note (set by the server)
+    #[serde(skip_deserializing)]
+    pub updated_by: Option<i32>,
 }
 
 #[derive(Deserialize)]
@@ -182,7 +619,12 @@ pub struct UpdateNote {
 impl UpdateNote {
     /// Initializes a new note from the provided old note, as well as a partially constructed
     /// UpdateNote.
-    pub fn new_note(&mut self, old: Note) -> Note {
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The note as it currently is
+    /// * `editor` - The ID of the user making the change
+    pub fn new_note(&mut self, old: Note, editor: i32) -> Note {
         // Return the new note
         Note {
             // The ID of this note CANNOT change
@@ -215,10 +657,136 @@ impl UpdateNote {
             } else {
                 old.body
             },
+
+            // Every write produces a new version of the note
+            version: old.version + 1,
+
+            // When the note was created CANNOT change
+            created_at: old.created_at,
+
+            // Bumped by the database when the note is saved
+            updated_at: old.updated_at,
+
+            // The note was last changed by whoever is changing it now
+            updated_by: Some(editor),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(Note)]
+#[table_name = "note_revisions"]
+pub struct NoteRevision {
+    /// The ID of the revision
+    pub id: i32,
+
+    /// The ID of the note that the revision belongs to
+    pub note_id: i32,
+
+    /// The sequence number of the revision (starts at 1 for each note)
+    pub revision: i32,
+
+    /// The ID of the user that authored the revision
+    pub user_id: i32,
+
+    /// The title of the note at this revision
+    pub title: String,
+
+    /// The text contained in the note at this revision
+    pub body: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "note_revisions"]
+pub struct NewNoteRevision<'a> {
+    /// The ID of the note that the revision belongs to
+    pub note_id: i32,
+
+    /// The sequence number of the revision
+    pub revision: i32,
+
+    /// The ID of the user that authored the revision
+    pub user_id: i32,
+
+    /// The title of the note at this revision
+    pub title: &'a str,
+
+    /// The text contained in the note at this revision
+    pub body: &'a str,
+}
+
+/// A comment left on a note. Commenting doesn't change the note itself.
+#[derive(Serialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(Note)]
+#[table_name = "note_comments"]
+pub struct NoteComment {
+    /// The ID of the comment
+    pub id: i32,
+
+    /// The ID of the note that the comment was left on
+    pub note_id: i32,
+
+    /// The ID of the user that wrote the comment
+    pub user_id: i32,
+
+    /// The text of the comment
+    pub body: String,
+
+    /// When the comment was written
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "note_comments"]
+pub struct NewNoteComment<'a> {
+    /// The ID of the note that the comment is left on
+    pub note_id: i32,
+
+    /// The ID of the user that wrote the comment
+    pub user_id: i32,
+
+    /// The text of the comment
+    pub body: &'a str,
+}
+
+/// A request to leave a comment on a note.
+#[derive(Deserialize)]
+pub struct NewComment {
+    /// The text of the comment
+    pub body: String,
+}
+
+/// The differences between two revisions of a note. Usually used in server responses.
+#[derive(Serialize)]
+pub struct NoteDiff {
+    /// The revision that the diff starts from
+    pub from: i32,
+
+    /// The revision that the diff ends at
+    pub to: i32,
+
+    /// A line-by-line diff of the note's title
+    pub title: Vec<Change>,
+
+    /// A line-by-line diff of the note's body
+    pub body: Vec<Change>,
+}
+
+/// The role of a member that can read a board, but can't change anything on it.
+pub const ROLE_VIEWER: i16 = 0;
+
+/// The role of a member that can read and annotate a board, but can't edit its notes.
+pub const ROLE_COMMENTER: i16 = 1;
+
+/// The role of a member that can write to a board.
+pub const ROLE_EDITOR: i16 = 2;
+
+/// The role of a member that can manage a board's settings and the other members of the board.
+pub const ROLE_ADMIN: i16 = 3;
+
+/// The role of the user that owns a board. Each board has exactly one owner.
+pub const ROLE_OWNER: i16 = 4;
+
 #[derive(
     Serialize, Deserialize, Identifiable, Insertable, Queryable, Associations, PartialEq, Debug,
 )]
@@ -236,11 +804,18 @@ pub struct Permission {
     /// The ID of the board associated with the permission
     pub board_id: i32,
 
-    /// Whether or not the user can read from this board
-    pub read: bool,
+    /// The role that the user has on the board (0 => viewer, 1 => commenter, 2 => editor,
+    /// 3 => admin, 4 => owner)
+    pub role: i16,
+
+    /// When the permission was created
+    pub created_at: NaiveDateTime,
+
+    /// When the permission was last changed
+    pub updated_at: NaiveDateTime,
 
-    /// Whether or not the user can write to this board
-    pub write: bool,
+    /// The ID of the user that last changed the permission, if they still exist
+    pub updated_by: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Insertable)]
@@ -252,9 +827,208 @@ pub struct NewPermission {
     /// The ID of the board associated with the permission
     pub board_id: i32,
 
-    /// Whether or not the user can read from the board
-    pub read: bool,
+    /// The role that the user has on the board
+    pub role: i16,
+
+    /// The ID of the user that granted the permission
+    pub updated_by: Option<i32>,
+}
+
+/// A single note or board matching a search query. Usually used in server responses.
+#[derive(Serialize, QueryableByName, Debug)]
+pub struct SearchResult {
+    /// The kind of entity that matched the query ("note" or "board")
+    #[sql_type = "diesel::sql_types::Text"]
+    pub kind: String,
+
+    /// The ID of the matching note or board
+    #[sql_type = "diesel::sql_types::Int4"]
+    pub id: i32,
+
+    /// The ID of the board that the match belongs to (for boards, this is the same as the ID)
+    #[sql_type = "diesel::sql_types::Int4"]
+    pub board_id: i32,
+
+    /// The title of the match as escaped HTML, with any matching terms wrapped in <mark>
+    #[sql_type = "diesel::sql_types::Text"]
+    pub title: String,
+
+    /// An excerpt of the note's body surrounding any matching terms as escaped HTML, with the terms
+    /// wrapped in <mark> (empty for boards)
+    #[sql_type = "diesel::sql_types::Text"]
+    pub snippet: String,
+
+    /// How closely the match fits the query (higher is better)
+    #[sql_type = "diesel::sql_types::Float4"]
+    pub rank: f32,
+}
+
+/// A request to grant a user access to a board.
+#[derive(Deserialize)]
+pub struct GrantPermission {
+    /// The ID of the user being granted access
+    pub user_id: i32,
+
+    /// The role that the user will have on the board
+    pub role: i16,
+}
+
+/// A request to change the role that a user has on a board.
+#[derive(Deserialize, AsChangeset)]
+#[table_name = "permissions"]
+pub struct UpdatePermission {
+    /// The role that the user will have on the board
+    pub role: i16,
+
+    /// The ID of the user changing the role (set by the server)
+    #[serde(skip_deserializing)]
+    pub updated_by: Option<i32>,
+}
+
+/// The status of an invitation that hasn't been responded to yet.
+pub const INVITATION_PENDING: i16 = 0;
+
+/// The status of an invitation that has been turned into a permission.
+pub const INVITATION_ACCEPTED: i16 = 1;
+
+/// The status of an invitation that the invitee turned down.
+pub const INVITATION_DECLINED: i16 = 2;
+
+/// The status of an invitation that was withdrawn by the board's owner.
+pub const INVITATION_REVOKED: i16 = 3;
+
+/// The status of an invitation that ran out before it was responded to, and has been replaced.
+pub const INVITATION_EXPIRED: i16 = 4;
+
+#[derive(Serialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(Board)]
+#[table_name = "board_invitations"]
+pub struct BoardInvitation {
+    /// The ID of the invitation
+    pub id: i32,
+
+    /// The ID of the board that the invitee is invited to
+    pub board_id: i32,
+
+    /// The ID of the user that sent the invitation
+    pub inviter_id: i32,
+
+    /// The email address that the invitation was sent to
+    pub email: String,
+
+    /// A hash of the invitation's signed acceptance token
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+
+    /// When the invitation can no longer be accepted
+    pub expires_at: NaiveDateTime,
+
+    /// The state of the invitation (0 => pending, 1 => accepted, 2 => declined, 3 => revoked,
+    /// 4 => expired)
+    pub status: i16,
+
+    /// The role that the invitee will have on the board
+    pub role: i16,
+}
+
+#[derive(Insertable)]
+#[table_name = "board_invitations"]
+pub struct NewBoardInvitation<'a> {
+    /// The ID of the board that the invitee is invited to
+    pub board_id: i32,
+
+    /// The ID of the user that sent the invitation
+    pub inviter_id: i32,
+
+    /// The email address that the invitation was sent to
+    pub email: &'a str,
+
+    /// A hash of the invitation's signed acceptance token
+    pub token_hash: &'a str,
+
+    /// When the invitation can no longer be accepted
+    pub expires_at: NaiveDateTime,
+
+    /// The role that the invitee will have on the board
+    pub role: i16,
+}
+
+/// A request to invite someone to a board by their email address.
+#[derive(Deserialize)]
+pub struct InviteByEmail {
+    /// The email address of the invitee
+    pub email: String,
+
+    /// The role that the invitee will have on the board
+    pub role: i16,
+}
+
+/// A newly created invitation, along with its acceptance token. The token is only ever shown
+/// here, since only its hash is stored. Usually used in server responses.
+#[derive(Serialize)]
+pub struct IssuedInvitation {
+    /// The invitation
+    pub invitation: BoardInvitation,
+
+    /// The token that the invitee can use to accept or decline the invitation
+    pub token: String,
+}
+
+/// A response to an invitation, with the token that was issued for it.
+#[derive(Deserialize)]
+pub struct InvitationResponse {
+    /// The token that was issued for the invitation
+    pub token: String,
+}
+
+/// The status of a transfer that the recipient hasn't responded to yet.
+pub const TRANSFER_PENDING: i16 = 0;
+
+/// The status of a transfer that the recipient accepted, making them the board's owner.
+pub const TRANSFER_ACCEPTED: i16 = 1;
+
+/// The status of a transfer that the recipient turned down.
+pub const TRANSFER_DECLINED: i16 = 2;
+
+/// The status of a transfer that was withdrawn by the board's owner.
+pub const TRANSFER_CANCELLED: i16 = 3;
+
+#[derive(Serialize, Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(Board)]
+#[table_name = "board_transfers"]
+pub struct BoardTransfer {
+    /// The ID of the transfer
+    pub id: i32,
+
+    /// The ID of the board being transferred
+    pub board_id: i32,
+
+    /// The ID of the owner that offered the board
+    pub from_user_id: i32,
+
+    /// The ID of the user that the board was offered to
+    pub to_user_id: i32,
+
+    /// The state of the transfer (0 => pending, 1 => accepted, 2 => declined, 3 => cancelled)
+    pub status: i16,
+}
+
+#[derive(Insertable)]
+#[table_name = "board_transfers"]
+pub struct NewBoardTransfer {
+    /// The ID of the board being transferred
+    pub board_id: i32,
+
+    /// The ID of the owner that offered the board
+    pub from_user_id: i32,
+
+    /// The ID of the user that the board was offered to
+    pub to_user_id: i32,
+}
 
-    /// Whether or not the user can write to the board
-    pub write: bool,
+/// A request to give a board to another user.
+#[derive(Deserialize)]
+pub struct OfferTransfer {
+    /// The ID of the user that the board will be offered to
+    pub user_id: i32,
 }
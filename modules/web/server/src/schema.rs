@@ -1,9 +1,85 @@
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Array<Text>,
+        board_ids -> Nullable<Array<Int4>>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    auth_sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        user_agent -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Timestamp,
+        expires_at -> Timestamp,
+        identity_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    board_invitations (id) {
+        id -> Int4,
+        board_id -> Int4,
+        inviter_id -> Int4,
+        email -> Text,
+        token_hash -> Text,
+        expires_at -> Timestamp,
+        status -> Int2,
+        role -> Int2,
+    }
+}
+
+table! {
+    board_transfers (id) {
+        id -> Int4,
+        board_id -> Int4,
+        from_user_id -> Int4,
+        to_user_id -> Int4,
+        status -> Int2,
+    }
+}
+
 table! {
     boards (id) {
         id -> Int4,
         user_id -> Int4,
         title -> Text,
         visibility -> Int2,
+        version -> Int4,
+        share_slug -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        updated_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    note_comments (id) {
+        id -> Int4,
+        note_id -> Int4,
+        user_id -> Int4,
+        body -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    note_revisions (id) {
+        id -> Int4,
+        note_id -> Int4,
+        revision -> Int4,
+        user_id -> Int4,
+        title -> Text,
+        body -> Text,
     }
 }
 
@@ -14,6 +90,21 @@ table! {
         board_id -> Int4,
         title -> Text,
         body -> Text,
+        version -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        updated_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    password_credentials (user_id) {
+        user_id -> Int4,
+        email -> Text,
+        password_hash -> Text,
+        verified -> Bool,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -22,18 +113,88 @@ table! {
         id -> Int4,
         user_id -> Int4,
         board_id -> Int4,
-        read -> Bool,
-        write -> Bool,
+        role -> Int2,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        updated_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+    }
+}
+
+table! {
+    two_factor_credentials (user_id) {
+        user_id -> Int4,
+        secret -> Text,
+        enabled -> Bool,
+        last_used_step -> Int8,
+        failed_attempts -> Int4,
+        last_failed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Text,
+        subject -> Text,
+        access_token -> Nullable<Text>,
+        refresh_token -> Nullable<Text>,
+        token_expires_at -> Nullable<Timestamp>,
+        checked_at -> Timestamp,
     }
 }
 
 table! {
     users (id) {
         id -> Int4,
-        oauth_id -> Int4,
-        oauth_token -> Text,
         email -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
-allow_tables_to_appear_in_same_query!(boards, notes, permissions, users,);
+joinable!(api_tokens -> users (user_id));
+joinable!(auth_sessions -> user_identities (identity_id));
+joinable!(auth_sessions -> users (user_id));
+joinable!(board_invitations -> boards (board_id));
+joinable!(board_invitations -> users (inviter_id));
+joinable!(board_transfers -> boards (board_id));
+joinable!(boards -> users (user_id));
+joinable!(note_comments -> notes (note_id));
+joinable!(note_comments -> users (user_id));
+joinable!(note_revisions -> notes (note_id));
+joinable!(note_revisions -> users (user_id));
+joinable!(notes -> boards (board_id));
+joinable!(notes -> users (user_id));
+joinable!(password_credentials -> users (user_id));
+joinable!(permissions -> boards (board_id));
+joinable!(permissions -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(two_factor_credentials -> users (user_id));
+joinable!(user_identities -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    auth_sessions,
+    board_invitations,
+    board_transfers,
+    boards,
+    note_comments,
+    note_revisions,
+    notes,
+    password_credentials,
+    permissions,
+    recovery_codes,
+    two_factor_credentials,
+    user_identities,
+    users,
+);
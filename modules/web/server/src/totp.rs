@@ -0,0 +1,339 @@
+use rand::{rngs::OsRng, RngCore};
+use ring::{constant_time, hmac};
+
+/// The number of seconds that each code is valid for.
+pub const PERIOD: u64 = 30;
+
+/// The number of digits in each code.
+pub const DIGITS: u32 = 6;
+
+/// The number of periods either side of the current one whose codes are still accepted, so that
+/// users with slightly fast or slow clocks can still log in.
+const ALLOWED_DRIFT: u64 = 1;
+
+/// The number of bytes in a generated secret (160 bits, as recommended by RFC 4226).
+const SECRET_LEN: usize = 20;
+
+/// The characters of the RFC 4648 base32 alphabet, which authenticator apps expect secrets in.
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A shared secret that generates RFC 6238 time-based one-time passwords (HMAC-SHA1, 6 digits,
+/// 30-second periods), as understood by every common authenticator app. Every method takes the
+/// current time explicitly, so that codes can be checked against a fixed clock.
+pub struct Totp {
+    /// The raw shared secret
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    /// Generates a new, random secret.
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+
+        Self { secret } // Return the new instance
+    }
+
+    /// Reads a secret from its base32 encoding. Returns `None` if the encoding is invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `encoded` - The base32-encoded secret (case, spaces and padding are ignored)
+    pub fn from_base32(encoded: &str) -> Option<Self> {
+        let secret = base32_decode(encoded)?;
+        if secret.is_empty() {
+            return None;
+        }
+
+        Some(Self { secret })
+    }
+
+    /// Gets the base32 encoding of the secret, which users can type into their authenticator app.
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.secret)
+    }
+
+    /// Gets the otpauth:// URI of the secret, which authenticator apps can scan as a QR code.
+    ///
+    /// # Arguments
+    ///
+    /// * `issuer` - The name of the service that the secret logs in to (e.g. Notedly)
+    /// * `account` - The name of the account that the secret logs in to (e.g. an email address)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use server::totp::Totp;
+    ///
+    /// let totp = Totp::from_base32("GEZDGNBVGY3TQOJQ").unwrap();
+    ///
+    /// assert_eq!(
+    ///     totp.provisioning_uri("Notedly", "jo@example.com"),
+    ///     "otpauth://totp/Notedly:jo%40example.com?secret=GEZDGNBVGY3TQOJQ&issuer=Notedly\
+    ///      &algorithm=SHA1&digits=6&period=30"
+    /// );
+    /// ```
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            self.to_base32(),
+            percent_encode(issuer),
+            DIGITS,
+            PERIOD
+        )
+    }
+
+    /// Gets the code for the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time, in seconds since the Unix epoch
+    pub fn code_at(&self, time: u64) -> String {
+        self.code_for_step(time / PERIOD)
+    }
+
+    /// Checks a code against the given time, allowing for a period of clock drift either side.
+    /// Returns the time step that the code belongs to, so that callers can refuse to accept the
+    /// same code (or an older one) twice.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The code that the user entered
+    /// * `time` - The time, in seconds since the Unix epoch
+    /// * `last_step` - The time step of the last code that was accepted, if any. Only codes from
+    /// later time steps are accepted.
+    pub fn verify(&self, code: &str, time: u64, last_step: Option<u64>) -> Option<u64> {
+        let code = code.trim();
+        if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let current = time / PERIOD;
+
+        (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+            .filter(|step| last_step.map_or(true, |last| *step > last))
+            .find(|step| {
+                // Compare the codes in constant time
+                constant_time::verify_slices_are_equal(
+                    self.code_for_step(*step).as_bytes(),
+                    code.as_bytes(),
+                )
+                .is_ok()
+            })
+    }
+
+    /// Gets the code for the given time step (RFC 4226, section 5.3).
+    fn code_for_step(&self, step: u64) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.secret);
+        let hash = hmac::sign(&key, &step.to_be_bytes());
+        let hash = hash.as_ref();
+
+        // Take 31 bits from the offset given by the last nibble of the hash
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let truncated = (u32::from(hash[offset]) & 0x7f) << 24
+            | u32::from(hash[offset + 1]) << 16
+            | u32::from(hash[offset + 2]) << 8
+            | u32::from(hash[offset + 3]);
+
+        format!(
+            "{:0width$}",
+            truncated % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+}
+
+/// Encodes the given bytes as unpadded RFC 4648 base32.
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes that will be encoded
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+
+        // Write out every full group of 5 bits
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    // Pad out the last group with zeroes
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Decodes RFC 4648 base32, ignoring case, spaces and padding. Returns `None` if the input
+/// contains any other character.
+///
+/// # Arguments
+///
+/// * `encoded` - The base32 that will be decoded
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+
+    for c in encoded.bytes().filter(|c| *c != b'=' && *c != b' ') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        // Read out every full byte
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986, so that the given text
+/// can be used in any part of a URI.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA1 secret used by the test vectors in RFC 6238, appendix B.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    /// Gets a Totp with the secret from RFC 6238.
+    fn rfc_totp() -> Totp {
+        Totp {
+            secret: RFC_SECRET.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        let totp = rfc_totp();
+
+        // The RFC's codes have 8 digits, so only their last 6 are compared
+        for (time, code) in &[
+            (59, "287082"),
+            (1_111_111_109, "081804"),
+            (1_111_111_111, "050471"),
+            (1_234_567_890, "005924"),
+            (2_000_000_000, "279037"),
+            (20_000_000_000, "353130"),
+        ] {
+            assert_eq!(totp.code_at(*time), *code, "code at {}", time);
+        }
+    }
+
+    #[test]
+    fn test_verify_allows_drift() {
+        let totp = rfc_totp();
+        let now = 1_111_111_111;
+
+        // Codes from the previous and next periods are accepted
+        assert_eq!(
+            totp.verify(&totp.code_at(now - PERIOD), now, None),
+            Some(now / PERIOD - 1)
+        );
+        assert_eq!(
+            totp.verify(&totp.code_at(now), now, None),
+            Some(now / PERIOD)
+        );
+        assert_eq!(
+            totp.verify(&totp.code_at(now + PERIOD), now, None),
+            Some(now / PERIOD + 1)
+        );
+
+        // Codes from further away aren't
+        assert_eq!(
+            totp.verify(&totp.code_at(now - 2 * PERIOD), now, None),
+            None
+        );
+        assert_eq!(
+            totp.verify(&totp.code_at(now + 2 * PERIOD), now, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects_replays() {
+        let totp = rfc_totp();
+        let now = 1_234_567_890;
+        let code = totp.code_at(now);
+
+        let step = totp.verify(&code, now, None).unwrap();
+
+        // The same code can't be used again, even though it's still current
+        assert_eq!(totp.verify(&code, now, Some(step)), None);
+        assert_eq!(totp.verify(&code, now + 1, Some(step)), None);
+
+        // The next code can
+        let next = totp.code_at(now + PERIOD);
+        assert_eq!(totp.verify(&next, now + PERIOD, Some(step)), Some(step + 1));
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_codes() {
+        let totp = rfc_totp();
+        let now = 59;
+
+        assert_eq!(totp.verify("", now, None), None);
+        assert_eq!(totp.verify("28708", now, None), None);
+        assert_eq!(totp.verify("94287082", now, None), None);
+        assert_eq!(totp.verify("28708a", now, None), None);
+        assert_eq!(totp.verify(" 287082 ", now, None), Some(1));
+    }
+
+    #[test]
+    fn test_base32_round_trip() {
+        // The test vectors from RFC 4648, section 10
+        for (plain, encoded) in &[
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(plain.as_bytes()), *encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), plain.as_bytes());
+        }
+
+        // Authenticator apps show secrets in groups of lowercase letters
+        assert_eq!(
+            base32_decode("mzxw 6ytb oi======").unwrap(),
+            b"foobar".to_vec()
+        );
+        assert_eq!(base32_decode("MZXW1"), None);
+    }
+
+    #[test]
+    fn test_generated_secrets_round_trip() {
+        let totp = Totp::generate();
+        let decoded = Totp::from_base32(&totp.to_base32()).unwrap();
+
+        assert_eq!(decoded.secret.len(), SECRET_LEN);
+        assert_eq!(decoded.code_at(59), totp.code_at(59));
+    }
+}